@@ -0,0 +1,130 @@
+//! Measures the throughput of decoding crypto frames, comparing the previous decoding path
+//! (`serde_json::Value` tree, cloned per element, cloned again for the channel) with the current
+//! one.
+//!
+//! Run with `cargo bench -p polyticker-lib --bench decode`. Frames are generated in the format
+//! of the `XT.*` feed unless `POLYTICKER_BENCH_RECORDING` names a recording made with
+//! `polyticker web-socket --record`, whose frames are then used instead.
+
+use polyticker_lib::websocket::connection::decode_frame;
+use polyticker_lib::websocket::crypto::CryptoEvent;
+use polyticker_lib::websocket::recorder::FrameReader;
+use serde_json::Value;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const PAIRS: [&str; 8] = [
+    "BTC-USD", "ETH-USD", "SOL-USD", "XRP-USD", "BTC-EUR", "ETH-BTC", "DOGE-USD", "LTC-USD",
+];
+
+/// Frames like those of the `XT.*` feed, with a batch of trades each.
+fn generated_frames(count: usize, batch: usize) -> Vec<String> {
+    let start = 1_697_150_000_000i64;
+    (0..count)
+        .map(|frame| {
+            let trades: Vec<String> = (0..batch)
+                .map(|index| {
+                    let n = frame * batch + index;
+                    format!(
+                        r#"{{"ev":"XT","pair":"{}","p":{:.2},"t":{},"s":{:.8},"c":[{}],"i":"{}","x":{},"r":{}}}"#,
+                        PAIRS[n % PAIRS.len()],
+                        26_000.0 + (n % 1000) as f64 * 0.37,
+                        start + n as i64,
+                        0.001 + (n % 97) as f64 * 0.013,
+                        1 + n % 2,
+                        100_000_000 + n,
+                        1 + n % 4,
+                        start + n as i64 + 15,
+                    )
+                })
+                .collect();
+            format!("[{}]", trades.join(","))
+        })
+        .collect()
+}
+
+fn recorded_frames(path: &str) -> Vec<String> {
+    FrameReader::open(path)
+        .expect("readable recording")
+        .filter_map(|frame| frame.ok())
+        .map(|frame| frame.frame)
+        .collect()
+}
+
+/// The decoding path before frames were deserialized directly into events.
+fn decode_via_value(text: &str) -> Vec<CryptoEvent> {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(_) => return Vec::new(),
+    };
+    let Value::Array(values) = value else {
+        return Vec::new();
+    };
+    values
+        .iter()
+        .filter_map(|value| {
+            let event = match value["ev"].as_str()? {
+                "XT" => CryptoEvent::Trade(serde_json::from_value(value.clone()).ok()?),
+                "XQ" => CryptoEvent::Quote(serde_json::from_value(value.clone()).ok()?),
+                _ => return None,
+            };
+            // events used to be cloned into the channel
+            Some(event.clone())
+        })
+        .collect()
+}
+
+fn decode_direct(text: &str) -> Vec<CryptoEvent> {
+    decode_frame::<CryptoEvent>(text)
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+/// Decodes all frames repeatedly for at least `duration` and returns the events per second.
+fn measure(frames: &[String], duration: Duration, decode: fn(&str) -> Vec<CryptoEvent>) -> f64 {
+    let started = Instant::now();
+    let mut events = 0usize;
+    loop {
+        for frame in frames {
+            events += black_box(decode(black_box(frame))).len();
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= duration {
+            return events as f64 / elapsed.as_secs_f64();
+        }
+    }
+}
+
+fn main() {
+    // `cargo test --benches` runs this without `--bench`, a short smoke run is enough then
+    let benchmarking = std::env::args().any(|arg| arg == "--bench");
+    let duration = if benchmarking {
+        Duration::from_secs(3)
+    } else {
+        Duration::from_millis(10)
+    };
+    let frames = match std::env::var("POLYTICKER_BENCH_RECORDING") {
+        Ok(path) => recorded_frames(&path),
+        Err(_) => generated_frames(2_000, 25),
+    };
+    let bytes: usize = frames.iter().map(String::len).sum();
+    println!(
+        "{} frames, {} events, {:.1} MB",
+        frames.len(),
+        frames
+            .iter()
+            .map(|frame| decode_direct(frame).len())
+            .sum::<usize>(),
+        bytes as f64 / 1_000_000.0
+    );
+
+    // warm up the symbol table and caches
+    measure(&frames, duration / 10, decode_direct);
+
+    let before = measure(&frames, duration, decode_via_value);
+    let after = measure(&frames, duration, decode_direct);
+    println!("value tree + clones: {:>12.0} events/s", before);
+    println!("direct decoding:     {:>12.0} events/s", after);
+    println!("speedup:             {:>12.2}x", after / before);
+}
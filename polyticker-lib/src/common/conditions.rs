@@ -0,0 +1,711 @@
+use crate::request::reference::conditions::{ConditionRecord, Conditions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The values a trade may update, as defined by the SIP rules for its conditions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Eligibility {
+    /// The trade may set the high or low price.
+    #[serde(rename = "updates_high_low")]
+    pub high_low: bool,
+    /// The trade may set the open, close and last price.
+    #[serde(rename = "updates_open_close")]
+    pub open_close: bool,
+    /// The trade counts towards the volume.
+    #[serde(rename = "updates_volume")]
+    pub volume: bool,
+}
+
+impl Eligibility {
+    pub const ALL: Eligibility = Eligibility {
+        high_low: true,
+        open_close: true,
+        volume: true,
+    };
+
+    pub const NONE: Eligibility = Eligibility {
+        high_low: false,
+        open_close: false,
+        volume: false,
+    };
+
+    /// Counts towards the volume only, e.g. odd lots and average price trades.
+    pub const VOLUME_ONLY: Eligibility = Eligibility {
+        high_low: false,
+        open_close: false,
+        volume: true,
+    };
+
+    /// Sets the high, low and volume but not the last price, e.g. prints out of sequence.
+    pub const NOT_LAST: Eligibility = Eligibility {
+        high_low: true,
+        open_close: false,
+        volume: true,
+    };
+
+    /// Both rules must allow an update.
+    fn and(self, other: Eligibility) -> Eligibility {
+        Eligibility {
+            high_low: self.high_low && other.high_low,
+            open_close: self.open_close && other.open_close,
+            volume: self.volume && other.volume,
+        }
+    }
+}
+
+/// Whether values are computed across all exchanges or for a single one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// The consolidated values published by the SIP.
+    Consolidated,
+    /// The values of the market center (exchange) the trade was reported by.
+    MarketCenter,
+}
+
+/// The update rules of a condition, for consolidated and per exchange values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpdateRules {
+    pub consolidated: Eligibility,
+    pub market_center: Eligibility,
+}
+
+impl UpdateRules {
+    pub const ALL: UpdateRules = UpdateRules::same(Eligibility::ALL);
+
+    const fn same(eligibility: Eligibility) -> UpdateRules {
+        UpdateRules {
+            consolidated: eligibility,
+            market_center: eligibility,
+        }
+    }
+
+    pub fn for_scope(&self, scope: Scope) -> Eligibility {
+        match scope {
+            Scope::Consolidated => self.consolidated,
+            Scope::MarketCenter => self.market_center,
+        }
+    }
+}
+
+/// A stock trade condition as numbered by Polygon (`c` in `T` events, `conditions` in v3 trades).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SipCondition {
+    RegularTrade,
+    Acquisition,
+    AveragePriceTrade,
+    AutomaticExecution,
+    BunchedTrade,
+    BunchedSoldTrade,
+    CapElection,
+    CashSale,
+    ClosingPrints,
+    CrossTrade,
+    DerivativelyPriced,
+    Distribution,
+    FormT,
+    ExtendedHoursSoldOutOfSequence,
+    IntermarketSweep,
+    MarketCenterOfficialClose,
+    MarketCenterOfficialOpen,
+    MarketCenterOpeningTrade,
+    MarketCenterReopeningTrade,
+    MarketCenterClosingTrade,
+    NextDay,
+    PriceVariationTrade,
+    PriorReferencePrice,
+    Rule155Trade,
+    Rule127Trade,
+    OpeningPrints,
+    StoppedStock,
+    ReopeningPrints,
+    Seller,
+    SoldLast,
+    SoldLastAndStoppedStock,
+    SoldOutOfSequence,
+    SoldOutOfSequenceAndStoppedStock,
+    SplitTrade,
+    StockOption,
+    YellowFlagRegularTrade,
+    OddLotTrade,
+    CorrectedConsolidatedClose,
+    TradeThroughExempt,
+    ContingentTrade,
+    QualifiedContingentTrade,
+    /// A condition missing from the built-in table.
+    Other(i32),
+}
+
+/// The built-in stock conditions: Polygon ID, condition, name and consolidated and market
+/// center rules. A table loaded with `ConditionTable::refresh` takes precedence.
+const SIP_CONDITIONS: &[(i32, SipCondition, &str, Eligibility, Eligibility)] = {
+    use Eligibility as E;
+    use SipCondition::*;
+    &[
+        (0, RegularTrade, "Regular Trade", E::ALL, E::ALL),
+        (1, Acquisition, "Acquisition", E::ALL, E::ALL),
+        (
+            2,
+            AveragePriceTrade,
+            "Average Price Trade",
+            E::VOLUME_ONLY,
+            E::VOLUME_ONLY,
+        ),
+        (3, AutomaticExecution, "Automatic Execution", E::ALL, E::ALL),
+        (4, BunchedTrade, "Bunched Trade", E::ALL, E::ALL),
+        (
+            5,
+            BunchedSoldTrade,
+            "Bunched Sold Trade",
+            E::NOT_LAST,
+            E::NOT_LAST,
+        ),
+        (6, CapElection, "CAP Election", E::ALL, E::ALL),
+        (7, CashSale, "Cash Sale", E::VOLUME_ONLY, E::VOLUME_ONLY),
+        (8, ClosingPrints, "Closing Prints", E::ALL, E::ALL),
+        (9, CrossTrade, "Cross Trade", E::ALL, E::ALL),
+        (
+            10,
+            DerivativelyPriced,
+            "Derivatively Priced",
+            E::NOT_LAST,
+            E::NOT_LAST,
+        ),
+        (11, Distribution, "Distribution", E::ALL, E::ALL),
+        (12, FormT, "Form T", E::VOLUME_ONLY, E::VOLUME_ONLY),
+        (
+            13,
+            ExtendedHoursSoldOutOfSequence,
+            "Extended Trading Hours (Sold Out of Sequence)",
+            E::VOLUME_ONLY,
+            E::VOLUME_ONLY,
+        ),
+        (14, IntermarketSweep, "Intermarket Sweep", E::ALL, E::ALL),
+        (
+            15,
+            MarketCenterOfficialClose,
+            "Market Center Official Close",
+            E::NONE,
+            Eligibility {
+                high_low: true,
+                open_close: true,
+                volume: false,
+            },
+        ),
+        (
+            16,
+            MarketCenterOfficialOpen,
+            "Market Center Official Open",
+            E::NONE,
+            Eligibility {
+                high_low: true,
+                open_close: true,
+                volume: false,
+            },
+        ),
+        (
+            17,
+            MarketCenterOpeningTrade,
+            "Market Center Opening Trade",
+            E::ALL,
+            E::ALL,
+        ),
+        (
+            18,
+            MarketCenterReopeningTrade,
+            "Market Center Reopening Trade",
+            E::ALL,
+            E::ALL,
+        ),
+        (
+            19,
+            MarketCenterClosingTrade,
+            "Market Center Closing Trade",
+            E::ALL,
+            E::ALL,
+        ),
+        (20, NextDay, "Next Day", E::VOLUME_ONLY, E::VOLUME_ONLY),
+        (
+            21,
+            PriceVariationTrade,
+            "Price Variation Trade",
+            E::VOLUME_ONLY,
+            E::VOLUME_ONLY,
+        ),
+        (
+            22,
+            PriorReferencePrice,
+            "Prior Reference Price",
+            E::NOT_LAST,
+            E::NOT_LAST,
+        ),
+        (23, Rule155Trade, "Rule 155 Trade (AMEX)", E::ALL, E::ALL),
+        (24, Rule127Trade, "Rule 127 NYSE", E::ALL, E::ALL),
+        (25, OpeningPrints, "Opening Prints", E::ALL, E::ALL),
+        (
+            27,
+            StoppedStock,
+            "Stopped Stock (Regular Trade)",
+            E::ALL,
+            E::ALL,
+        ),
+        (28, ReopeningPrints, "Re-Opening Prints", E::ALL, E::ALL),
+        (29, Seller, "Seller", E::VOLUME_ONLY, E::VOLUME_ONLY),
+        (30, SoldLast, "Sold Last", E::ALL, E::ALL),
+        (
+            31,
+            SoldLastAndStoppedStock,
+            "Sold Last and Stopped Stock",
+            E::ALL,
+            E::ALL,
+        ),
+        (
+            32,
+            SoldOutOfSequence,
+            "Sold (Out of Sequence)",
+            E::NOT_LAST,
+            E::NOT_LAST,
+        ),
+        (
+            33,
+            SoldOutOfSequenceAndStoppedStock,
+            "Sold (Out of Sequence) and Stopped Stock",
+            E::NOT_LAST,
+            E::NOT_LAST,
+        ),
+        (34, SplitTrade, "Split Trade", E::ALL, E::ALL),
+        (35, StockOption, "Stock Option", E::ALL, E::ALL),
+        (
+            36,
+            YellowFlagRegularTrade,
+            "Yellow Flag Regular Trade",
+            E::ALL,
+            E::ALL,
+        ),
+        (
+            37,
+            OddLotTrade,
+            "Odd Lot Trade",
+            E::VOLUME_ONLY,
+            E::VOLUME_ONLY,
+        ),
+        (
+            38,
+            CorrectedConsolidatedClose,
+            "Corrected Consolidated Close (per listing market)",
+            Eligibility {
+                high_low: true,
+                open_close: true,
+                volume: false,
+            },
+            E::NONE,
+        ),
+        (41, TradeThroughExempt, "Trade Thru Exempt", E::ALL, E::ALL),
+        (
+            52,
+            ContingentTrade,
+            "Contingent Trade",
+            E::VOLUME_ONLY,
+            E::VOLUME_ONLY,
+        ),
+        (
+            53,
+            QualifiedContingentTrade,
+            "Qualified Contingent Trade",
+            E::VOLUME_ONLY,
+            E::VOLUME_ONLY,
+        ),
+    ]
+};
+
+impl SipCondition {
+    pub fn from_id(id: i32) -> Self {
+        SIP_CONDITIONS
+            .iter()
+            .find(|(condition_id, ..)| *condition_id == id)
+            .map_or(SipCondition::Other(id), |(_, condition, ..)| *condition)
+    }
+
+    pub fn id(&self) -> i32 {
+        match self {
+            SipCondition::Other(id) => *id,
+            condition => SIP_CONDITIONS
+                .iter()
+                .find(|(_, c, ..)| c == condition)
+                .map(|(id, ..)| *id)
+                .expect("every named condition is in the table"),
+        }
+    }
+
+    /// The name of the condition, or `None` for conditions missing from the built-in table.
+    pub fn name(&self) -> Option<&'static str> {
+        SIP_CONDITIONS
+            .iter()
+            .find(|(_, c, ..)| c == self)
+            .map(|(_, _, name, ..)| *name)
+    }
+
+    /// The built-in update rules of the condition. Conditions missing from the table are
+    /// assumed to update everything.
+    pub fn update_rules(&self) -> UpdateRules {
+        SIP_CONDITIONS.iter().find(|(_, c, ..)| c == self).map_or(
+            UpdateRules::ALL,
+            |(_, _, _, consolidated, market_center)| UpdateRules {
+                consolidated: *consolidated,
+                market_center: *market_center,
+            },
+        )
+    }
+
+    /// Returns true for prints smaller than a round lot.
+    pub fn is_odd_lot(&self) -> bool {
+        matches!(self, SipCondition::OddLotTrade)
+    }
+
+    /// Returns true for prints reported out of sequence, which must not move the last price.
+    pub fn is_out_of_sequence(&self) -> bool {
+        matches!(
+            self,
+            SipCondition::ExtendedHoursSoldOutOfSequence
+                | SipCondition::SoldOutOfSequence
+                | SipCondition::SoldOutOfSequenceAndStoppedStock
+        )
+    }
+}
+
+/// A crypto trade condition (`c` in `XT` events), the side of the taker. Both kinds of trades
+/// update all values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CryptoCondition {
+    SellSide,
+    BuySide,
+    /// A condition not documented by Polygon.
+    Other(i32),
+}
+
+impl CryptoCondition {
+    pub fn from_id(id: i32) -> Self {
+        match id {
+            1 => CryptoCondition::SellSide,
+            2 => CryptoCondition::BuySide,
+            id => CryptoCondition::Other(id),
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        match self {
+            CryptoCondition::SellSide => 1,
+            CryptoCondition::BuySide => 2,
+            CryptoCondition::Other(id) => *id,
+        }
+    }
+}
+
+/// The name and update rules of a condition ID.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConditionInfo {
+    pub name: String,
+    pub update_rules: UpdateRules,
+}
+
+/// The update rules of the conditions of an asset class, by condition ID.
+///
+/// Starts from a built-in table and can be refreshed from `/v3/reference/conditions`, so new or
+/// changed conditions are picked up without a release.
+#[derive(Clone, Debug, Default)]
+pub struct ConditionTable {
+    conditions: HashMap<i32, ConditionInfo>,
+}
+
+impl ConditionTable {
+    /// The built-in table of stock trade conditions.
+    pub fn stocks() -> Self {
+        let conditions = SIP_CONDITIONS
+            .iter()
+            .map(|(id, _, name, consolidated, market_center)| {
+                (
+                    *id,
+                    ConditionInfo {
+                        name: name.to_string(),
+                        update_rules: UpdateRules {
+                            consolidated: *consolidated,
+                            market_center: *market_center,
+                        },
+                    },
+                )
+            })
+            .collect();
+        ConditionTable { conditions }
+    }
+
+    /// The table of crypto trade conditions, which update all values.
+    pub fn crypto() -> Self {
+        let conditions = [(1, "Sell Side"), (2, "Buy Side")]
+            .into_iter()
+            .map(|(id, name)| {
+                (
+                    id,
+                    ConditionInfo {
+                        name: name.to_string(),
+                        update_rules: UpdateRules::ALL,
+                    },
+                )
+            })
+            .collect();
+        ConditionTable { conditions }
+    }
+
+    /// Replaces the entries of the table with the trade conditions of `asset_class` (e.g.
+    /// `stocks`) served by Polygon, keeping built-in entries that Polygon no longer lists.
+    ///
+    /// # Returns
+    ///
+    /// The number of conditions loaded, or the error of the request.
+    pub async fn refresh(
+        &mut self,
+        client: &Conditions,
+        asset_class: &str,
+    ) -> Result<usize, reqwest::Error> {
+        let records = client.get_all_conditions(asset_class, "trade").await?;
+        let loaded = records.len();
+        self.extend(records);
+        Ok(loaded)
+    }
+
+    /// Adds or replaces the entries of `records` that carry update rules.
+    pub fn extend<I: IntoIterator<Item = ConditionRecord>>(&mut self, records: I) {
+        for record in records {
+            if let Some(update_rules) = record.update_rules {
+                self.conditions.insert(
+                    record.id,
+                    ConditionInfo {
+                        name: record.name,
+                        update_rules,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn get(&self, id: i32) -> Option<&ConditionInfo> {
+        self.conditions.get(&id)
+    }
+
+    /// The combined rules of a trade: a value is only updated if every one of its conditions
+    /// allows it. Unknown conditions are assumed to update everything.
+    pub fn update_rules(&self, conditions: &[i32]) -> UpdateRules {
+        conditions
+            .iter()
+            .filter_map(|id| self.conditions.get(id))
+            .fold(UpdateRules::ALL, |rules, info| UpdateRules {
+                consolidated: rules.consolidated.and(info.update_rules.consolidated),
+                market_center: rules.market_center.and(info.update_rules.market_center),
+            })
+    }
+}
+
+/// Decides which prints bar builders, `Bucket` and the like should take into account, from the
+/// condition IDs of each trade.
+#[derive(Clone, Debug)]
+pub struct TradeFilter {
+    pub table: ConditionTable,
+    pub scope: Scope,
+    /// Ignore odd lot prints entirely, including their volume.
+    pub skip_odd_lots: bool,
+    /// Ignore prints reported out of sequence entirely, including their volume.
+    pub skip_out_of_sequence: bool,
+}
+
+impl TradeFilter {
+    /// Consolidated stock values, ignoring odd lots and prints out of sequence.
+    pub fn stocks() -> Self {
+        TradeFilter {
+            table: ConditionTable::stocks(),
+            scope: Scope::Consolidated,
+            skip_odd_lots: true,
+            skip_out_of_sequence: true,
+        }
+    }
+
+    /// Crypto values, for which every trade is eligible.
+    pub fn crypto() -> Self {
+        TradeFilter {
+            table: ConditionTable::crypto(),
+            scope: Scope::Consolidated,
+            skip_odd_lots: false,
+            skip_out_of_sequence: false,
+        }
+    }
+
+    /// The values a trade with `conditions` may update.
+    pub fn eligibility(&self, conditions: &[i32]) -> Eligibility {
+        let skipped = conditions
+            .iter()
+            .map(|id| SipCondition::from_id(*id))
+            .any(|c| {
+                (self.skip_odd_lots && c.is_odd_lot())
+                    || (self.skip_out_of_sequence && c.is_out_of_sequence())
+            });
+        if skipped {
+            return Eligibility::NONE;
+        }
+        self.table.update_rules(conditions).for_scope(self.scope)
+    }
+
+    /// Returns true if the trade may set the last (and open or close) price.
+    pub fn updates_last(&self, conditions: &[i32]) -> bool {
+        self.eligibility(conditions).open_close
+    }
+
+    /// Returns true if the trade may set the high or low price.
+    pub fn updates_high_low(&self, conditions: &[i32]) -> bool {
+        self.eligibility(conditions).high_low
+    }
+
+    /// Returns true if the trade counts towards the volume.
+    pub fn updates_volume(&self, conditions: &[i32]) -> bool {
+        self.eligibility(conditions).volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGULAR: i32 = 0;
+    const AVERAGE_PRICE: i32 = 2;
+    const FORM_T: i32 = 12;
+    const INTERMARKET_SWEEP: i32 = 14;
+    const OFFICIAL_CLOSE: i32 = 15;
+    const SOLD_OUT_OF_SEQUENCE: i32 = 32;
+    const ODD_LOT: i32 = 37;
+
+    fn counting_odd_lots() -> TradeFilter {
+        TradeFilter {
+            skip_odd_lots: false,
+            skip_out_of_sequence: false,
+            ..TradeFilter::stocks()
+        }
+    }
+
+    #[test]
+    fn maps_condition_ids() {
+        assert_eq!(SipCondition::from_id(ODD_LOT), SipCondition::OddLotTrade);
+        assert_eq!(SipCondition::from_id(FORM_T), SipCondition::FormT);
+        assert_eq!(SipCondition::from_id(99), SipCondition::Other(99));
+        assert_eq!(SipCondition::AveragePriceTrade.id(), AVERAGE_PRICE);
+        assert_eq!(SipCondition::Other(99).id(), 99);
+        assert_eq!(SipCondition::FormT.name(), Some("Form T"));
+        assert_eq!(SipCondition::Other(99).name(), None);
+    }
+
+    #[test]
+    fn combines_the_rules_of_every_condition() {
+        let table = ConditionTable::stocks();
+        assert_eq!(table.update_rules(&[]), UpdateRules::ALL);
+        assert_eq!(
+            table.update_rules(&[REGULAR, INTERMARKET_SWEEP]),
+            UpdateRules::ALL
+        );
+        assert_eq!(
+            table
+                .update_rules(&[INTERMARKET_SWEEP, FORM_T])
+                .consolidated,
+            Eligibility::VOLUME_ONLY
+        );
+        assert_eq!(
+            table.update_rules(&[SOLD_OUT_OF_SEQUENCE]).consolidated,
+            Eligibility::NOT_LAST
+        );
+        // volume only and not last leave volume only
+        assert_eq!(
+            table
+                .update_rules(&[SOLD_OUT_OF_SEQUENCE, AVERAGE_PRICE])
+                .consolidated,
+            Eligibility::VOLUME_ONLY
+        );
+        // unknown conditions update everything
+        assert_eq!(table.update_rules(&[99]), UpdateRules::ALL);
+        assert_eq!(
+            table.update_rules(&[99, ODD_LOT]).consolidated,
+            Eligibility::VOLUME_ONLY
+        );
+    }
+
+    #[test]
+    fn tells_the_scopes_apart() {
+        let rules = ConditionTable::stocks().update_rules(&[OFFICIAL_CLOSE]);
+        assert_eq!(rules.for_scope(Scope::Consolidated), Eligibility::NONE);
+        let market_center = rules.for_scope(Scope::MarketCenter);
+        assert!(market_center.open_close && market_center.high_low && !market_center.volume);
+    }
+
+    #[test]
+    fn loaded_rules_replace_the_built_in_ones() {
+        let mut table = ConditionTable::stocks();
+        let record = |id: i32, update_rules: Option<UpdateRules>| ConditionRecord {
+            id,
+            name: format!("Condition {}", id),
+            abbreviation: None,
+            asset_class: "stocks".to_string(),
+            data_types: vec!["trade".to_string()],
+            condition_type: "sale_condition".to_string(),
+            description: None,
+            exchange: None,
+            legacy: false,
+            sip_mapping: HashMap::new(),
+            update_rules,
+        };
+        table.extend([
+            record(FORM_T, Some(UpdateRules::ALL)),
+            record(99, Some(UpdateRules::same(Eligibility::NONE))),
+            // without rules, the built-in entry is kept
+            record(ODD_LOT, None),
+        ]);
+
+        assert_eq!(table.update_rules(&[FORM_T]), UpdateRules::ALL);
+        assert_eq!(table.get(FORM_T).unwrap().name, "Condition 12");
+        assert_eq!(table.update_rules(&[99]).consolidated, Eligibility::NONE);
+        assert_eq!(table.get(ODD_LOT).unwrap().name, "Odd Lot Trade");
+    }
+
+    #[test]
+    fn stock_filter_skips_odd_lots_and_prints_out_of_sequence() {
+        let filter = TradeFilter::stocks();
+        assert_eq!(filter.eligibility(&[REGULAR]), Eligibility::ALL);
+        assert_eq!(filter.eligibility(&[ODD_LOT]), Eligibility::NONE);
+        assert_eq!(
+            filter.eligibility(&[INTERMARKET_SWEEP, SOLD_OUT_OF_SEQUENCE]),
+            Eligibility::NONE
+        );
+        assert!(!filter.updates_volume(&[ODD_LOT]));
+        assert!(!filter.updates_last(&[SOLD_OUT_OF_SEQUENCE]));
+    }
+
+    #[test]
+    fn volume_only_prints_do_not_update_the_last_price() {
+        let filter = counting_odd_lots();
+        for conditions in [
+            &[ODD_LOT][..],
+            &[AVERAGE_PRICE],
+            &[FORM_T],
+            &[REGULAR, FORM_T],
+        ] {
+            assert!(filter.updates_volume(conditions), "{:?}", conditions);
+            assert!(!filter.updates_last(conditions), "{:?}", conditions);
+            assert!(!filter.updates_high_low(conditions), "{:?}", conditions);
+        }
+        assert!(filter.updates_volume(&[SOLD_OUT_OF_SEQUENCE]));
+        assert!(filter.updates_high_low(&[SOLD_OUT_OF_SEQUENCE]));
+        assert!(!filter.updates_last(&[SOLD_OUT_OF_SEQUENCE]));
+        assert!(filter.updates_last(&[REGULAR, INTERMARKET_SWEEP]));
+        assert!(!filter.updates_volume(&[OFFICIAL_CLOSE]));
+    }
+
+    #[test]
+    fn crypto_filter_accepts_every_trade() {
+        let filter = TradeFilter::crypto();
+        for conditions in [&[][..], &[1], &[2], &[ODD_LOT]] {
+            assert_eq!(filter.eligibility(conditions), Eligibility::ALL);
+        }
+    }
+}
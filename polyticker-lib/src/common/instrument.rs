@@ -0,0 +1,443 @@
+use crate::exchange::registry::AssetClass;
+use crate::websocket::forex::CurrencyPair;
+use crate::websocket::symbol::{self, Symbol};
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// Quote currencies recognized when splitting a crypto ticker such as `X:BTCUSD`, longest first
+/// so `X:ETHBUSD` is quoted in `BUSD` rather than `USD`. Other quotes are assumed to be three
+/// letters long.
+const CRYPTO_QUOTES: &[&str] = &[
+    "USDT", "USDC", "BUSD", "USD", "EUR", "GBP", "JPY", "AUD", "CAD", "CHF", "KRW", "SGD", "HKD",
+    "BTC", "ETH", "DAI",
+];
+
+/// A tradable instrument of any asset class, convertible between the notations of the REST API
+/// and of the websocket feeds without losing information.
+///
+/// | Asset class | REST ticker            | Websocket symbol       |
+/// |-------------|------------------------|------------------------|
+/// | Equity      | `AAPL`                 | `AAPL`                 |
+/// | Crypto      | `X:BTCUSD`             | `BTC-USD`              |
+/// | Forex       | `C:EURUSD`             | `EUR/USD`              |
+/// | Option      | `O:SPY241220P00720000` | `O:SPY241220P00720000` |
+/// | Index       | `I:SPX`                | `I:SPX`                |
+///
+/// Parsing accepts either notation. Formatting (`Display`, serde) writes the REST ticker.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Instrument {
+    Equity(Symbol),
+    Crypto {
+        base: Symbol,
+        quote: Symbol,
+    },
+    Forex {
+        base: Symbol,
+        quote: Symbol,
+    },
+    Option(OptionContract),
+    /// An index, by its ticker without the `I:` prefix.
+    Index(Symbol),
+}
+
+/// Whether an options contract gives the right to buy or to sell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OptionRight {
+    Call,
+    Put,
+}
+
+/// An options contract, as encoded in OCC symbols like `O:SPY241220P00720000`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OptionContract {
+    pub underlying: Symbol,
+    pub expiration: NaiveDate,
+    pub right: OptionRight,
+    /// The strike price in thousandths of a dollar, as encoded in the symbol.
+    pub strike_thousandths: u64,
+}
+
+impl OptionContract {
+    /// The strike price in dollars.
+    pub fn strike(&self) -> f64 {
+        self.strike_thousandths as f64 / 1000.0
+    }
+
+    /// Parses the part of an OCC symbol after `O:`, e.g. `SPY241220P00720000`.
+    fn parse(occ: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::Error::msg(format!("Invalid options contract: O:{}", occ));
+        // the underlying has a variable length, the 15 characters after it do not
+        if occ.len() <= 15 || !occ.is_ascii() {
+            return Err(invalid());
+        }
+        let (underlying, rest) = occ.split_at(occ.len() - 15);
+        let expiration = NaiveDate::parse_from_str(&rest[..6], "%y%m%d").map_err(|_| invalid())?;
+        let right = match &rest[6..7] {
+            "C" => OptionRight::Call,
+            "P" => OptionRight::Put,
+            _ => return Err(invalid()),
+        };
+        if !rest[7..].bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let strike_thousandths = rest[7..].parse().map_err(|_| invalid())?;
+        Ok(OptionContract {
+            underlying: Symbol::new(underlying),
+            expiration,
+            right,
+            strike_thousandths,
+        })
+    }
+}
+
+impl fmt::Display for OptionContract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "O:{}{}{}{:08}",
+            self.underlying,
+            self.expiration.format("%y%m%d"),
+            match self.right {
+                OptionRight::Call => "C",
+                OptionRight::Put => "P",
+            },
+            self.strike_thousandths
+        )
+    }
+}
+
+impl Instrument {
+    pub fn equity(ticker: &str) -> Self {
+        Instrument::Equity(Symbol::new(ticker))
+    }
+
+    pub fn crypto(base: &str, quote: &str) -> Self {
+        Instrument::Crypto {
+            base: Symbol::new(base),
+            quote: Symbol::new(quote),
+        }
+    }
+
+    pub fn forex(base: &str, quote: &str) -> Self {
+        Instrument::Forex {
+            base: Symbol::new(base),
+            quote: Symbol::new(quote),
+        }
+    }
+
+    pub fn index(ticker: &str) -> Self {
+        Instrument::Index(Symbol::new(ticker))
+    }
+
+    /// Parses a symbol as sent by the websocket feed of `asset_class`, where the notation alone
+    /// can be ambiguous (e.g. a `-` in a crypto pair and in some equity tickers).
+    pub fn from_feed(asset_class: AssetClass, symbol: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::Error::msg(format!("Invalid {} symbol: {}", asset_class, symbol));
+        match asset_class {
+            AssetClass::Stocks => parse_ticker(symbol).map(Instrument::Equity),
+            AssetClass::Crypto => {
+                let (base, quote) = symbol.split_once('-').ok_or_else(invalid)?;
+                Ok(Instrument::Crypto {
+                    base: parse_ticker(base)?,
+                    quote: parse_ticker(quote)?,
+                })
+            }
+            AssetClass::Fx => Ok(symbol.parse::<CurrencyPair>()?.into()),
+            AssetClass::Options => symbol
+                .strip_prefix("O:")
+                .ok_or_else(invalid)
+                .and_then(OptionContract::parse)
+                .map(Instrument::Option),
+            AssetClass::Indices => symbol
+                .strip_prefix("I:")
+                .ok_or_else(invalid)
+                .and_then(parse_ticker)
+                .map(Instrument::Index),
+        }
+    }
+
+    pub fn asset_class(&self) -> AssetClass {
+        match self {
+            Instrument::Equity(_) => AssetClass::Stocks,
+            Instrument::Crypto { .. } => AssetClass::Crypto,
+            Instrument::Forex { .. } => AssetClass::Fx,
+            Instrument::Option(_) => AssetClass::Options,
+            Instrument::Index(_) => AssetClass::Indices,
+        }
+    }
+
+    /// The ticker used by the REST API, e.g. `X:BTCUSD`.
+    pub fn rest_ticker(&self) -> String {
+        self.to_string()
+    }
+
+    /// The symbol used by the websocket feeds, e.g. `BTC-USD`.
+    pub fn feed_symbol(&self) -> String {
+        match self {
+            Instrument::Crypto { base, quote } => format!("{}-{}", base, quote),
+            Instrument::Forex { base, quote } => format!("{}/{}", base, quote),
+            other => other.to_string(),
+        }
+    }
+
+    /// The websocket subscription param for a channel of this instrument, e.g.
+    /// `XT.BTC-USD` for channel `XT`.
+    pub fn subscription(&self, channel: &str) -> String {
+        format!("{}.{}", channel, self.feed_symbol())
+    }
+
+    /// The traded symbol: the ticker of an equity or index, the base currency of a pair, or the
+    /// full ticker of an options contract.
+    pub fn symbol(&self) -> Cow<'_, str> {
+        match self {
+            Instrument::Equity(ticker) | Instrument::Index(ticker) => Cow::Borrowed(ticker),
+            Instrument::Crypto { base, .. } | Instrument::Forex { base, .. } => Cow::Borrowed(base),
+            Instrument::Option(contract) => Cow::Owned(contract.to_string()),
+        }
+    }
+
+    /// The currency prices are expressed in: the quote currency of a pair, otherwise US dollars.
+    pub fn currency(&self) -> &str {
+        match self {
+            Instrument::Crypto { quote, .. } | Instrument::Forex { quote, .. } => quote,
+            _ => "USD",
+        }
+    }
+}
+
+/// The websocket subscription params of a channel for several instruments, e.g.
+/// `XT.BTC-USD,XT.ETH-USD`.
+pub fn subscriptions<'a, I>(channel: &str, instruments: I) -> String
+where
+    I: IntoIterator<Item = &'a Instrument>,
+{
+    instruments
+        .into_iter()
+        .map(|instrument| instrument.subscription(channel))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn parse_ticker(ticker: &str) -> anyhow::Result<Symbol> {
+    if ticker.is_empty() || ticker.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(anyhow::Error::msg(format!("Invalid ticker: {:?}", ticker)));
+    }
+    Ok(Symbol::new(ticker))
+}
+
+/// Splits a concatenated crypto pair such as `BTCUSD` at its quote currency, which must be one
+/// of `CRYPTO_QUOTES`: without a separator, the end of an unknown quote cannot be told apart.
+fn split_crypto(pair: &str) -> Option<(&str, &str)> {
+    CRYPTO_QUOTES.iter().find_map(|quote| {
+        pair.strip_suffix(quote)
+            .filter(|base| !base.is_empty())
+            .map(|base| (base, &pair[base.len()..]))
+    })
+}
+
+impl FromStr for Instrument {
+    type Err = anyhow::Error;
+
+    /// Parses a REST ticker: `X:BTCUSD`, `C:EURUSD`, `O:...`, `I:SPX`, or a stock ticker such
+    /// as `AAPL` or `BRK-B` when there is no prefix. Websocket symbols are parsed with
+    /// `from_feed`, as their notation depends on the feed.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::Error::msg(format!("Invalid instrument: {}", s));
+        if let Some(pair) = s.strip_prefix("X:") {
+            let (base, quote) = split_crypto(pair).ok_or_else(invalid)?;
+            return Ok(Instrument::crypto(base, quote));
+        }
+        if let Some(pair) = s.strip_prefix("C:") {
+            if pair.len() != 6 || !pair.is_ascii() {
+                return Err(invalid());
+            }
+            return Ok(Instrument::forex(&pair[..3], &pair[3..]));
+        }
+        if s.starts_with("O:") {
+            return Instrument::from_feed(AssetClass::Options, s);
+        }
+        if s.starts_with("I:") {
+            return Instrument::from_feed(AssetClass::Indices, s);
+        }
+        Instrument::from_feed(AssetClass::Stocks, s)
+    }
+}
+
+impl From<CurrencyPair> for Instrument {
+    fn from(pair: CurrencyPair) -> Self {
+        Instrument::Forex {
+            base: pair.base,
+            quote: pair.quote,
+        }
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instrument::Equity(ticker) => write!(f, "{}", ticker),
+            Instrument::Crypto { base, quote } => write!(f, "X:{}{}", base, quote),
+            Instrument::Forex { base, quote } => write!(f, "C:{}{}", base, quote),
+            Instrument::Option(contract) => write!(f, "{}", contract),
+            Instrument::Index(ticker) => write!(f, "I:{}", ticker),
+        }
+    }
+}
+
+/// Serialized as the REST ticker, which parses back to the same instrument. Crypto pairs with a
+/// quote currency outside `CRYPTO_QUOTES` do not parse back and fail to deserialize.
+impl Serialize for Instrument {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Instrument {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        symbol::deserialize_str_with(deserializer, |text| {
+            text.parse().map_err(|e: anyhow::Error| e.to_string())
+        })
+    }
+}
+
+/// Deserializes the ticker of an endpoint that only lists stocks, e.g. grouped daily bars.
+pub(crate) fn deserialize_stock_ticker<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Instrument, D::Error> {
+    symbol::deserialize_str_with(deserializer, |text| {
+        Instrument::from_feed(AssetClass::Stocks, text).map_err(|e| e.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `rest`, checks its feed symbol and that both notations give it back.
+    fn roundtrip(rest: &str, feed: &str) -> Instrument {
+        let instrument: Instrument = rest.parse().unwrap();
+        assert_eq!(instrument.rest_ticker(), rest);
+        assert_eq!(instrument.feed_symbol(), feed);
+        assert_eq!(
+            Instrument::from_feed(instrument.asset_class(), feed).unwrap(),
+            instrument
+        );
+        let json = serde_json::to_string(&instrument).unwrap();
+        assert_eq!(json, format!("\"{}\"", rest));
+        assert_eq!(
+            serde_json::from_str::<Instrument>(&json).unwrap(),
+            instrument
+        );
+        instrument
+    }
+
+    #[test]
+    fn roundtrips_crypto_pairs() {
+        assert_eq!(
+            roundtrip("X:BTCUSD", "BTC-USD"),
+            Instrument::crypto("BTC", "USD")
+        );
+        assert_eq!(
+            roundtrip("X:BTCUSDT", "BTC-USDT"),
+            Instrument::crypto("BTC", "USDT")
+        );
+        assert_eq!(
+            roundtrip("X:ETHBUSD", "ETH-BUSD"),
+            Instrument::crypto("ETH", "BUSD")
+        );
+        assert_eq!(
+            roundtrip("X:DOGEBTC", "DOGE-BTC"),
+            Instrument::crypto("DOGE", "BTC")
+        );
+        // without a separator, an unknown quote has no end
+        assert!("X:SOLXYZ".parse::<Instrument>().is_err());
+        assert!("X:USD".parse::<Instrument>().is_err());
+        assert!(serde_json::from_str::<Instrument>("\"X:SOLXYZ\"").is_err());
+        assert_eq!(
+            Instrument::from_feed(AssetClass::Crypto, "SOL-XYZ").unwrap(),
+            Instrument::crypto("SOL", "XYZ")
+        );
+        assert_eq!(Instrument::crypto("ETH", "BUSD").currency(), "BUSD");
+    }
+
+    #[test]
+    fn roundtrips_forex_pairs() {
+        let pair = roundtrip("C:EURUSD", "EUR/USD");
+        assert_eq!(pair, Instrument::forex("EUR", "USD"));
+        assert_eq!(pair.symbol(), "EUR");
+        assert_eq!(pair.currency(), "USD");
+        assert!("C:EURUS".parse::<Instrument>().is_err());
+    }
+
+    #[test]
+    fn roundtrips_options_contracts() {
+        let instrument = roundtrip("O:SPY241220P00720000", "O:SPY241220P00720000");
+        let Instrument::Option(contract) = &instrument else {
+            panic!("not an option: {:?}", instrument);
+        };
+        assert_eq!(contract.underlying, "SPY");
+        assert_eq!(
+            contract.expiration,
+            NaiveDate::from_ymd_opt(2024, 12, 20).unwrap()
+        );
+        assert_eq!(contract.right, OptionRight::Put);
+        assert_eq!(contract.strike(), 720.0);
+        assert_eq!(instrument.asset_class(), AssetClass::Options);
+
+        let call = roundtrip("O:BRKB250117C00412500", "O:BRKB250117C00412500");
+        let Instrument::Option(contract) = call else {
+            panic!("not an option: {:?}", call);
+        };
+        assert_eq!(contract.underlying, "BRKB");
+        assert_eq!(contract.right, OptionRight::Call);
+        assert_eq!(contract.strike(), 412.5);
+
+        for invalid in [
+            "O:SPY",
+            "O:SPY241320P00720000",
+            "O:SPY241220X00720000",
+            "O:SPY241220P0072000A",
+        ] {
+            assert!(invalid.parse::<Instrument>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn roundtrips_indices_and_equities() {
+        assert_eq!(roundtrip("I:SPX", "I:SPX"), Instrument::index("SPX"));
+        assert_eq!(roundtrip("AAPL", "AAPL"), Instrument::equity("AAPL"));
+        assert_eq!(Instrument::equity("AAPL").currency(), "USD");
+        assert!("I:".parse::<Instrument>().is_err());
+        assert!("AA PL".parse::<Instrument>().is_err());
+    }
+
+    #[test]
+    fn reads_a_dash_by_asset_class() {
+        // a ticker without a prefix is a stock, share classes included
+        let class_b = roundtrip("BRK-B", "BRK-B");
+        assert_eq!(class_b, Instrument::equity("BRK-B"));
+        assert_eq!(
+            Instrument::from_feed(AssetClass::Stocks, "BRK-B").unwrap(),
+            class_b
+        );
+        assert_eq!(class_b.subscription("T"), "T.BRK-B");
+        // the crypto feed separates the currencies of a pair with a dash
+        assert_eq!(
+            Instrument::from_feed(AssetClass::Crypto, "BTC-USDT").unwrap(),
+            Instrument::crypto("BTC", "USDT")
+        );
+        assert!(Instrument::from_feed(AssetClass::Crypto, "BTCUSD").is_err());
+    }
+
+    #[test]
+    fn joins_subscriptions() {
+        let instruments = [
+            Instrument::crypto("BTC", "USD"),
+            Instrument::crypto("ETH", "USDT"),
+        ];
+        assert_eq!(subscriptions("XT", &instruments), "XT.BTC-USD,XT.ETH-USDT");
+    }
+}
@@ -1 +1,3 @@
+pub mod conditions;
+pub mod instrument;
 pub mod trade;
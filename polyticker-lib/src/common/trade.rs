@@ -1,16 +1,62 @@
+use crate::common::instrument::Instrument;
+use crate::exchange::registry::AssetClass;
 use chrono::{DateTime, Utc};
 
-pub struct TradeData {
-    pub symbol: String,
-    pub currency: String,
+/// A trade as seen by the consumers of every feed, e.g. `exchange::Bucket`.
+///
+/// The trade ID and conditions are borrowed from the event, so producing a `TradeData` does not
+/// allocate: the instrument is made of interned symbols.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeData<'a> {
+    pub instrument: Instrument,
     pub price: f64,
+    /// The traded quantity, in units of the symbol (shares, coins, contracts). Zero for quotes
+    /// exposed as trades.
+    pub size: f64,
     pub timestamp: DateTime<Utc>,
     pub exchange_id: i64,
+    /// The trade ID, unique per exchange and symbol. `None` for quotes exposed as trades.
+    pub trade_id: Option<&'a str>,
+    /// The condition IDs of the trade, see `common::conditions`.
+    pub conditions: &'a [i32],
+    /// The consolidated tape of a US stock trade.
+    pub tape: Option<Tape>,
+    /// When Polygon received the trade, for feeds that report it apart from `timestamp`.
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+/// The consolidated tape a US stock trade was reported to, by listing exchange.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tape {
+    /// Stocks listed on the NYSE.
+    A,
+    /// Stocks listed on NYSE American, NYSE Arca and the regional exchanges.
+    B,
+    /// Stocks listed on Nasdaq.
+    C,
+}
+
+impl Tape {
+    /// The tape of a Polygon tape ID (1 = NYSE, 2 = AMEX, 3 = Nasdaq).
+    pub fn from_id(id: i64) -> Option<Tape> {
+        match id {
+            1 => Some(Tape::A),
+            2 => Some(Tape::B),
+            3 => Some(Tape::C),
+            _ => None,
+        }
+    }
 }
 
 pub enum Currency {
     USD,
 }
-pub trait Trade {
-    fn get_trade(&self) -> anyhow::Result<TradeData>;
+
+/// An event that can be consumed as a trade. Implementations are shared across the tasks of
+/// a feed, hence `Send + Sync`.
+pub trait Trade: Send + Sync {
+    fn get_trade(&self) -> anyhow::Result<TradeData<'_>>;
+
+    /// The asset class of the trade, which gives meaning to its `exchange_id`.
+    fn asset_class(&self) -> AssetClass;
 }
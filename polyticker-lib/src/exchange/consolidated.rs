@@ -0,0 +1,312 @@
+use crate::exchange::LastTrade;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+
+/// The fewest fresh prices outlier rejection needs; with fewer, no price can be told apart as
+/// the bad one.
+const MIN_PRICES_FOR_OUTLIERS: usize = 3;
+
+/// Scales the median absolute deviation to the standard deviation of normally distributed
+/// prices (the modified z-score of Iglewicz and Hoaglin).
+const MAD_SCALE: f64 = 0.6745;
+
+/// Settings for consolidating the last prices of a bucket's exchanges.
+#[derive(Clone, Debug)]
+pub struct ConsolidationConfig {
+    /// Exchanges whose last trade is older than this are left out. `None` keeps every exchange.
+    pub staleness: Option<Duration>,
+    /// Prices whose modified z-score (from the median absolute deviation) exceeds this are
+    /// rejected as bad ticks. `None` disables outlier rejection.
+    pub outlier_threshold: Option<f64>,
+    /// The smallest deviation assumed, in basis points of the median, so that prices agreeing
+    /// to the tick on most exchanges do not make every other price an outlier.
+    pub min_deviation_bps: f64,
+}
+
+impl Default for ConsolidationConfig {
+    fn default() -> Self {
+        ConsolidationConfig {
+            staleness: Some(Duration::from_secs(60)),
+            outlier_threshold: Some(3.5),
+            min_deviation_bps: 1.0,
+        }
+    }
+}
+
+impl ConsolidationConfig {
+    /// The default settings with the given staleness window.
+    pub fn with_staleness(staleness: Duration) -> Self {
+        ConsolidationConfig {
+            staleness: Some(staleness),
+            ..Default::default()
+        }
+    }
+}
+
+/// A price consolidated across the exchanges of a bucket.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConsolidatedPrice {
+    /// The exchanges whose prices went into the metrics.
+    pub exchanges: Vec<i64>,
+    /// The exchanges left out because their last trade is too old.
+    pub stale: Vec<i64>,
+    /// The exchanges left out because their price is an outlier.
+    pub outliers: Vec<i64>,
+    pub median: f64,
+    pub mean: f64,
+    /// The last prices weighted by the size of the last trades. `None` if the trades carry no
+    /// size, e.g. forex quotes.
+    pub volume_weighted: Option<f64>,
+    /// The lowest price.
+    pub min: f64,
+    /// The highest price.
+    pub max: f64,
+}
+
+impl ConsolidatedPrice {
+    /// The difference between the highest and the lowest price.
+    pub fn spread(&self) -> f64 {
+        self.max - self.min
+    }
+
+    /// The spread in basis points of the median.
+    pub fn spread_bps(&self) -> f64 {
+        self.spread() / self.median * 10_000.0
+    }
+}
+
+/// Consolidates the last trades of a bucket as of `now`.
+///
+/// # Returns
+///
+/// The consolidated price, or `None` if no exchange is left once stale prices and outliers are
+/// removed.
+pub(crate) fn consolidate<'a, I>(
+    trades: I,
+    config: &ConsolidationConfig,
+    now: DateTime<Utc>,
+) -> Option<ConsolidatedPrice>
+where
+    I: IntoIterator<Item = &'a LastTrade>,
+{
+    let mut stale = Vec::new();
+    let mut fresh = Vec::new();
+    for trade in trades {
+        let age = (now - trade.timestamp).to_std().unwrap_or(Duration::ZERO);
+        match config.staleness {
+            Some(staleness) if age > staleness => stale.push(trade.exchange_id),
+            _ => fresh.push(trade),
+        }
+    }
+
+    let mut outliers = Vec::new();
+    if let Some(threshold) = config.outlier_threshold {
+        if fresh.len() >= MIN_PRICES_FOR_OUTLIERS {
+            let center = median(fresh.iter().map(|trade| trade.price));
+            let deviation = median(fresh.iter().map(|trade| (trade.price - center).abs()))
+                .max(center.abs() * config.min_deviation_bps / 10_000.0);
+            fresh.retain(|trade| {
+                let distance = (trade.price - center).abs();
+                // a zero deviation (with `min_deviation_bps` at zero) rejects any other price
+                let score = if distance == 0.0 {
+                    0.0
+                } else {
+                    MAD_SCALE * distance / deviation
+                };
+                if score > threshold {
+                    outliers.push(trade.exchange_id);
+                }
+                score <= threshold
+            });
+        }
+    }
+    if fresh.is_empty() {
+        return None;
+    }
+
+    let prices = fresh.iter().map(|trade| trade.price);
+    let volume: f64 = fresh.iter().map(|trade| trade.size).sum();
+    let volume_weighted = (volume > 0.0).then(|| {
+        fresh
+            .iter()
+            .map(|trade| trade.price * trade.size)
+            .sum::<f64>()
+            / volume
+    });
+    Some(ConsolidatedPrice {
+        exchanges: fresh.iter().map(|trade| trade.exchange_id).collect(),
+        stale,
+        outliers,
+        median: median(prices.clone()),
+        mean: prices.clone().sum::<f64>() / fresh.len() as f64,
+        volume_weighted,
+        min: prices.clone().fold(f64::INFINITY, f64::min),
+        max: prices.fold(f64::NEG_INFINITY, f64::max),
+    })
+}
+
+fn median(values: impl Iterator<Item = f64>) -> f64 {
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::registry::AssetClass;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap()
+    }
+
+    fn trade(exchange_id: i64, price: f64, age_secs: i64) -> LastTrade {
+        LastTrade {
+            exchange_id,
+            asset_class: AssetClass::Crypto,
+            price,
+            size: 1.0,
+            timestamp: now() - chrono::Duration::seconds(age_secs),
+        }
+    }
+
+    fn prices(prices: &[f64]) -> Vec<LastTrade> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(index, price)| trade(index as i64 + 1, *price, 0))
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_tick() {
+        let trades = prices(&[100.0, 100.1, 99.9, 100.05, 110.0]);
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+
+        assert_eq!(price.exchanges, vec![1, 2, 3, 4]);
+        assert_eq!(price.outliers, vec![5]);
+        assert!(price.stale.is_empty());
+        assert_close(price.median, 100.025);
+        assert_close(price.mean, 100.0125);
+        assert_close(price.min, 99.9);
+        assert_close(price.max, 100.1);
+        assert_close(price.spread_bps(), 0.2 / 100.025 * 10_000.0);
+    }
+
+    #[test]
+    fn keeps_every_price_without_a_threshold() {
+        let trades = prices(&[100.0, 100.1, 99.9, 100.05, 110.0]);
+        let config = ConsolidationConfig {
+            outlier_threshold: None,
+            ..Default::default()
+        };
+        let price = consolidate(&trades, &config, now()).unwrap();
+        assert_eq!(price.exchanges, vec![1, 2, 3, 4, 5]);
+        assert!(price.outliers.is_empty());
+        assert_close(price.max, 110.0);
+    }
+
+    #[test]
+    fn needs_three_prices_to_reject_one() {
+        let trades = prices(&[100.0, 200.0]);
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+        assert_eq!(price.exchanges, vec![1, 2]);
+        assert!(price.outliers.is_empty());
+        assert_close(price.median, 150.0);
+    }
+
+    #[test]
+    fn floors_the_deviation_of_agreeing_prices() {
+        // three exchanges agree to the tick, so the median absolute deviation is zero
+        let trades = prices(&[100.0, 100.0, 100.0, 100.02]);
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+        assert_eq!(price.exchanges, vec![1, 2, 3, 4]);
+        assert!(price.outliers.is_empty());
+
+        let config = ConsolidationConfig {
+            min_deviation_bps: 0.0,
+            ..Default::default()
+        };
+        let price = consolidate(&trades, &config, now()).unwrap();
+        assert_eq!(price.exchanges, vec![1, 2, 3]);
+        assert_eq!(price.outliers, vec![4]);
+
+        // a price a few basis points away is still an outlier past the floor
+        let trades = prices(&[100.0, 100.0, 100.0, 100.1]);
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+        assert_eq!(price.outliers, vec![4]);
+    }
+
+    #[test]
+    fn leaves_out_stale_exchanges() {
+        let trades = [
+            trade(1, 100.0, 5),
+            trade(2, 101.0, 61),
+            trade(3, 102.0, 60),
+            // ahead of the local clock
+            trade(4, 103.0, -2),
+        ];
+        let config = ConsolidationConfig::with_staleness(Duration::from_secs(60));
+        let price = consolidate(&trades, &config, now()).unwrap();
+        assert_eq!(price.exchanges, vec![1, 3, 4]);
+        assert_eq!(price.stale, vec![2]);
+
+        let config = ConsolidationConfig {
+            staleness: None,
+            ..Default::default()
+        };
+        let price = consolidate(&trades, &config, now()).unwrap();
+        assert_eq!(price.exchanges, vec![1, 2, 3, 4]);
+        assert!(price.stale.is_empty());
+    }
+
+    #[test]
+    fn has_no_price_once_every_exchange_is_stale() {
+        let trades = [trade(1, 100.0, 120), trade(2, 101.0, 90)];
+        let config = ConsolidationConfig::with_staleness(Duration::from_secs(60));
+        assert_eq!(consolidate(&trades, &config, now()), None);
+        assert_eq!(consolidate(&[], &config, now()), None);
+    }
+
+    #[test]
+    fn consolidates_a_single_exchange() {
+        let trades = [trade(7, 42.5, 0)];
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+        assert_eq!(price.exchanges, vec![7]);
+        assert_eq!(price.median, 42.5);
+        assert_eq!(price.mean, 42.5);
+        assert_eq!(price.volume_weighted, Some(42.5));
+        assert_eq!(price.spread(), 0.0);
+        assert_eq!(price.spread_bps(), 0.0);
+    }
+
+    #[test]
+    fn weights_prices_by_size() {
+        let mut trades = prices(&[100.0, 102.0]);
+        trades[1].size = 3.0;
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+        assert_close(price.volume_weighted.unwrap(), 101.5);
+
+        for trade in &mut trades {
+            trade.size = 0.0;
+        }
+        let price = consolidate(&trades, &ConsolidationConfig::default(), now()).unwrap();
+        assert_eq!(price.volume_weighted, None);
+    }
+}
@@ -0,0 +1,617 @@
+use crate::exchange::registry::{exchange_name, AssetClass};
+use crate::exchange::{display_price, Bucket, BucketSet, LastTrade};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+/// Settings for detecting price divergences between the exchanges of a bucket.
+#[derive(Clone, Debug)]
+pub struct DivergenceConfig {
+    /// The net spread, in basis points after fees, at which a divergence opens.
+    pub open_bps: f64,
+    /// The net spread below which an open divergence closes. Lower than `open_bps` so that a
+    /// spread hovering around the threshold does not open and close on every trade.
+    pub close_bps: f64,
+    /// How long the net spread must stay above `open_bps` before the divergence opens.
+    pub min_persistence: Duration,
+    /// The fee paid on each side of a trade, in basis points, by exchange ID.
+    pub fees_bps: HashMap<i64, f64>,
+    /// The fee of exchanges missing from `fees_bps`.
+    pub default_fee_bps: f64,
+    /// Exchanges whose last trade is older than this are left out. `None` keeps every exchange.
+    pub staleness: Option<Duration>,
+    /// How long the durations of closed divergences are kept for the statistics, counted from
+    /// their close.
+    pub duration_window: Duration,
+    /// The most durations kept per divergence, bounding memory if one opens and closes all day.
+    /// The oldest are dropped first.
+    pub max_durations: usize,
+}
+
+impl Default for DivergenceConfig {
+    fn default() -> Self {
+        DivergenceConfig {
+            open_bps: 10.0,
+            close_bps: 5.0,
+            min_persistence: Duration::from_secs(2),
+            fees_bps: HashMap::new(),
+            default_fee_bps: 0.0,
+            staleness: Some(Duration::from_secs(60)),
+            duration_window: Duration::from_secs(3600),
+            max_durations: 1000,
+        }
+    }
+}
+
+impl DivergenceConfig {
+    /// The default settings opening at `open_bps` and closing at half of it.
+    pub fn with_threshold(open_bps: f64) -> Self {
+        DivergenceConfig {
+            open_bps,
+            close_bps: open_bps / 2.0,
+            ..Default::default()
+        }
+    }
+
+    fn fee_bps(&self, exchange_id: i64) -> f64 {
+        self.fees_bps
+            .get(&exchange_id)
+            .copied()
+            .unwrap_or(self.default_fee_bps)
+    }
+}
+
+/// The spread between buying on one exchange and selling on another, at their last prices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairSpread {
+    /// The asset class of the exchanges, which gives meaning to their IDs.
+    pub asset_class: AssetClass,
+    /// The exchange with the lower price.
+    pub buy_exchange: i64,
+    /// The exchange with the higher price.
+    pub sell_exchange: i64,
+    pub buy_price: f64,
+    pub sell_price: f64,
+    /// The price difference.
+    pub spread: f64,
+    /// The price difference in basis points of the buy price.
+    pub spread_bps: f64,
+    /// `spread_bps` less the fees of both exchanges.
+    pub net_bps: f64,
+}
+
+/// A divergence opening or closing between two exchanges of a bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DivergenceEvent {
+    Opened {
+        symbol: String,
+        currency: String,
+        spread: PairSpread,
+        at: DateTime<Utc>,
+    },
+    Closed {
+        symbol: String,
+        currency: String,
+        asset_class: AssetClass,
+        buy_exchange: i64,
+        sell_exchange: i64,
+        opened_at: DateTime<Utc>,
+        at: DateTime<Utc>,
+        /// The largest net spread while open, in basis points.
+        peak_bps: f64,
+    },
+}
+
+impl DivergenceEvent {
+    /// How long a closed divergence was open.
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            DivergenceEvent::Opened { .. } => None,
+            DivergenceEvent::Closed { opened_at, at, .. } => {
+                Some((*at - *opened_at).to_std().unwrap_or(Duration::ZERO))
+            }
+        }
+    }
+}
+
+impl fmt::Display for DivergenceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DivergenceEvent::Opened {
+                symbol,
+                currency,
+                spread,
+                at,
+            } => write!(
+                f,
+                "{} OPEN {}/{}: buy {} at {} sell {} at {}, {} ({:.1} bps, {:.1} bps net)",
+                at,
+                symbol,
+                currency,
+                exchange_name(spread.asset_class, spread.buy_exchange),
+                spread.buy_price,
+                exchange_name(spread.asset_class, spread.sell_exchange),
+                spread.sell_price,
+                display_price(spread.spread),
+                spread.spread_bps,
+                spread.net_bps
+            ),
+            DivergenceEvent::Closed {
+                symbol,
+                currency,
+                asset_class,
+                buy_exchange,
+                sell_exchange,
+                at,
+                peak_bps,
+                ..
+            } => write!(
+                f,
+                "{} CLOSE {}/{}: buy {} sell {} after {:?}, peak {:.1} bps net",
+                at,
+                symbol,
+                currency,
+                exchange_name(*asset_class, *buy_exchange),
+                exchange_name(*asset_class, *sell_exchange),
+                self.duration().unwrap_or_default(),
+                peak_bps
+            ),
+        }
+    }
+}
+
+/// Statistics of how long divergences stayed open.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DurationStats {
+    pub count: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub max: Duration,
+}
+
+impl DurationStats {
+    fn from_durations<'a>(durations: impl Iterator<Item = &'a Duration>) -> Self {
+        let mut durations: Vec<Duration> = durations.copied().collect();
+        if durations.is_empty() {
+            return DurationStats::default();
+        }
+        durations.sort();
+        let total: Duration = durations.iter().sum();
+        DurationStats {
+            count: durations.len(),
+            mean: total / durations.len() as u32,
+            median: durations[durations.len() / 2],
+            max: durations[durations.len() - 1],
+        }
+    }
+}
+
+/// A spread above the open threshold, possibly not persistent enough yet to be open.
+struct Divergence {
+    asset_class: AssetClass,
+    since: DateTime<Utc>,
+    opened_at: Option<DateTime<Utc>>,
+    peak_bps: f64,
+}
+
+/// Identifies a divergence: symbol, currency, buy exchange and sell exchange.
+type DivergenceKey = (String, String, i64, i64);
+
+/// Watches the spreads between the exchanges of buckets and reports divergences that persist
+/// above a threshold.
+pub struct DivergenceMonitor {
+    config: DivergenceConfig,
+    divergences: HashMap<DivergenceKey, Divergence>,
+    /// When recent divergences closed and how long they were open, oldest first.
+    durations: HashMap<DivergenceKey, VecDeque<(DateTime<Utc>, Duration)>>,
+}
+
+impl DivergenceMonitor {
+    pub fn new(config: DivergenceConfig) -> Self {
+        DivergenceMonitor {
+            config,
+            divergences: HashMap::new(),
+            durations: HashMap::new(),
+        }
+    }
+
+    /// The spreads between every two fresh exchanges of the bucket, largest net spread first.
+    pub fn spreads(&self, bucket: &Bucket) -> Vec<PairSpread> {
+        self.spreads_at(bucket, Utc::now())
+    }
+
+    /// Like `spreads`, judging staleness as of `now`.
+    pub fn spreads_at(&self, bucket: &Bucket, now: DateTime<Utc>) -> Vec<PairSpread> {
+        let fresh: Vec<&LastTrade> = bucket
+            .trades()
+            .filter(|trade| match self.config.staleness {
+                Some(staleness) => {
+                    (now - trade.timestamp).to_std().unwrap_or(Duration::ZERO) <= staleness
+                }
+                None => true,
+            })
+            .collect();
+        let mut spreads = Vec::new();
+        for (index, a) in fresh.iter().enumerate() {
+            for b in &fresh[index + 1..] {
+                let (buy, sell) = if a.price <= b.price { (a, b) } else { (b, a) };
+                let spread = sell.price - buy.price;
+                let spread_bps = spread / buy.price * 10_000.0;
+                spreads.push(PairSpread {
+                    asset_class: buy.asset_class,
+                    buy_exchange: buy.exchange_id,
+                    sell_exchange: sell.exchange_id,
+                    buy_price: buy.price,
+                    sell_price: sell.price,
+                    spread,
+                    spread_bps,
+                    net_bps: spread_bps
+                        - self.config.fee_bps(buy.exchange_id)
+                        - self.config.fee_bps(sell.exchange_id),
+                });
+            }
+        }
+        spreads.sort_by(|a, b| b.net_bps.total_cmp(&a.net_bps));
+        spreads
+    }
+
+    /// Re-evaluates the spreads of the bucket.
+    ///
+    /// # Returns
+    ///
+    /// The divergences that opened or closed since the last update.
+    pub fn update(&mut self, bucket: &Bucket) -> Vec<DivergenceEvent> {
+        self.update_at(bucket, Utc::now())
+    }
+
+    /// Like `update` as of `now`, e.g. the time of a replayed frame.
+    pub fn update_at(&mut self, bucket: &Bucket, now: DateTime<Utc>) -> Vec<DivergenceEvent> {
+        let (symbol, currency) = (bucket.symbol().to_string(), bucket.currency().to_string());
+        let spreads: HashMap<(i64, i64), PairSpread> = self
+            .spreads_at(bucket, now)
+            .into_iter()
+            .map(|spread| ((spread.buy_exchange, spread.sell_exchange), spread))
+            .collect();
+        let mut events = Vec::new();
+
+        // close or forget the divergences whose spread narrowed or whose exchanges went stale
+        let tracked: Vec<DivergenceKey> = self
+            .divergences
+            .keys()
+            .filter(|(s, c, _, _)| *s == symbol && *c == currency)
+            .cloned()
+            .collect();
+        for key in tracked {
+            let net_bps = spreads.get(&(key.2, key.3)).map(|spread| spread.net_bps);
+            let divergence = &self.divergences[&key];
+            let threshold = match divergence.opened_at {
+                Some(_) => self.config.close_bps,
+                None => self.config.open_bps,
+            };
+            if net_bps.is_some_and(|net_bps| net_bps >= threshold) {
+                continue;
+            }
+            let divergence = self.divergences.remove(&key).unwrap();
+            if let Some(opened_at) = divergence.opened_at {
+                let event = DivergenceEvent::Closed {
+                    symbol: key.0.clone(),
+                    currency: key.1.clone(),
+                    asset_class: divergence.asset_class,
+                    buy_exchange: key.2,
+                    sell_exchange: key.3,
+                    opened_at,
+                    at: now,
+                    peak_bps: divergence.peak_bps,
+                };
+                let durations = self.durations.entry(key).or_default();
+                durations.push_back((now, event.duration().unwrap_or_default()));
+                while durations.len() > self.config.max_durations.max(1) {
+                    durations.pop_front();
+                }
+                events.push(event);
+            }
+        }
+
+        self.expire_durations(now);
+
+        // track the spreads above the threshold and open those that persisted long enough
+        for ((buy, sell), spread) in spreads {
+            let key = (symbol.clone(), currency.clone(), buy, sell);
+            let divergence = match self.divergences.get_mut(&key) {
+                Some(divergence) => divergence,
+                None if spread.net_bps >= self.config.open_bps => {
+                    self.divergences.entry(key).or_insert(Divergence {
+                        asset_class: spread.asset_class,
+                        since: now,
+                        opened_at: None,
+                        peak_bps: spread.net_bps,
+                    })
+                }
+                None => continue,
+            };
+            divergence.peak_bps = divergence.peak_bps.max(spread.net_bps);
+            let persisted = (now - divergence.since).to_std().unwrap_or(Duration::ZERO);
+            if divergence.opened_at.is_none() && persisted >= self.config.min_persistence {
+                divergence.opened_at = Some(now);
+                events.push(DivergenceEvent::Opened {
+                    symbol: symbol.clone(),
+                    currency: currency.clone(),
+                    spread,
+                    at: now,
+                });
+            }
+        }
+        events
+    }
+
+    /// Re-evaluates the spreads of every bucket of the set, see `update`.
+    pub fn update_set(&mut self, buckets: &BucketSet) -> Vec<DivergenceEvent> {
+        self.update_set_at(buckets, Utc::now())
+    }
+
+    /// Like `update_set` as of `now`.
+    pub fn update_set_at(
+        &mut self,
+        buckets: &BucketSet,
+        now: DateTime<Utc>,
+    ) -> Vec<DivergenceEvent> {
+        buckets
+            .buckets()
+            .flat_map(|bucket| self.update_at(bucket, now))
+            .collect()
+    }
+
+    /// The number of divergences open now.
+    pub fn open_count(&self) -> usize {
+        self.divergences
+            .values()
+            .filter(|divergence| divergence.opened_at.is_some())
+            .count()
+    }
+
+    /// How long the divergences that closed within `duration_window` stayed open.
+    pub fn duration_stats(&self) -> DurationStats {
+        DurationStats::from_durations(
+            self.durations
+                .values()
+                .flatten()
+                .map(|(_, duration)| duration),
+        )
+    }
+
+    /// Like `duration_stats`, for the divergences of `symbol` in `currency` buying on
+    /// `buy_exchange` and selling on `sell_exchange`.
+    pub fn pair_duration_stats(
+        &self,
+        symbol: &str,
+        currency: &str,
+        buy_exchange: i64,
+        sell_exchange: i64,
+    ) -> DurationStats {
+        let key = (
+            symbol.to_string(),
+            currency.to_string(),
+            buy_exchange,
+            sell_exchange,
+        );
+        DurationStats::from_durations(
+            self.durations
+                .get(&key)
+                .into_iter()
+                .flatten()
+                .map(|(_, duration)| duration),
+        )
+    }
+
+    /// Drops the durations of divergences that closed before `duration_window`.
+    fn expire_durations(&mut self, now: DateTime<Utc>) {
+        let cutoff =
+            now - chrono::Duration::from_std(self.config.duration_window).unwrap_or_default();
+        self.durations.retain(|_, durations| {
+            while durations
+                .front()
+                .is_some_and(|(closed_at, _)| *closed_at < cutoff)
+            {
+                durations.pop_front();
+            }
+            !durations.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::crypto::CryptoTradeEvent;
+    use crate::websocket::symbol::Symbol;
+    use chrono::TimeZone;
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap()
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        start() + chrono::Duration::seconds(secs)
+    }
+
+    fn trade(bucket: &mut Bucket, exchange_id: i64, price: f64, timestamp: DateTime<Utc>) {
+        let pair = format!("{}-{}", bucket.symbol(), bucket.currency());
+        bucket
+            .add_trade(Box::new(CryptoTradeEvent {
+                event_type: Symbol::new("XT"),
+                pair: Symbol::new(&pair),
+                price,
+                timestamp,
+                size: 1.0,
+                conditions: Vec::new(),
+                trade_id: timestamp.timestamp_millis().to_string(),
+                exchange_id,
+                received_timestamp: timestamp,
+            }))
+            .unwrap();
+    }
+
+    fn monitor() -> DivergenceMonitor {
+        DivergenceMonitor::new(DivergenceConfig {
+            staleness: None,
+            ..DivergenceConfig::with_threshold(10.0)
+        })
+    }
+
+    /// Opens a divergence of 20 bps at `opened` and closes it `open_secs` later.
+    fn open_and_close(
+        monitor: &mut DivergenceMonitor,
+        bucket: &mut Bucket,
+        opened: i64,
+        open_secs: i64,
+    ) {
+        trade(bucket, 1, 100.0, at(opened - 2));
+        trade(bucket, 2, 100.2, at(opened - 2));
+        monitor.update_at(bucket, at(opened - 2));
+        assert_eq!(monitor.update_at(bucket, at(opened)).len(), 1);
+        trade(bucket, 2, 100.0, at(opened + open_secs));
+        assert_eq!(monitor.update_at(bucket, at(opened + open_secs)).len(), 1);
+    }
+
+    #[test]
+    fn computes_net_spreads() {
+        let mut bucket = Bucket::new("BTC", "USD");
+        trade(&mut bucket, 1, 100.0, at(0));
+        trade(&mut bucket, 2, 100.3, at(0));
+        trade(&mut bucket, 3, 100.1, at(0));
+        let mut config = DivergenceConfig::with_threshold(10.0);
+        config.fees_bps.insert(2, 4.0);
+        config.default_fee_bps = 1.0;
+        let spreads = DivergenceMonitor::new(config).spreads_at(&bucket, at(1));
+
+        assert_eq!(spreads.len(), 3);
+        assert_eq!((spreads[0].buy_exchange, spreads[0].sell_exchange), (1, 2));
+        assert!((spreads[0].spread_bps - 30.0).abs() < 1e-6);
+        assert!((spreads[0].net_bps - 25.0).abs() < 1e-6);
+        assert!(spreads.windows(2).all(|w| w[0].net_bps >= w[1].net_bps));
+    }
+
+    #[test]
+    fn opens_once_the_spread_persisted_and_closes_below_the_lower_threshold() {
+        let mut monitor = monitor();
+        let mut bucket = Bucket::new("BTC", "USD");
+        trade(&mut bucket, 1, 100.0, at(0));
+        trade(&mut bucket, 2, 100.2, at(0));
+
+        assert!(monitor.update_at(&bucket, at(0)).is_empty());
+        assert!(monitor.update_at(&bucket, at(1)).is_empty());
+        assert_eq!(monitor.open_count(), 0);
+
+        let events = monitor.update_at(&bucket, at(2));
+        let [DivergenceEvent::Opened { symbol, spread, .. }] = &events[..] else {
+            panic!("not opened: {:?}", events);
+        };
+        assert_eq!(symbol, "BTC");
+        assert_eq!((spread.buy_exchange, spread.sell_exchange), (1, 2));
+        assert_eq!(monitor.open_count(), 1);
+        // no second event while open
+        assert!(monitor.update_at(&bucket, at(3)).is_empty());
+
+        // between the close and open thresholds the divergence stays open
+        trade(&mut bucket, 2, 100.07, at(4));
+        assert!(monitor.update_at(&bucket, at(4)).is_empty());
+        assert_eq!(monitor.open_count(), 1);
+
+        trade(&mut bucket, 2, 100.03, at(6));
+        let events = monitor.update_at(&bucket, at(6));
+        let [closed @ DivergenceEvent::Closed {
+            opened_at,
+            peak_bps,
+            ..
+        }] = &events[..]
+        else {
+            panic!("not closed: {:?}", events);
+        };
+        assert_eq!(*opened_at, at(2));
+        assert!((peak_bps - 20.0).abs() < 1e-6);
+        assert_eq!(closed.duration(), Some(Duration::from_secs(4)));
+        assert_eq!(monitor.open_count(), 0);
+    }
+
+    #[test]
+    fn forgets_a_spread_that_narrows_before_persisting() {
+        let mut monitor = monitor();
+        let mut bucket = Bucket::new("BTC", "USD");
+        trade(&mut bucket, 1, 100.0, at(0));
+        trade(&mut bucket, 2, 100.2, at(0));
+        assert!(monitor.update_at(&bucket, at(0)).is_empty());
+
+        // below the open threshold, although above the close threshold
+        trade(&mut bucket, 2, 100.07, at(1));
+        assert!(monitor.update_at(&bucket, at(1)).is_empty());
+
+        // widening again starts over
+        trade(&mut bucket, 2, 100.2, at(2));
+        assert!(monitor.update_at(&bucket, at(2)).is_empty());
+        assert!(monitor.update_at(&bucket, at(3)).is_empty());
+        assert_eq!(monitor.update_at(&bucket, at(4)).len(), 1);
+        assert_eq!(monitor.duration_stats().count, 0);
+    }
+
+    #[test]
+    fn closes_when_an_exchange_goes_stale() {
+        let mut monitor = DivergenceMonitor::new(DivergenceConfig::with_threshold(10.0));
+        let mut bucket = Bucket::new("BTC", "USD");
+        trade(&mut bucket, 1, 100.0, at(0));
+        trade(&mut bucket, 2, 100.2, at(0));
+        monitor.update_at(&bucket, at(0));
+        assert_eq!(monitor.update_at(&bucket, at(2)).len(), 1);
+
+        trade(&mut bucket, 1, 100.0, at(50));
+        let events = monitor.update_at(&bucket, at(61));
+        assert!(matches!(events[..], [DivergenceEvent::Closed { .. }]));
+    }
+
+    #[test]
+    fn keeps_duration_stats_per_symbol() {
+        let mut monitor = monitor();
+        let mut btc = Bucket::new("BTC", "USD");
+        let mut eth = Bucket::new("ETH", "USD");
+        open_and_close(&mut monitor, &mut btc, 2, 3);
+        open_and_close(&mut monitor, &mut eth, 10, 9);
+        open_and_close(&mut monitor, &mut btc, 30, 5);
+
+        let btc_stats = monitor.pair_duration_stats("BTC", "USD", 1, 2);
+        assert_eq!(btc_stats.count, 2);
+        assert_eq!(btc_stats.max, Duration::from_secs(5));
+        let eth_stats = monitor.pair_duration_stats("ETH", "USD", 1, 2);
+        assert_eq!(eth_stats.count, 1);
+        assert_eq!(eth_stats.mean, Duration::from_secs(9));
+        assert_eq!(monitor.pair_duration_stats("BTC", "USD", 2, 1).count, 0);
+
+        let stats = monitor.duration_stats();
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.median, Duration::from_secs(5));
+        assert_eq!(stats.max, Duration::from_secs(9));
+    }
+
+    #[test]
+    fn keeps_the_durations_of_the_window() {
+        let mut monitor = DivergenceMonitor::new(DivergenceConfig {
+            staleness: None,
+            duration_window: Duration::from_secs(60),
+            max_durations: 2,
+            ..DivergenceConfig::with_threshold(10.0)
+        });
+        let mut bucket = Bucket::new("BTC", "USD");
+        for (index, opened) in [2, 12, 22].into_iter().enumerate() {
+            open_and_close(&mut monitor, &mut bucket, opened, index as i64 + 1);
+        }
+        // capped to the latest two
+        let stats = monitor.pair_duration_stats("BTC", "USD", 1, 2);
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.mean, Duration::from_millis(2500));
+
+        // the two kept closed at 14 s and 25 s
+        monitor.update_at(&bucket, at(75));
+        assert_eq!(monitor.duration_stats().count, 1);
+        monitor.update_at(&bucket, at(86));
+        assert_eq!(monitor.duration_stats(), DurationStats::default());
+    }
+}
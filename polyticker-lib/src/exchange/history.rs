@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How much trade history a bucket keeps per exchange.
+#[derive(Clone, Debug)]
+pub struct HistoryConfig {
+    /// How far back trades are kept, relative to the latest trade of the exchange. Statistics
+    /// over longer periods only see this much.
+    pub window: Duration,
+    /// The most trades kept per exchange, bounding memory on busy venues. The oldest trades are
+    /// dropped first.
+    pub max_trades: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            window: Duration::from_secs(300),
+            max_trades: 10_000,
+        }
+    }
+}
+
+impl HistoryConfig {
+    /// The default settings keeping `window` of trades.
+    pub fn with_window(window: Duration) -> Self {
+        HistoryConfig {
+            window,
+            ..Default::default()
+        }
+    }
+}
+
+/// A trade kept in the history of an exchange.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradePrint {
+    pub price: f64,
+    pub size: f64,
+    pub timestamp: DateTime<Utc>,
+    /// Whether the trade may set the last price. Prints that only count for volume, such as
+    /// average price or Form T trades, leave the prices of `RollingStats` alone.
+    pub updates_last: bool,
+}
+
+/// Statistics of the trades of an exchange over a period ending now. The counts cover every
+/// print, the prices only those that may set the last price.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RollingStats {
+    #[serde(rename = "period_secs", serialize_with = "serialize_secs")]
+    pub period: Duration,
+    /// The number of trades in the period.
+    pub trade_count: usize,
+    /// The total size of the trades in the period.
+    pub volume: f64,
+    /// The price at the start of the period: the last trade before it, or the first trade in
+    /// it if the history does not reach further back.
+    pub open: f64,
+    /// The price of the last trade that may set it.
+    pub last: f64,
+    /// The standard deviation of the log returns between consecutive trades in the period.
+    /// `None` with fewer than three trades.
+    pub volatility: Option<f64>,
+}
+
+impl RollingStats {
+    /// The price change over the period.
+    pub fn change(&self) -> f64 {
+        self.last - self.open
+    }
+
+    /// The price change over the period in basis points of the opening price.
+    pub fn change_bps(&self) -> f64 {
+        self.change() / self.open * 10_000.0
+    }
+}
+
+fn serialize_secs<S: Serializer>(period: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(period.as_secs_f64())
+}
+
+/// The recent trades of one exchange, oldest first.
+#[derive(Clone, Debug)]
+pub struct TradeHistory {
+    config: HistoryConfig,
+    trades: VecDeque<TradePrint>,
+}
+
+impl TradeHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        TradeHistory {
+            config,
+            trades: VecDeque::new(),
+        }
+    }
+
+    /// Adds a trade in timestamp order, then drops the trades that fell out of the window.
+    pub fn push(&mut self, trade: TradePrint) {
+        // late prints go before the newer trades already kept
+        let index = self
+            .trades
+            .iter()
+            .rposition(|kept| kept.timestamp <= trade.timestamp)
+            .map_or(0, |index| index + 1);
+        self.trades.insert(index, trade);
+
+        let Some(latest) = self.trades.back().map(|trade| trade.timestamp) else {
+            return;
+        };
+        let cutoff = latest - chrono::Duration::from_std(self.config.window).unwrap_or_default();
+        // the last price before the window is kept as the window's opening price
+        let opening = self
+            .trades
+            .iter()
+            .rposition(|trade| trade.timestamp <= cutoff && trade.updates_last);
+        let mut index = 0;
+        self.trades.retain(|trade| {
+            let keep = trade.timestamp > cutoff || Some(index) == opening;
+            index += 1;
+            keep
+        });
+        while self.trades.len() > self.config.max_trades.max(1) {
+            self.trades.pop_front();
+        }
+    }
+
+    /// The trades kept, oldest first.
+    pub fn trades(&self) -> impl Iterator<Item = &TradePrint> {
+        self.trades.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    /// The statistics of the `period` ending at `now`, or `None` if no trade had set the last
+    /// price by then.
+    pub fn stats_at(&self, period: Duration, now: DateTime<Utc>) -> Option<RollingStats> {
+        let start = now - chrono::Duration::from_std(period).unwrap_or_default();
+        let until_now: Vec<&TradePrint> = self
+            .trades
+            .iter()
+            .take_while(|trade| trade.timestamp <= now)
+            .collect();
+        let priced: Vec<&TradePrint> = until_now
+            .iter()
+            .copied()
+            .filter(|trade| trade.updates_last)
+            .collect();
+        let last = priced.last()?;
+        let before = priced.iter().rposition(|trade| trade.timestamp <= start);
+        let priced_in_period = &priced[before.map_or(0, |index| index + 1)..];
+        let open = match before {
+            Some(index) => priced[index].price,
+            None => priced_in_period.first()?.price,
+        };
+        let in_period = until_now.iter().filter(|trade| trade.timestamp > start);
+
+        let returns: Vec<f64> = priced_in_period
+            .windows(2)
+            .map(|pair| (pair[1].price / pair[0].price).ln())
+            .collect();
+        let volatility = (returns.len() >= 2).then(|| {
+            let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+            let variance = returns.iter().map(|ret| (ret - mean).powi(2)).sum::<f64>()
+                / (returns.len() - 1) as f64;
+            variance.sqrt()
+        });
+        Some(RollingStats {
+            period,
+            trade_count: in_period.clone().count(),
+            volume: in_period.map(|trade| trade.size).sum(),
+            open,
+            last: last.price,
+            volatility,
+        })
+    }
+}
@@ -1,11 +1,49 @@
-use crate::common::trade::{Trade, TradeData};
-use prettytable::row;
-use std::collections::BTreeMap;
+pub mod consolidated;
+pub mod divergence;
+pub mod history;
+pub mod registry;
+pub mod render;
 
+use crate::common::conditions::TradeFilter;
+use crate::common::instrument::Instrument;
+use crate::common::trade::Trade;
+use crate::exchange::consolidated::{ConsolidatedPrice, ConsolidationConfig};
+use crate::exchange::history::{HistoryConfig, RollingStats, TradeHistory, TradePrint};
+use crate::exchange::registry::AssetClass;
+use crate::exchange::render::{Renderer, TableRenderer};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::time::Duration;
+
+/// The last trade of an exchange in a `Bucket`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LastTrade {
+    pub exchange_id: i64,
+    /// The asset class of the trade, which gives meaning to `exchange_id`.
+    pub asset_class: AssetClass,
+    pub price: f64,
+    pub size: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl LastTrade {
+    /// The name of the exchange in the global registry.
+    pub fn exchange_name(&self) -> String {
+        registry::exchange_name(self.asset_class, self.exchange_id)
+    }
+}
+
+/// The last trade and recent history of each exchange for one symbol and currency, e.g. BTC
+/// in USD.
 pub struct Bucket {
     symbol: String,
     currency: String,
-    data: BTreeMap<i64, Box<dyn Trade>>,
+    data: BTreeMap<i64, LastTrade>,
+    history: BTreeMap<i64, TradeHistory>,
+    filter: Option<TradeFilter>,
+    consolidation: ConsolidationConfig,
+    history_config: HistoryConfig,
 }
 
 impl Bucket {
@@ -14,39 +52,445 @@ impl Bucket {
             symbol: symbol.to_string(),
             currency: currency.to_string(),
             data: BTreeMap::new(),
+            history: BTreeMap::new(),
+            filter: None,
+            consolidation: ConsolidationConfig::default(),
+            history_config: HistoryConfig::default(),
+        }
+    }
+
+    /// Creates a bucket that applies the update rules of trade conditions: trades counting
+    /// towards the volume (e.g. odd lots) go into the history, but only those allowed to set the
+    /// last price (e.g. not average price trades) replace the exchange's last trade.
+    pub fn with_filter(symbol: &str, currency: &str, filter: TradeFilter) -> Self {
+        Self {
+            filter: Some(filter),
+            ..Self::new(symbol, currency)
         }
     }
-    // add_trade, error out if symbol is different, the i64 is the exchange number
+
+    /// Creates a bucket for the symbol and currency of an instrument.
+    pub fn for_instrument(instrument: &Instrument) -> Self {
+        Self::new(&instrument.symbol(), instrument.currency())
+    }
+
+    /// Consolidates the prices of the bucket with `config` instead of the defaults.
+    pub fn with_consolidation(mut self, config: ConsolidationConfig) -> Self {
+        self.consolidation = config;
+        self
+    }
+
+    /// Keeps the trade history of each exchange as configured instead of the defaults.
+    pub fn with_history(mut self, config: HistoryConfig) -> Self {
+        self.history_config = config;
+        self
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Adds the trade to the history of its exchange and records it as the exchange's last,
+    /// unless the exchange already has a later one or the filter rules it out. Errors out if the
+    /// trade is for another symbol or currency.
     pub fn add_trade(&mut self, trade: Box<dyn Trade>) -> anyhow::Result<()> {
         let trade_data = trade.get_trade()?;
-        if trade_data.symbol != self.symbol && trade_data.currency != self.currency {
-            return Err(anyhow::Error::msg("Symbol mismatch"));
+        let instrument = &trade_data.instrument;
+        if instrument.symbol() != self.symbol || instrument.currency() != self.currency {
+            return Err(anyhow::Error::msg(format!(
+                "Symbol mismatch: {} {} in the bucket of {} {}",
+                instrument.symbol(),
+                instrument.currency(),
+                self.symbol,
+                self.currency
+            )));
         }
-        self.data.insert(trade_data.exchange_id, trade);
+        let (updates_volume, updates_last) = match &self.filter {
+            Some(filter) => (
+                filter.updates_volume(trade_data.conditions),
+                filter.updates_last(trade_data.conditions),
+            ),
+            None => (true, true),
+        };
+        if updates_volume {
+            let history_config = &self.history_config;
+            self.history
+                .entry(trade_data.exchange_id)
+                .or_insert_with(|| TradeHistory::new(history_config.clone()))
+                .push(TradePrint {
+                    price: trade_data.price,
+                    size: trade_data.size,
+                    timestamp: trade_data.timestamp,
+                    updates_last,
+                });
+        }
+        if !updates_last {
+            return Ok(());
+        }
+        if let Some(last) = self.data.get(&trade_data.exchange_id) {
+            // a late print does not replace a newer one
+            if last.timestamp > trade_data.timestamp {
+                return Ok(());
+            }
+        }
+        self.data.insert(
+            trade_data.exchange_id,
+            LastTrade {
+                exchange_id: trade_data.exchange_id,
+                asset_class: trade.asset_class(),
+                price: trade_data.price,
+                size: trade_data.size,
+                timestamp: trade_data.timestamp,
+            },
+        );
         Ok(())
     }
 
+    /// The last trade of an exchange.
+    pub fn last(&self, exchange_id: i64) -> Option<&LastTrade> {
+        self.data.get(&exchange_id)
+    }
+
+    /// The last trade of each exchange, by exchange ID.
+    pub fn trades(&self) -> impl Iterator<Item = &LastTrade> {
+        self.data.values()
+    }
+
+    /// The most recent trade across exchanges.
+    pub fn latest(&self) -> Option<&LastTrade> {
+        self.data.values().max_by_key(|trade| trade.timestamp)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The price consolidated across exchanges: median, mean, volume weighted, min and max,
+    /// leaving out stale exchanges and outliers. `None` if no exchange is left.
+    pub fn consolidated(&self) -> Option<ConsolidatedPrice> {
+        self.consolidated_at(Utc::now())
+    }
+
+    /// Like `consolidated`, judging staleness as of `now`, e.g. the time of a replayed frame.
+    pub fn consolidated_at(&self, now: DateTime<Utc>) -> Option<ConsolidatedPrice> {
+        consolidated::consolidate(self.data.values(), &self.consolidation, now)
+    }
+
+    /// The recent trades of an exchange.
+    pub fn history(&self, exchange_id: i64) -> Option<&TradeHistory> {
+        self.history.get(&exchange_id)
+    }
+
+    /// The price change, trade count, volume and volatility of an exchange over the last
+    /// `period`, e.g. one or five minutes. `None` if the exchange has no trades.
+    pub fn rolling_stats(&self, exchange_id: i64, period: Duration) -> Option<RollingStats> {
+        self.rolling_stats_at(exchange_id, period, Utc::now())
+    }
+
+    /// Like `rolling_stats`, for the period ending at `now`.
+    pub fn rolling_stats_at(
+        &self,
+        exchange_id: i64,
+        period: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<RollingStats> {
+        self.history(exchange_id)?.stats_at(period, now)
+    }
+
     // print the trades in order of exchange id, in a grid with prettytables-rs
     pub fn print_trades(&self) -> anyhow::Result<()> {
-        let mut table = prettytable::Table::new();
-        table.add_row(row![
-            "Exchange ID",
-            "Symbol",
-            "Currency",
-            "Price",
-            "Timestamp"
-        ]);
-        for (_, trade) in self.data.iter() {
-            let trade_data = trade.get_trade()?;
-            table.add_row(row![
-                trade_data.exchange_id,
-                trade_data.symbol,
-                trade_data.currency,
-                trade_data.price,
-                trade_data.timestamp
-            ]);
-        }
-        table.printstd();
+        render::table(self).printstd();
         Ok(())
     }
 }
+
+/// Buckets of several symbols, each trade routed to the bucket of its symbol and currency.
+#[derive(Default)]
+pub struct BucketSet {
+    buckets: BTreeMap<(String, String), Bucket>,
+    /// When set, trades of other symbols are ignored.
+    watchlist: Option<BTreeSet<(String, String)>>,
+    filter: Option<TradeFilter>,
+    consolidation: ConsolidationConfig,
+    history: HistoryConfig,
+}
+
+impl BucketSet {
+    /// Creates a set that opens a bucket for every symbol it receives trades of.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a set with a bucket for each of `instruments`, ignoring trades of other symbols.
+    pub fn watching<'a, I>(instruments: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Instrument>,
+    {
+        let mut set = Self::new();
+        let keys: BTreeSet<(String, String)> = instruments.into_iter().map(key).collect();
+        for (symbol, currency) in &keys {
+            set.buckets.insert(
+                (symbol.clone(), currency.clone()),
+                Bucket::new(symbol, currency),
+            );
+        }
+        set.watchlist = Some(keys);
+        set
+    }
+
+    /// Consolidates the prices of every bucket with `config`, see `Bucket::with_consolidation`.
+    pub fn with_consolidation(mut self, config: ConsolidationConfig) -> Self {
+        for bucket in self.buckets.values_mut() {
+            bucket.consolidation = config.clone();
+        }
+        self.consolidation = config;
+        self
+    }
+
+    /// Keeps the trade history of every bucket as configured, see `Bucket::with_history`.
+    pub fn with_history(mut self, config: HistoryConfig) -> Self {
+        for bucket in self.buckets.values_mut() {
+            bucket.history_config = config.clone();
+        }
+        self.history = config;
+        self
+    }
+
+    /// Applies `filter` to the trades of every bucket, see `Bucket::with_filter`.
+    pub fn with_filter(mut self, filter: TradeFilter) -> Self {
+        for bucket in self.buckets.values_mut() {
+            bucket.filter = Some(filter.clone());
+        }
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Adds the trade to the bucket of its symbol and currency, opening the bucket if needed.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the trade was routed to a bucket, `false` if its symbol is not watched.
+    pub fn add_trade(&mut self, trade: Box<dyn Trade>) -> anyhow::Result<bool> {
+        let key = key(&trade.get_trade()?.instrument);
+        if let Some(watchlist) = &self.watchlist {
+            if !watchlist.contains(&key) {
+                return Ok(false);
+            }
+        }
+        let (filter, consolidation, history) = (&self.filter, &self.consolidation, &self.history);
+        self.buckets
+            .entry(key)
+            .or_insert_with_key(|(symbol, currency)| {
+                match filter {
+                    Some(filter) => Bucket::with_filter(symbol, currency, filter.clone()),
+                    None => Bucket::new(symbol, currency),
+                }
+                .with_consolidation(consolidation.clone())
+                .with_history(history.clone())
+            })
+            .add_trade(trade)?;
+        Ok(true)
+    }
+
+    /// The bucket of an instrument's symbol and currency.
+    pub fn get(&self, instrument: &Instrument) -> Option<&Bucket> {
+        self.buckets.get(&key(instrument))
+    }
+
+    /// The bucket of a symbol and currency, e.g. `("BTC", "USD")`.
+    pub fn get_pair(&self, symbol: &str, currency: &str) -> Option<&Bucket> {
+        self.buckets
+            .get(&(symbol.to_string(), currency.to_string()))
+    }
+
+    /// The buckets, by symbol and currency.
+    pub fn buckets(&self) -> impl Iterator<Item = &Bucket> {
+        self.buckets.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Prints every bucket, one table per symbol.
+    pub fn print_trades(&self) -> anyhow::Result<()> {
+        self.render(&mut TableRenderer, &mut std::io::stdout())
+    }
+
+    /// Prints the buckets of `instruments`, skipping those the set has no bucket for.
+    pub fn print_instruments(&self, instruments: &[Instrument]) -> anyhow::Result<()> {
+        self.render_instruments(instruments, &mut TableRenderer, &mut std::io::stdout())
+    }
+
+    /// Writes every bucket to `out` with `renderer`.
+    pub fn render(&self, renderer: &mut dyn Renderer, out: &mut dyn Write) -> anyhow::Result<()> {
+        let buckets: Vec<&Bucket> = self.buckets.values().collect();
+        renderer.render(&buckets, out)?;
+        Ok(out.flush()?)
+    }
+
+    /// Writes the buckets of `instruments` to `out` with `renderer`, skipping those the set has
+    /// no bucket for.
+    pub fn render_instruments(
+        &self,
+        instruments: &[Instrument],
+        renderer: &mut dyn Renderer,
+        out: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let buckets: Vec<&Bucket> = instruments
+            .iter()
+            .filter_map(|instrument| self.get(instrument))
+            .collect();
+        renderer.render(&buckets, out)?;
+        Ok(out.flush()?)
+    }
+}
+
+/// Rounds a computed price to 8 decimals, hiding the floating point noise of averages.
+pub(crate) fn display_price(price: f64) -> String {
+    ((price * 1e8).round() / 1e8).to_string()
+}
+
+fn key(instrument: &Instrument) -> (String, String) {
+    (
+        instrument.symbol().into_owned(),
+        instrument.currency().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::stocks::TradeEvent;
+    use crate::websocket::symbol::Symbol;
+    use chrono::TimeZone;
+
+    const AVERAGE_PRICE: i32 = 2;
+    const FORM_T: i32 = 12;
+    const SOLD_OUT_OF_SEQUENCE: i32 = 32;
+    const ODD_LOT: i32 = 37;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap() + chrono::Duration::seconds(secs)
+    }
+
+    fn trade(price: f64, size: u64, conditions: &[i32], secs: i64) -> Box<dyn Trade> {
+        Box::new(TradeEvent {
+            event_type: Symbol::new("T"),
+            symbol: Symbol::new("AAPL"),
+            exchange_id: 4,
+            trade_id: secs.to_string(),
+            tape: 3,
+            price,
+            trade_size: size,
+            trade_conditions: conditions.to_vec(),
+            timestamp: at(secs),
+            sequence_number: secs,
+            trade_reporting_facility_id: None,
+            trf_timestamp: None,
+        })
+    }
+
+    fn counting_odd_lots() -> TradeFilter {
+        TradeFilter {
+            skip_odd_lots: false,
+            ..TradeFilter::stocks()
+        }
+    }
+
+    #[test]
+    fn counts_the_volume_of_prints_that_do_not_set_the_last_price() {
+        let mut bucket = Bucket::with_filter("AAPL", "USD", counting_odd_lots());
+        bucket.add_trade(trade(180.0, 100, &[], 0)).unwrap();
+        bucket.add_trade(trade(180.5, 30, &[ODD_LOT], 1)).unwrap();
+        bucket
+            .add_trade(trade(179.0, 500, &[AVERAGE_PRICE], 2))
+            .unwrap();
+        bucket.add_trade(trade(181.0, 200, &[FORM_T], 3)).unwrap();
+
+        let last = bucket.last(4).unwrap();
+        assert_eq!(last.price, 180.0);
+        assert_eq!(last.timestamp, at(0));
+
+        let stats = bucket
+            .rolling_stats_at(4, Duration::from_secs(60), at(3))
+            .unwrap();
+        assert_eq!(stats.trade_count, 4);
+        assert_eq!(stats.volume, 830.0);
+        // the prices only see the print that sets the last price
+        assert_eq!(stats.open, 180.0);
+        assert_eq!(stats.last, 180.0);
+        assert_eq!(stats.change(), 0.0);
+        assert_eq!(stats.volatility, None);
+    }
+
+    #[test]
+    fn keeps_the_last_price_before_the_window_as_the_open() {
+        let mut bucket = Bucket::with_filter("AAPL", "USD", counting_odd_lots())
+            .with_history(HistoryConfig::with_window(Duration::from_secs(60)));
+        bucket.add_trade(trade(180.0, 100, &[], 0)).unwrap();
+        bucket
+            .add_trade(trade(179.0, 500, &[AVERAGE_PRICE], 10))
+            .unwrap();
+        bucket.add_trade(trade(182.0, 100, &[], 90)).unwrap();
+
+        // the average price print fell out of the window, the opening price did not
+        assert_eq!(bucket.history(4).unwrap().len(), 2);
+        let stats = bucket
+            .rolling_stats_at(4, Duration::from_secs(60), at(90))
+            .unwrap();
+        assert_eq!(stats.open, 180.0);
+        assert_eq!(stats.last, 182.0);
+        assert_eq!(stats.trade_count, 1);
+        assert_eq!(stats.volume, 100.0);
+    }
+
+    #[test]
+    fn skipped_prints_count_for_nothing() {
+        let mut bucket = Bucket::with_filter("AAPL", "USD", TradeFilter::stocks());
+        bucket.add_trade(trade(180.0, 100, &[], 0)).unwrap();
+        bucket.add_trade(trade(180.5, 30, &[ODD_LOT], 1)).unwrap();
+        bucket
+            .add_trade(trade(178.0, 100, &[SOLD_OUT_OF_SEQUENCE], 2))
+            .unwrap();
+
+        assert_eq!(bucket.last(4).unwrap().price, 180.0);
+        let stats = bucket
+            .rolling_stats_at(4, Duration::from_secs(60), at(2))
+            .unwrap();
+        assert_eq!(stats.trade_count, 1);
+        assert_eq!(stats.volume, 100.0);
+    }
+
+    #[test]
+    fn volume_only_prints_alone_leave_no_last_trade() {
+        let mut bucket = Bucket::with_filter("AAPL", "USD", counting_odd_lots());
+        bucket.add_trade(trade(180.5, 30, &[ODD_LOT], 0)).unwrap();
+        assert!(bucket.is_empty());
+        assert_eq!(bucket.history(4).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn takes_every_trade_without_a_filter() {
+        let mut bucket = Bucket::new("AAPL", "USD");
+        bucket.add_trade(trade(180.0, 100, &[], 0)).unwrap();
+        bucket
+            .add_trade(trade(179.0, 500, &[AVERAGE_PRICE], 1))
+            .unwrap();
+        // a late print goes into the history only
+        bucket.add_trade(trade(178.0, 100, &[], -5)).unwrap();
+
+        assert_eq!(bucket.last(4).unwrap().price, 179.0);
+        assert_eq!(bucket.history(4).unwrap().len(), 3);
+        let mut other = Bucket::new("MSFT", "USD");
+        assert!(other.add_trade(trade(180.0, 100, &[], 0)).is_err());
+    }
+}
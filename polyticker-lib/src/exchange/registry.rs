@@ -0,0 +1,408 @@
+use crate::request::reference::exchanges::{ExchangeRecord, Exchanges};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+
+/// The asset classes served by Polygon. Exchange IDs are only unique within an asset class,
+/// e.g. `1` is Coinbase for crypto and NYSE American for stocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AssetClass {
+    Stocks,
+    Options,
+    Crypto,
+    Fx,
+    Indices,
+}
+
+impl AssetClass {
+    /// The name of the asset class in the REST API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetClass::Stocks => "stocks",
+            AssetClass::Options => "options",
+            AssetClass::Crypto => "crypto",
+            AssetClass::Fx => "fx",
+            AssetClass::Indices => "indices",
+        }
+    }
+
+    pub fn from_api(name: &str) -> Option<Self> {
+        match name {
+            "stocks" => Some(AssetClass::Stocks),
+            "options" => Some(AssetClass::Options),
+            "crypto" => Some(AssetClass::Crypto),
+            "fx" => Some(AssetClass::Fx),
+            "indices" => Some(AssetClass::Indices),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AssetClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The kind of venue behind an exchange ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExchangeType {
+    /// A lit exchange.
+    Exchange,
+    /// A trade reporting facility for off-exchange trades.
+    Trf,
+    /// A securities information processor, the consolidated feed.
+    Sip,
+    Other,
+}
+
+impl ExchangeType {
+    pub fn from_api(name: &str) -> Self {
+        match name {
+            "exchange" => ExchangeType::Exchange,
+            "TRF" => ExchangeType::Trf,
+            "SIP" => ExchangeType::Sip,
+            _ => ExchangeType::Other,
+        }
+    }
+}
+
+/// An exchange, trade reporting facility or SIP.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExchangeInfo {
+    pub id: i64,
+    pub name: String,
+    /// The Market Identifier Code (ISO 10383), if the venue has one.
+    pub mic: Option<String>,
+    pub exchange_type: ExchangeType,
+    pub asset_class: AssetClass,
+}
+
+impl ExchangeInfo {
+    fn new(
+        asset_class: AssetClass,
+        id: i64,
+        name: &str,
+        mic: Option<&str>,
+        exchange_type: ExchangeType,
+    ) -> Self {
+        ExchangeInfo {
+            id,
+            name: name.to_string(),
+            mic: mic.map(str::to_string),
+            exchange_type,
+            asset_class,
+        }
+    }
+}
+
+/// The built-in venues: asset class, ID, name, MIC and type. Polygon lists more venues (and
+/// may renumber them), `ExchangeRegistry::refresh` loads the current list.
+const EXCHANGES: &[(AssetClass, i64, &str, Option<&str>, ExchangeType)] = {
+    use AssetClass::*;
+    use ExchangeType::*;
+    &[
+        (Stocks, 1, "NYSE American", Some("XASE"), Exchange),
+        (Stocks, 2, "Nasdaq BX", Some("XBOS"), Exchange),
+        (Stocks, 3, "NYSE National", Some("XCIS"), Exchange),
+        (
+            Stocks,
+            4,
+            "FINRA Alternative Display Facility",
+            Some("XADF"),
+            Trf,
+        ),
+        (Stocks, 5, "Unlisted Trading Privileges", None, Sip),
+        (
+            Stocks,
+            6,
+            "International Securities Exchange",
+            Some("XISE"),
+            Exchange,
+        ),
+        (Stocks, 7, "Cboe EDGA", Some("EDGA"), Exchange),
+        (Stocks, 8, "Cboe EDGX", Some("EDGX"), Exchange),
+        (Stocks, 9, "NYSE Chicago", Some("XCHI"), Exchange),
+        (
+            Stocks,
+            10,
+            "New York Stock Exchange",
+            Some("XNYS"),
+            Exchange,
+        ),
+        (Stocks, 11, "NYSE Arca", Some("ARCX"), Exchange),
+        (Stocks, 12, "Nasdaq", Some("XNAS"), Exchange),
+        (Stocks, 13, "Consolidated Tape Association", None, Sip),
+        (
+            Stocks,
+            14,
+            "Long-Term Stock Exchange",
+            Some("LTSE"),
+            Exchange,
+        ),
+        (Stocks, 15, "Investors Exchange", Some("IEXG"), Exchange),
+        (Stocks, 16, "Cboe Stock Exchange", Some("CBSX"), Exchange),
+        (
+            Stocks,
+            17,
+            "Nasdaq Philadelphia Exchange",
+            Some("XPHL"),
+            Exchange,
+        ),
+        (Stocks, 18, "Cboe BYX", Some("BATY"), Exchange),
+        (Stocks, 19, "Cboe BZX", Some("BATS"), Exchange),
+        (Stocks, 20, "MIAX Pearl", Some("EPRL"), Exchange),
+        (Stocks, 21, "Members Exchange", Some("MEMX"), Exchange),
+        (Stocks, 62, "OTC Equity Security", Some("OOTC"), Exchange),
+        (Stocks, 201, "FINRA NYSE TRF", Some("FINN"), Trf),
+        (Stocks, 202, "FINRA Nasdaq TRF Carteret", Some("FINC"), Trf),
+        (Stocks, 203, "FINRA Nasdaq TRF Chicago", None, Trf),
+        (
+            Options,
+            300,
+            "NYSE American Options",
+            Some("AMXO"),
+            Exchange,
+        ),
+        (Options, 301, "Nasdaq BX Options", Some("XBXO"), Exchange),
+        (Options, 302, "Cboe Options", Some("XCBO"), Exchange),
+        (Options, 303, "MIAX Emerald", Some("EMLD"), Exchange),
+        (Options, 304, "Cboe EDGX Options", Some("EDGO"), Exchange),
+        (Options, 305, "Nasdaq GEMX", Some("GMNI"), Exchange),
+        (Options, 306, "Nasdaq ISE", Some("XISX"), Exchange),
+        (Options, 307, "Nasdaq MRX", Some("MCRY"), Exchange),
+        (Options, 308, "MIAX Options", Some("XMIO"), Exchange),
+        (Options, 309, "NYSE Arca Options", Some("ARCO"), Exchange),
+        (
+            Options,
+            310,
+            "Options Price Reporting Authority",
+            Some("OPRA"),
+            Sip,
+        ),
+        (Options, 311, "MIAX Pearl Options", Some("MPRL"), Exchange),
+        (
+            Options,
+            312,
+            "Nasdaq Options Market",
+            Some("XNDQ"),
+            Exchange,
+        ),
+        (Options, 313, "Nasdaq PHLX", Some("XPHL"), Exchange),
+        (Options, 314, "Cboe C2 Options", Some("C2OX"), Exchange),
+        (Options, 315, "Cboe BZX Options", Some("BATO"), Exchange),
+        (Options, 316, "MEMX Options", Some("MXOP"), Exchange),
+        (Crypto, 1, "Coinbase", None, Exchange),
+        (Crypto, 2, "Bitfinex", None, Exchange),
+        (Crypto, 6, "Bitstamp", None, Exchange),
+        (Crypto, 23, "Kraken", None, Exchange),
+        (Fx, 48, "Currency Banks 1", None, Exchange),
+    ]
+};
+
+/// Names, MICs and types of the venues behind exchange IDs, per asset class.
+#[derive(Clone, Debug, Default)]
+pub struct ExchangeRegistry {
+    exchanges: HashMap<(AssetClass, i64), ExchangeInfo>,
+}
+
+impl ExchangeRegistry {
+    /// The registry of the built-in venues.
+    pub fn builtin() -> Self {
+        let mut registry = ExchangeRegistry::default();
+        for (asset_class, id, name, mic, exchange_type) in EXCHANGES {
+            registry.insert(ExchangeInfo::new(
+                *asset_class,
+                *id,
+                name,
+                *mic,
+                *exchange_type,
+            ));
+        }
+        registry
+    }
+
+    pub fn insert(&mut self, exchange: ExchangeInfo) {
+        self.exchanges
+            .insert((exchange.asset_class, exchange.id), exchange);
+    }
+
+    /// Adds or replaces the venues of `records`, skipping those of asset classes not modelled
+    /// by `AssetClass`.
+    pub fn extend<I: IntoIterator<Item = ExchangeRecord>>(&mut self, records: I) {
+        for record in records {
+            let Some(asset_class) = AssetClass::from_api(&record.asset_class) else {
+                continue;
+            };
+            self.insert(ExchangeInfo {
+                id: record.id,
+                name: record.name,
+                mic: record.mic,
+                exchange_type: ExchangeType::from_api(&record.exchange_type),
+                asset_class,
+            });
+        }
+    }
+
+    /// Loads the venues of `asset_class` (or of all asset classes) from Polygon, replacing the
+    /// entries with the same IDs.
+    ///
+    /// # Returns
+    ///
+    /// The number of venues loaded, or the error of the request.
+    pub async fn refresh(
+        &mut self,
+        client: &Exchanges,
+        asset_class: Option<AssetClass>,
+    ) -> Result<usize, reqwest::Error> {
+        let response = client
+            .get_exchanges(asset_class.as_ref().map(AssetClass::as_str))
+            .await?;
+        let loaded = response.results.len();
+        self.extend(response.results);
+        Ok(loaded)
+    }
+
+    pub fn get(&self, asset_class: AssetClass, id: i64) -> Option<&ExchangeInfo> {
+        self.exchanges.get(&(asset_class, id))
+    }
+
+    /// The name of the venue, or its ID if the venue is unknown.
+    pub fn name(&self, asset_class: AssetClass, id: i64) -> String {
+        self.get(asset_class, id)
+            .map_or_else(|| id.to_string(), |exchange| exchange.name.clone())
+    }
+
+    /// Finds a venue by its MIC.
+    pub fn by_mic(&self, mic: &str) -> Option<&ExchangeInfo> {
+        self.exchanges
+            .values()
+            .find(|exchange| exchange.mic.as_deref() == Some(mic))
+    }
+
+    /// The venues of an asset class, by ID.
+    pub fn exchanges(&self, asset_class: AssetClass) -> Vec<&ExchangeInfo> {
+        let mut exchanges: Vec<&ExchangeInfo> = self
+            .exchanges
+            .values()
+            .filter(|exchange| exchange.asset_class == asset_class)
+            .collect();
+        exchanges.sort_by_key(|exchange| exchange.id);
+        exchanges
+    }
+}
+
+fn global_registry() -> &'static RwLock<ExchangeRegistry> {
+    static GLOBAL: OnceLock<RwLock<ExchangeRegistry>> = OnceLock::new();
+    GLOBAL.get_or_init(|| RwLock::new(ExchangeRegistry::builtin()))
+}
+
+/// The registry used to name venues in `Display` impls and `Bucket` output. It starts as the
+/// built-in registry.
+pub fn global() -> RwLockReadGuard<'static, ExchangeRegistry> {
+    global_registry().read().unwrap()
+}
+
+/// Replaces the global registry, e.g. with one refreshed from Polygon.
+pub fn install(registry: ExchangeRegistry) {
+    *global_registry().write().unwrap() = registry;
+}
+
+/// The name of a venue in the global registry, or its ID if the venue is unknown.
+pub fn exchange_name(asset_class: AssetClass, id: i64) -> String {
+    global().name(asset_class, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(asset_class: &str, id: i64, name: &str, mic: Option<&str>) -> ExchangeRecord {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": name,
+            "mic": mic,
+            "type": "exchange",
+            "asset_class": asset_class,
+            "locale": "us",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn looks_ids_up_per_asset_class() {
+        let registry = ExchangeRegistry::builtin();
+        assert_eq!(registry.name(AssetClass::Crypto, 1), "Coinbase");
+        assert_eq!(registry.name(AssetClass::Stocks, 1), "NYSE American");
+        assert!(registry.get(AssetClass::Options, 1).is_none());
+
+        let nyse = registry.get(AssetClass::Stocks, 10).unwrap();
+        assert_eq!(nyse.name, "New York Stock Exchange");
+        assert_eq!(nyse.mic.as_deref(), Some("XNYS"));
+        assert_eq!(nyse.exchange_type, ExchangeType::Exchange);
+        assert_eq!(
+            registry.get(AssetClass::Stocks, 4).unwrap().exchange_type,
+            ExchangeType::Trf
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_id_of_unknown_venues() {
+        let registry = ExchangeRegistry::builtin();
+        assert_eq!(registry.name(AssetClass::Crypto, 999), "999");
+        assert_eq!(registry.name(AssetClass::Indices, 1), "1");
+        assert_eq!(
+            ExchangeRegistry::default().name(AssetClass::Stocks, 10),
+            "10"
+        );
+    }
+
+    #[test]
+    fn finds_venues_by_mic() {
+        let registry = ExchangeRegistry::builtin();
+        let nasdaq = registry.by_mic("XNAS").unwrap();
+        assert_eq!((nasdaq.asset_class, nasdaq.id), (AssetClass::Stocks, 12));
+        let opra = registry.by_mic("OPRA").unwrap();
+        assert_eq!((opra.asset_class, opra.id), (AssetClass::Options, 310));
+        assert!(registry.by_mic("XLON").is_none());
+    }
+
+    #[test]
+    fn lists_the_venues_of_an_asset_class_by_id() {
+        let registry = ExchangeRegistry::builtin();
+        let ids: Vec<i64> = registry
+            .exchanges(AssetClass::Crypto)
+            .iter()
+            .map(|exchange| exchange.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 6, 23]);
+        assert!(registry.exchanges(AssetClass::Indices).is_empty());
+    }
+
+    #[test]
+    fn merges_records_over_the_builtin_venues() {
+        let mut registry = ExchangeRegistry::builtin();
+        let builtin_stocks = registry.exchanges(AssetClass::Stocks).len();
+        registry.extend([
+            // renamed
+            record("crypto", 1, "Coinbase Advanced", None),
+            // new
+            record("stocks", 22, "24X National Exchange", Some("24EX")),
+            // not modelled, skipped
+            record("futures", 1, "CME", Some("XCME")),
+        ]);
+
+        assert_eq!(registry.name(AssetClass::Crypto, 1), "Coinbase Advanced");
+        // the same ID of another asset class is untouched
+        assert_eq!(registry.name(AssetClass::Stocks, 1), "NYSE American");
+        assert_eq!(
+            registry.exchanges(AssetClass::Stocks).len(),
+            builtin_stocks + 1
+        );
+        assert_eq!(registry.by_mic("24EX").unwrap().id, 22);
+        assert!(registry.by_mic("XCME").is_none());
+        // other built-in venues are kept
+        assert_eq!(registry.name(AssetClass::Crypto, 23), "Kraken");
+    }
+}
@@ -0,0 +1,503 @@
+use crate::exchange::consolidated::ConsolidatedPrice;
+use crate::exchange::history::RollingStats;
+use crate::exchange::{display_price, Bucket};
+use chrono::{DateTime, Utc};
+use prettytable::row;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The periods of the rolling statistics shown per exchange: one and five minutes.
+const ROLLING_PERIODS: [Duration; 2] = [Duration::from_secs(60), Duration::from_secs(300)];
+
+/// Writes the state of buckets in some format, e.g. to stdout, a file or a socket.
+pub trait Renderer {
+    /// Writes one snapshot of `buckets` to `out`. Called on every refresh; renderers with a
+    /// preamble, like the CSV header, write it on the first call only.
+    fn render(&mut self, buckets: &[&Bucket], out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// The state of a bucket at one point in time, as written by the JSON renderers.
+#[derive(Clone, Debug, Serialize)]
+pub struct BucketSnapshot {
+    pub symbol: String,
+    pub currency: String,
+    /// When the snapshot was taken.
+    pub at: DateTime<Utc>,
+    /// The last trade of each exchange, by exchange ID.
+    pub exchanges: Vec<ExchangeSnapshot>,
+    pub consolidated: Option<ConsolidatedPrice>,
+}
+
+/// The last trade of an exchange in a `BucketSnapshot`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ExchangeSnapshot {
+    pub exchange_id: i64,
+    /// The name of the exchange in the global registry.
+    pub exchange: String,
+    pub price: f64,
+    pub size: f64,
+    pub timestamp: DateTime<Utc>,
+    /// The statistics of the exchange's trades over the last one and five minutes.
+    pub rolling: Vec<RollingStats>,
+}
+
+impl BucketSnapshot {
+    pub fn of(bucket: &Bucket) -> Self {
+        let at = Utc::now();
+        BucketSnapshot {
+            symbol: bucket.symbol().to_string(),
+            currency: bucket.currency().to_string(),
+            at,
+            exchanges: bucket
+                .trades()
+                .map(|trade| ExchangeSnapshot {
+                    exchange_id: trade.exchange_id,
+                    exchange: trade.exchange_name(),
+                    price: trade.price,
+                    size: trade.size,
+                    timestamp: trade.timestamp,
+                    rolling: ROLLING_PERIODS
+                        .iter()
+                        .filter_map(|period| {
+                            bucket.rolling_stats_at(trade.exchange_id, *period, at)
+                        })
+                        .collect(),
+                })
+                .collect(),
+            consolidated: bucket.consolidated_at(at),
+        }
+    }
+}
+
+/// The output formats of the built-in renderers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderFormat {
+    Table,
+    Json,
+    Ndjson,
+    Csv,
+    Ticker,
+}
+
+impl RenderFormat {
+    /// Creates a renderer for the format.
+    pub fn renderer(&self) -> Box<dyn Renderer + Send> {
+        match self {
+            RenderFormat::Table => Box::new(TableRenderer),
+            RenderFormat::Json => Box::new(JsonRenderer::default()),
+            RenderFormat::Ndjson => Box::new(NdjsonRenderer),
+            RenderFormat::Csv => Box::new(CsvRenderer::default()),
+            RenderFormat::Ticker => Box::new(TickerRenderer),
+        }
+    }
+}
+
+impl FromStr for RenderFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "table" => Ok(RenderFormat::Table),
+            "json" => Ok(RenderFormat::Json),
+            "ndjson" => Ok(RenderFormat::Ndjson),
+            "csv" => Ok(RenderFormat::Csv),
+            "ticker" => Ok(RenderFormat::Ticker),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unknown format: {} (expected table, json, ndjson, csv or ticker)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for RenderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RenderFormat::Table => "table",
+            RenderFormat::Json => "json",
+            RenderFormat::Ndjson => "ndjson",
+            RenderFormat::Csv => "csv",
+            RenderFormat::Ticker => "ticker",
+        })
+    }
+}
+
+/// A grid per bucket with the last trade of each exchange and the consolidated price, preceded
+/// by the symbol and currency.
+pub struct TableRenderer;
+
+impl Renderer for TableRenderer {
+    fn render(&mut self, buckets: &[&Bucket], out: &mut dyn Write) -> io::Result<()> {
+        for bucket in buckets {
+            writeln!(out, "{} {}", bucket.symbol(), bucket.currency())?;
+            table(bucket).print(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON array of `BucketSnapshot`s per render.
+#[derive(Default)]
+pub struct JsonRenderer {
+    /// Indents the JSON over several lines.
+    pub pretty: bool,
+}
+
+impl Renderer for JsonRenderer {
+    fn render(&mut self, buckets: &[&Bucket], out: &mut dyn Write) -> io::Result<()> {
+        let snapshots: Vec<BucketSnapshot> = buckets
+            .iter()
+            .map(|bucket| BucketSnapshot::of(bucket))
+            .collect();
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut *out, &snapshots)?;
+        } else {
+            serde_json::to_writer(&mut *out, &snapshots)?;
+        }
+        writeln!(out)
+    }
+}
+
+/// One `BucketSnapshot` per line.
+pub struct NdjsonRenderer;
+
+impl Renderer for NdjsonRenderer {
+    fn render(&mut self, buckets: &[&Bucket], out: &mut dyn Write) -> io::Result<()> {
+        for bucket in buckets {
+            serde_json::to_writer(&mut *out, &BucketSnapshot::of(bucket))?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// One row per exchange of each bucket, with a header before the first render.
+#[derive(Default)]
+pub struct CsvRenderer {
+    header_written: bool,
+}
+
+impl Renderer for CsvRenderer {
+    fn render(&mut self, buckets: &[&Bucket], out: &mut dyn Write) -> io::Result<()> {
+        if !self.header_written {
+            writeln!(
+                out,
+                "at,symbol,currency,exchange_id,exchange,price,size,timestamp,\
+                 change_1m_bps,change_5m_bps,trades_5m,volume_5m,volatility_5m_bps"
+            )?;
+            self.header_written = true;
+        }
+        let now = Utc::now();
+        let at = now.to_rfc3339();
+        for bucket in buckets {
+            for trade in bucket.trades() {
+                let [minute, five_minutes] = ROLLING_PERIODS
+                    .map(|period| bucket.rolling_stats_at(trade.exchange_id, period, now));
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    at,
+                    csv_field(bucket.symbol()),
+                    csv_field(bucket.currency()),
+                    trade.exchange_id,
+                    csv_field(&trade.exchange_name()),
+                    trade.price,
+                    trade.size,
+                    trade.timestamp.to_rfc3339(),
+                    optional(minute.as_ref().map(RollingStats::change_bps)),
+                    optional(five_minutes.as_ref().map(RollingStats::change_bps)),
+                    optional(five_minutes.as_ref().map(|stats| stats.trade_count)),
+                    optional(five_minutes.as_ref().map(|stats| stats.volume)),
+                    optional(five_minutes.as_ref().and_then(volatility_bps))
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats a value that may be missing as an empty field.
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+fn volatility_bps(stats: &RollingStats) -> Option<f64> {
+    stats.volatility.map(|volatility| volatility * 10_000.0)
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A single line per bucket: the consolidated price, then the last price of each exchange,
+/// e.g. `BTC/USD 26717.3 (3 exchanges, 4.1 bps) Coinbase 26717.31 | Kraken 26718.4`.
+pub struct TickerRenderer;
+
+impl Renderer for TickerRenderer {
+    fn render(&mut self, buckets: &[&Bucket], out: &mut dyn Write) -> io::Result<()> {
+        for bucket in buckets {
+            write!(out, "{}/{}", bucket.symbol(), bucket.currency())?;
+            match bucket.consolidated() {
+                Some(price) => write!(
+                    out,
+                    " {} ({} exchanges, {:.1} bps)",
+                    display_price(price.median),
+                    price.exchanges.len(),
+                    price.spread_bps()
+                )?,
+                None => write!(out, " -")?,
+            }
+            let exchanges: Vec<String> = bucket
+                .trades()
+                .map(|trade| format!("{} {}", trade.exchange_name(), trade.price))
+                .collect();
+            writeln!(out, " {}", exchanges.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The grid of a bucket: the last trade of each exchange in order of exchange ID, then the
+/// consolidated price.
+pub(crate) fn table(bucket: &Bucket) -> prettytable::Table {
+    let mut table = prettytable::Table::new();
+    table.add_row(row![
+        "Exchange ID",
+        "Exchange",
+        "Symbol",
+        "Currency",
+        "Price",
+        "Size",
+        "Timestamp",
+        "1m bps",
+        "5m bps",
+        "5m trades",
+        "5m vol bps"
+    ]);
+    let now = Utc::now();
+    for trade in bucket.trades() {
+        let [minute, five_minutes] =
+            ROLLING_PERIODS.map(|period| bucket.rolling_stats_at(trade.exchange_id, period, now));
+        let rounded =
+            |bps: Option<f64>| bps.map_or_else(|| "-".to_string(), |bps| format!("{:.1}", bps));
+        table.add_row(row![
+            trade.exchange_id,
+            trade.exchange_name(),
+            bucket.symbol(),
+            bucket.currency(),
+            trade.price,
+            trade.size,
+            trade.timestamp,
+            rounded(minute.as_ref().map(RollingStats::change_bps)),
+            rounded(five_minutes.as_ref().map(RollingStats::change_bps)),
+            optional(five_minutes.as_ref().map(|stats| stats.trade_count)),
+            rounded(five_minutes.as_ref().and_then(volatility_bps))
+        ]);
+    }
+    if let Some(price) = bucket.consolidated_at(now) {
+        let excluded = format!(
+            "{} of {} exchanges ({} stale, {} outliers)",
+            price.exchanges.len(),
+            bucket.trades().count(),
+            price.stale.len(),
+            price.outliers.len()
+        );
+        let volume_weighted = price
+            .volume_weighted
+            .map_or_else(|| "-".to_string(), display_price);
+        let spread = format!(
+            "{:.1} bps ({} to {})",
+            price.spread_bps(),
+            price.min,
+            price.max
+        );
+        let rows = [
+            ("Median", display_price(price.median), excluded),
+            ("Mean", display_price(price.mean), String::new()),
+            ("Volume weighted", volume_weighted, String::new()),
+            ("Spread", display_price(price.spread()), spread),
+        ];
+        for (metric, value, note) in rows {
+            table.add_row(row![
+                "",
+                metric,
+                bucket.symbol(),
+                bucket.currency(),
+                value,
+                "",
+                note,
+                "",
+                "",
+                "",
+                ""
+            ]);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::registry::{self, AssetClass, ExchangeInfo, ExchangeType};
+    use crate::websocket::crypto::CryptoTradeEvent;
+    use crate::websocket::symbol::Symbol;
+    use serde_json::Value;
+
+    /// A bucket with a trade of each `(exchange_id, price)`, just now.
+    fn bucket(symbol: &str, trades: &[(i64, f64)]) -> Bucket {
+        let mut bucket = Bucket::new(symbol, "USD");
+        let now = Utc::now();
+        for (exchange_id, price) in trades {
+            bucket
+                .add_trade(Box::new(CryptoTradeEvent {
+                    event_type: Symbol::new("XT"),
+                    pair: Symbol::new(&format!("{}-USD", symbol)),
+                    price: *price,
+                    timestamp: now,
+                    size: 0.5,
+                    conditions: Vec::new(),
+                    trade_id: exchange_id.to_string(),
+                    exchange_id: *exchange_id,
+                    received_timestamp: now,
+                }))
+                .unwrap();
+        }
+        bucket
+    }
+
+    fn render(renderer: &mut dyn Renderer, buckets: &[&Bucket]) -> String {
+        let mut out = Vec::new();
+        renderer.render(buckets, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_the_csv_header_once() {
+        let btc = bucket("BTC", &[(1, 26717.3), (23, 26718.4)]);
+        let eth = bucket("ETH", &[(1, 1650.2)]);
+        let mut renderer = CsvRenderer::default();
+        let first = render(&mut renderer, &[&btc, &eth]);
+        let second = render(&mut renderer, &[&btc, &eth]);
+
+        let lines: Vec<&str> = first.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("at,symbol,currency,exchange_id,"));
+        let header_fields = lines[0].split(',').count();
+        for row in &lines[1..] {
+            assert_eq!(row.split(',').count(), header_fields, "{}", row);
+        }
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(
+            &fields[1..7],
+            ["BTC", "USD", "1", "Coinbase", "26717.3", "0.5"]
+        );
+        assert_eq!(fields[10], "1");
+
+        assert_eq!(second.lines().count(), 3);
+        assert!(!second.contains("symbol"));
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("Coinbase"), "Coinbase");
+        assert_eq!(csv_field("Acme, Inc"), "\"Acme, Inc\"");
+        assert_eq!(csv_field("The \"Spot\""), "\"The \"\"Spot\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+
+        let mut exchanges = registry::global().clone();
+        exchanges.insert(ExchangeInfo {
+            id: 9901,
+            name: "Acme, \"Spot\"".to_string(),
+            mic: None,
+            exchange_type: ExchangeType::Exchange,
+            asset_class: AssetClass::Crypto,
+        });
+        registry::install(exchanges);
+        let btc = bucket("BTC", &[(9901, 26717.3)]);
+        let csv = render(&mut CsvRenderer::default(), &[&btc]);
+        let row = csv.lines().nth(1).unwrap();
+        assert!(
+            row.contains(",9901,\"Acme, \"\"Spot\"\"\",26717.3,"),
+            "{}",
+            row
+        );
+    }
+
+    #[test]
+    fn writes_a_json_array_per_render() {
+        let btc = bucket("BTC", &[(1, 26717.3), (23, 26718.4)]);
+        let eth = bucket("ETH", &[(1, 1650.2)]);
+        let json = render(&mut JsonRenderer::default(), &[&btc, &eth]);
+
+        assert_eq!(json.lines().count(), 1);
+        let snapshots: Value = serde_json::from_str(&json).unwrap();
+        let snapshots = snapshots.as_array().unwrap();
+        assert_eq!(snapshots.len(), 2);
+        let btc = &snapshots[0];
+        assert_eq!(btc["symbol"], "BTC");
+        assert_eq!(btc["currency"], "USD");
+        assert!(btc["at"].is_string());
+        let exchanges = btc["exchanges"].as_array().unwrap();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0]["exchange_id"], 1);
+        assert_eq!(exchanges[0]["exchange"], "Coinbase");
+        assert_eq!(exchanges[0]["price"], 26717.3);
+        assert_eq!(exchanges[0]["size"], 0.5);
+        let rolling = exchanges[0]["rolling"].as_array().unwrap();
+        assert_eq!(rolling.len(), 2);
+        assert_eq!(rolling[0]["period_secs"], 60.0);
+        assert_eq!(rolling[1]["period_secs"], 300.0);
+        assert_eq!(rolling[1]["trade_count"], 1);
+        assert_eq!(btc["consolidated"]["exchanges"], serde_json::json!([1, 23]));
+        assert!(btc["consolidated"]["median"].is_f64());
+
+        let pretty = render(&mut JsonRenderer { pretty: true }, &[&eth]);
+        assert!(pretty.lines().count() > 1);
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap()[0]["symbol"],
+            "ETH"
+        );
+    }
+
+    #[test]
+    fn writes_a_snapshot_per_line_as_ndjson() {
+        let btc = bucket("BTC", &[(1, 26717.3)]);
+        let eth = bucket("ETH", &[(1, 1650.2), (2, 1650.4)]);
+        let empty = Bucket::new("SOL", "USD");
+        let ndjson = render(&mut NdjsonRenderer, &[&btc, &eth, &empty]);
+
+        let snapshots: Vec<Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(snapshots.len(), 3);
+        assert!(snapshots.iter().all(Value::is_object));
+        assert_eq!(snapshots[1]["symbol"], "ETH");
+        assert_eq!(snapshots[1]["exchanges"].as_array().unwrap().len(), 2);
+        assert_eq!(snapshots[2]["exchanges"], serde_json::json!([]));
+        assert!(snapshots[2]["consolidated"].is_null());
+    }
+
+    #[test]
+    fn parses_the_formats_it_displays() {
+        for format in [
+            RenderFormat::Table,
+            RenderFormat::Json,
+            RenderFormat::Ndjson,
+            RenderFormat::Csv,
+            RenderFormat::Ticker,
+        ] {
+            assert_eq!(format.to_string().parse::<RenderFormat>().unwrap(), format);
+        }
+        assert!("xml".parse::<RenderFormat>().is_err());
+    }
+}
@@ -4,7 +4,3 @@ pub mod request;
 pub mod util;
 pub mod websocket;
 
-use futures::{SinkExt, StreamExt};
-use serde_json::Value;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::protocol::Message;
@@ -0,0 +1,471 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// The largest request head accepted, requests are plain GETs without a body.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// A canned HTTP response of a `MockRestServer`.
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    pub status: u16,
+    pub body: String,
+    /// Additional headers, e.g. `Retry-After`.
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockResponse {
+    /// A `200 OK` response with a JSON body.
+    pub fn json(body: &Value) -> Self {
+        Self::with_status(200, body.to_string())
+    }
+
+    /// A response with any status and body.
+    pub fn with_status<S: Into<String>>(status: u16, body: S) -> Self {
+        MockResponse {
+            status,
+            body: body.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// The `429 Too Many Requests` response Polygon sends when the rate limit is exceeded.
+    pub fn rate_limited() -> Self {
+        let mut response = Self::with_status(
+            429,
+            json!({
+                "status": "ERROR",
+                "request_id": request_id(),
+                "error": "You've exceeded the maximum requests per minute, please wait or upgrade your subscription to continue.",
+            })
+            .to_string(),
+        );
+        response
+            .headers
+            .push(("Retry-After".to_string(), "1".to_string()));
+        response
+    }
+
+    /// The `401 Unauthorized` response Polygon sends for a missing or unknown API key.
+    pub fn unauthorized() -> Self {
+        Self::with_status(
+            401,
+            json!({
+                "status": "ERROR",
+                "request_id": request_id(),
+                "error": "Unknown API Key",
+            })
+            .to_string(),
+        )
+    }
+
+    /// The `404 Not Found` response for unknown paths.
+    pub fn not_found() -> Self {
+        Self::with_status(
+            404,
+            json!({
+                "status": "NOT_FOUND",
+                "request_id": request_id(),
+                "message": "The requested resource was not found",
+            })
+            .to_string(),
+        )
+    }
+
+    /// A `200 OK` response whose body is cut off in the middle of the JSON.
+    pub fn malformed() -> Self {
+        Self::with_status(
+            200,
+            r#"{"status":"OK","request_id":"a1b2","results":[{"c":1"#,
+        )
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            429 => "Too Many Requests",
+            500 => "Internal Server Error",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// A request received by a `MockRestServer`.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// The decoded query parameters, in order.
+    pub query: Vec<(String, String)>,
+    /// The value of the `Authorization` header, if any.
+    pub authorization: Option<String>,
+}
+
+impl RecordedRequest {
+    /// The value of a query parameter.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The responses for a path and, optionally, query parameters it must carry.
+struct Route {
+    path: String,
+    query: Vec<(String, String)>,
+    /// Served in order, the last one is repeated.
+    responses: VecDeque<MockResponse>,
+}
+
+impl Route {
+    fn matches(&self, request: &RecordedRequest) -> bool {
+        self.path == request.path
+            && self
+                .query
+                .iter()
+                .all(|(name, value)| request.query_param(name) == Some(value.as_str()))
+    }
+
+    fn next_response(&mut self) -> MockResponse {
+        if self.responses.len() > 1 {
+            self.responses.pop_front().unwrap()
+        } else {
+            self.responses
+                .front()
+                .cloned()
+                .unwrap_or_else(MockResponse::not_found)
+        }
+    }
+}
+
+struct ServerState {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>,
+    api_key: Option<String>,
+}
+
+/// A local HTTP server standing in for the Polygon REST API, for testing offline.
+///
+/// Responses are registered per path with `mock`, optionally restricted to requests carrying
+/// certain query parameters (`"/v3/trades/AAPL?cursor=2"`). Several responses for the same route
+/// are served in order and the last one is repeated, so a `429` followed by a success tests a
+/// retry. Requests for unknown paths get a `404`, and if an API key is required, requests without
+/// it get a `401`. Point a client at the server with `with_base_url(api_key, server.url())`.
+///
+/// Dropping the server stops it.
+pub struct MockRestServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+    task: JoinHandle<()>,
+}
+
+impl MockRestServer {
+    /// Starts a server on a free local port accepting any API key.
+    pub async fn start() -> io::Result<Self> {
+        Self::start_with_api_key(None).await
+    }
+
+    /// Starts a server answering requests not authorized with `Bearer <api_key>` with a `401`.
+    pub async fn start_with_api_key(api_key: Option<String>) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ServerState {
+            routes: Vec::new(),
+            requests: Vec::new(),
+            api_key,
+        }));
+        let accept_state = state.clone();
+        let task = tokio::task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::task::spawn(serve(stream, accept_state.clone()));
+            }
+        });
+        Ok(MockRestServer { addr, state, task })
+    }
+
+    /// The base URL to use in place of `https://api.polygon.io`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Serves `response` for `path`, after any responses registered for it before.
+    ///
+    /// The path may carry query parameters, e.g. `/v3/trades/AAPL?cursor=2`, which the request
+    /// must then include; other query parameters of the request are ignored.
+    pub fn mock(&self, path: &str, response: MockResponse) {
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, parse_query(query)),
+            None => (path, Vec::new()),
+        };
+        let mut state = self.state.lock().unwrap();
+        match state
+            .routes
+            .iter_mut()
+            .find(|route| route.path == path && route.query == query)
+        {
+            Some(route) => route.responses.push_back(response),
+            None => {
+                state.routes.push(Route {
+                    path: path.to_string(),
+                    query,
+                    responses: VecDeque::from([response]),
+                });
+                // the most specific routes are matched first
+                state
+                    .routes
+                    .sort_by_key(|route| std::cmp::Reverse(route.query.len()));
+            }
+        }
+    }
+
+    /// Serves `pages` as a chain of responses linked by `next_url`, like Polygon's paginated v3
+    /// endpoints. Every page is a JSON object; the `next_url` of all but the last is set.
+    pub fn mock_paginated(&self, path: &str, pages: Vec<Value>) {
+        let count = pages.len();
+        for (index, mut page) in pages.into_iter().enumerate() {
+            if index + 1 < count {
+                page["next_url"] =
+                    Value::from(format!("{}{}?cursor={}", self.url(), path, index + 1));
+            }
+            let route = match index {
+                0 => path.to_string(),
+                index => format!("{}?cursor={}", path, index),
+            };
+            self.mock(&route, MockResponse::json(&page));
+        }
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockRestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<ServerState>>) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        let authorized = state.api_key.as_ref().is_none_or(|api_key| {
+            request.authorization.as_deref() == Some(format!("Bearer {}", api_key).as_str())
+        });
+        if !authorized {
+            MockResponse::unauthorized()
+        } else {
+            state
+                .routes
+                .iter_mut()
+                .find(|route| route.matches(&request))
+                .map(Route::next_response)
+                .unwrap_or_else(MockResponse::not_found)
+        }
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.reason(),
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(response.body.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Reads the request line and headers of a request.
+async fn read_request(stream: &mut TcpStream) -> Option<RecordedRequest> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 || buffer.len() > MAX_REQUEST_SIZE {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let head = String::from_utf8_lossy(&buffer);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, parse_query(query)),
+        None => (target, Vec::new()),
+    };
+    let authorization = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .map(|(_, value)| value.trim().to_string());
+    Some(RecordedRequest {
+        method,
+        path: path.to_string(),
+        query,
+        authorization,
+    })
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn request_id() -> String {
+    format!("{:x}", Utc::now().timestamp_nanos_opt().unwrap_or_default())
+}
+
+/// A body of the aggregates endpoint with `count` bars of `ticker` starting at `from`, spaced
+/// `step` apart.
+pub fn aggregates_body(ticker: &str, from: DateTime<Utc>, step: Duration, count: usize) -> Value {
+    let results: Vec<Value> = (0..count)
+        .map(|index| {
+            let open = 100.0 + index as f64;
+            json!({
+                "o": open,
+                "h": open + 1.5,
+                "l": open - 0.5,
+                "c": open + 1.0,
+                "v": 1000.0 + 10.0 * index as f64,
+                "vw": open + 0.5,
+                "n": 10 + index,
+                "t": (from + step * index as i32).timestamp_millis(),
+            })
+        })
+        .collect();
+    json!({
+        "ticker": ticker,
+        "adjusted": true,
+        "queryCount": count,
+        "resultsCount": count,
+        "request_id": request_id(),
+        "status": "OK",
+        "results": results,
+    })
+}
+
+/// A body of the grouped daily endpoint with one bar per ticker on `date`.
+pub fn grouped_daily_body(date: NaiveDate, tickers: &[&str]) -> Value {
+    let timestamp = date
+        .and_hms_opt(20, 0, 0)
+        .map(|time| time.and_utc().timestamp_millis())
+        .unwrap_or_default();
+    let results: Vec<Value> = tickers
+        .iter()
+        .enumerate()
+        .map(|(index, ticker)| {
+            let open = 10.0 * (index + 1) as f64;
+            json!({
+                "T": ticker,
+                "o": open,
+                "h": open * 1.02,
+                "l": open * 0.98,
+                "c": open * 1.01,
+                "v": 50000.0,
+                "vw": open * 1.005,
+                "n": 500,
+                "t": timestamp,
+            })
+        })
+        .collect();
+    json!({
+        "adjusted": true,
+        "queryCount": tickers.len(),
+        "resultsCount": tickers.len(),
+        "request_id": request_id(),
+        "status": "OK",
+        "results": results,
+    })
+}
+
+/// The pages of the trades endpoint for `count` trades of a symbol starting at `from`, one
+/// millisecond apart, `page_size` trades per page. Serve them with `mock_paginated`.
+pub fn trades_pages(from: DateTime<Utc>, count: usize, page_size: usize) -> Vec<Value> {
+    let trades: Vec<Value> = (0..count)
+        .map(|index| {
+            let timestamp = (from + Duration::milliseconds(index as i64))
+                .timestamp_nanos_opt()
+                .unwrap_or_default();
+            json!({
+                "conditions": [],
+                "exchange": 4,
+                "id": index.to_string(),
+                "participant_timestamp": timestamp,
+                "price": 100.0 + index as f64 / 100.0,
+                "sequence_number": index + 1,
+                "sip_timestamp": timestamp,
+                "size": 100,
+                "tape": 3,
+            })
+        })
+        .collect();
+    let mut pages: Vec<Value> = trades
+        .chunks(page_size.max(1))
+        .map(|chunk| {
+            json!({
+                "request_id": request_id(),
+                "status": "OK",
+                "results": chunk,
+            })
+        })
+        .collect();
+    if pages.is_empty() {
+        pages.push(json!({ "request_id": request_id(), "status": "OK", "results": [] }));
+    }
+    pages
+}
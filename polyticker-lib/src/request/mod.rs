@@ -1,3 +1,6 @@
+#[cfg(feature = "test-support")]
+pub mod mock;
+pub mod reference;
 pub mod stocks;
 
 pub const BASE_URL: &str = "https://api.polygon.io";
\ No newline at end of file
@@ -0,0 +1,161 @@
+use reqwest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::common::conditions::UpdateRules;
+use crate::request::BASE_URL;
+
+/// Represents an interface for fetching the trade and quote conditions used by Polygon.
+pub struct Conditions {
+    /// The API key used for authenticating requests.
+    api_key: String,
+    /// The URL of the Polygon REST API.
+    base_url: String,
+}
+
+impl Conditions {
+    /// Creates a new `Conditions` instance with the provided API key.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    pub fn new(api_key: String) -> Conditions {
+        Conditions::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    /// Creates a new `Conditions` instance sending its requests to another server than Polygon,
+    /// e.g. a proxy or a `MockRestServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `base_url` - The URL to use in place of `https://api.polygon.io`.
+    pub fn with_base_url(api_key: String, base_url: String) -> Conditions {
+        Conditions { api_key, base_url }
+    }
+
+    /// Fetches the conditions of an asset class.
+    ///
+    /// # Arguments
+    ///
+    /// * `asset_class` - The asset class (`stocks`, `options`, `crypto` or `fx`).
+    /// * `data_type` - The data the conditions apply to (`trade`, `bbo` or `nbbo`).
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the first page as `ConditionsApiResponse` if successful, or an
+    /// error otherwise. Further pages can be fetched with `get_next_page`.
+    pub async fn get_conditions(
+        &self,
+        asset_class: &str,
+        data_type: &str,
+    ) -> Result<ConditionsApiResponse, reqwest::Error> {
+        let url = format!("{base}/v3/reference/conditions", base = self.base_url);
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", &self.api_key))
+            .query(&[
+                ("asset_class", asset_class),
+                ("data_type", data_type),
+                ("limit", "1000"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ConditionsApiResponse>()
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Fetches the page of conditions behind the `next_url` of a previous response.
+    pub async fn get_next_page(
+        &self,
+        next_url: &str,
+    ) -> Result<ConditionsApiResponse, reqwest::Error> {
+        let response = reqwest::Client::new()
+            .get(next_url)
+            .header("Authorization", format!("Bearer {}", &self.api_key))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ConditionsApiResponse>()
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Fetches all conditions of an asset class, following `next_url` until the last page.
+    pub async fn get_all_conditions(
+        &self,
+        asset_class: &str,
+        data_type: &str,
+    ) -> Result<Vec<ConditionRecord>, reqwest::Error> {
+        let mut response = self.get_conditions(asset_class, data_type).await?;
+        let mut conditions = std::mem::take(&mut response.results);
+        while let Some(next_url) = response.next_url.take() {
+            response = self.get_next_page(&next_url).await?;
+            conditions.append(&mut response.results);
+        }
+        Ok(conditions)
+    }
+}
+
+/// Represents the response from the Polygon conditions API.
+#[derive(Deserialize, Debug, Serialize)]
+pub struct ConditionsApiResponse {
+    /// A request id assigned by the server.
+    pub request_id: String,
+    /// The status of this request's response.
+    pub status: String,
+    /// The number of conditions in this page.
+    #[serde(default)]
+    pub count: i64,
+    /// An array of conditions.
+    #[serde(default)]
+    pub results: Vec<ConditionRecord>,
+    /// If present, this value can be used to fetch the next page of data.
+    pub next_url: Option<String>,
+}
+
+/// Represents a single condition.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct ConditionRecord {
+    /// The ID of the condition, as found in trades and quotes.
+    pub id: i32,
+
+    /// The name of the condition.
+    pub name: String,
+
+    /// A short code for the condition.
+    pub abbreviation: Option<String>,
+
+    /// The asset class the condition applies to.
+    pub asset_class: String,
+
+    /// The data types the condition applies to (`trade`, `bbo`, `nbbo`).
+    #[serde(default)]
+    pub data_types: Vec<String>,
+
+    /// The category of the condition, e.g. `sale_condition` or `settlement_condition`.
+    #[serde(rename = "type")]
+    pub condition_type: String,
+
+    /// A description of the condition.
+    pub description: Option<String>,
+
+    /// The exchange the condition is specific to, if any.
+    pub exchange: Option<i64>,
+
+    /// Whether the condition is no longer in use.
+    #[serde(default)]
+    pub legacy: bool,
+
+    /// The codes of the condition in each SIP feed (`CTA`, `UTP`, `OPRA`).
+    #[serde(default)]
+    pub sip_mapping: HashMap<String, String>,
+
+    /// Which values a trade with this condition may update.
+    pub update_rules: Option<UpdateRules>,
+}
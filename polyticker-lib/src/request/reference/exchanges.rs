@@ -0,0 +1,117 @@
+use reqwest;
+use serde::{Deserialize, Serialize};
+
+use crate::request::BASE_URL;
+
+/// Represents an interface for fetching the exchanges, trade reporting facilities and SIPs known
+/// to Polygon.
+pub struct Exchanges {
+    /// The API key used for authenticating requests.
+    api_key: String,
+    /// The URL of the Polygon REST API.
+    base_url: String,
+}
+
+impl Exchanges {
+    /// Creates a new `Exchanges` instance with the provided API key.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    pub fn new(api_key: String) -> Exchanges {
+        Exchanges::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    /// Creates a new `Exchanges` instance sending its requests to another server than Polygon,
+    /// e.g. a proxy or a `MockRestServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `base_url` - The URL to use in place of `https://api.polygon.io`.
+    pub fn with_base_url(api_key: String, base_url: String) -> Exchanges {
+        Exchanges { api_key, base_url }
+    }
+
+    /// Fetches the exchanges of an asset class, or of all asset classes.
+    ///
+    /// # Arguments
+    ///
+    /// * `asset_class` - The asset class (`stocks`, `options`, `crypto` or `fx`), or `None`
+    ///   for all of them.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `ExchangesApiResponse` if successful, or an error otherwise.
+    pub async fn get_exchanges(
+        &self,
+        asset_class: Option<&str>,
+    ) -> Result<ExchangesApiResponse, reqwest::Error> {
+        let url = format!("{base}/v3/reference/exchanges", base = self.base_url);
+
+        let mut request = reqwest::Client::new()
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", &self.api_key));
+        if let Some(asset_class) = asset_class {
+            request = request.query(&[("asset_class", asset_class)]);
+        }
+        let response = request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ExchangesApiResponse>()
+            .await?;
+
+        Ok(response)
+    }
+}
+
+/// Represents the response from the Polygon exchanges API.
+#[derive(Deserialize, Debug, Serialize)]
+pub struct ExchangesApiResponse {
+    /// A request id assigned by the server.
+    pub request_id: String,
+    /// The status of this request's response.
+    pub status: String,
+    /// The number of exchanges in the response.
+    #[serde(default)]
+    pub count: i64,
+    /// An array of exchanges.
+    #[serde(default)]
+    pub results: Vec<ExchangeRecord>,
+}
+
+/// Represents a single exchange, trade reporting facility or SIP.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct ExchangeRecord {
+    /// The ID of the exchange, as found in trades and quotes of its asset class.
+    pub id: i64,
+
+    /// The name of the exchange.
+    pub name: String,
+
+    /// The Market Identifier Code (ISO 10383) of the exchange.
+    pub mic: Option<String>,
+
+    /// The MIC of the entity operating the exchange.
+    pub operating_mic: Option<String>,
+
+    /// A short name for the exchange.
+    pub acronym: Option<String>,
+
+    /// The ID of the exchange in the SIP feeds.
+    pub participant_id: Option<String>,
+
+    /// The kind of venue (`exchange`, `TRF` or `SIP`).
+    #[serde(rename = "type")]
+    pub exchange_type: String,
+
+    /// The asset class of the exchange.
+    pub asset_class: String,
+
+    /// The locale of the exchange (`us` or `global`).
+    pub locale: String,
+
+    /// The website of the exchange.
+    pub url: Option<String>,
+}
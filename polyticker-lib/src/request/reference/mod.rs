@@ -0,0 +1,2 @@
+pub mod conditions;
+pub mod exchanges;
\ No newline at end of file
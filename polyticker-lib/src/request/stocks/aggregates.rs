@@ -1,15 +1,17 @@
 use reqwest;
 use serde::{Deserialize, Serialize};
 
+use crate::common::instrument::Instrument;
 use crate::request::BASE_URL;
 use crate::util::{Stocks, TimeUtil};
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
 
 /// Represents an interface for fetching stock aggregates.
 pub struct Aggregates {
     /// The API key used for authenticating requests.
     api_key: String,
+    /// The URL of the Polygon REST API.
+    base_url: String,
 }
 
 impl Aggregates {
@@ -19,14 +21,25 @@ impl Aggregates {
     ///
     /// * `api_key` - A string representing the Polygon API key.
     pub fn new(api_key: String) -> Aggregates {
-        Aggregates { api_key }
+        Aggregates::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    /// Creates a new `Aggregates` instance sending its requests to another server than Polygon,
+    /// e.g. a proxy or a `MockRestServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `base_url` - The URL to use in place of `https://api.polygon.io`.
+    pub fn with_base_url(api_key: String, base_url: String) -> Aggregates {
+        Aggregates { api_key, base_url }
     }
 
     /// Fetches aggregate data for a stock over a given date range in custom time window sizes.
     ///
     /// # Arguments
     ///
-    /// * `stocks_ticker` - The ticker symbol of the stock/equity.
+    /// * `ticker` - The instrument, e.g. `AAPL` or `X:BTCUSD`.
     /// * `multiplier` - The size of the timespan multiplier.
     /// * `timespan` - The size of the time window (e.g., "day").
     /// * `from` - The start of the aggregate time window, formatted as YYYY-MM-DD.
@@ -38,9 +51,10 @@ impl Aggregates {
     /// # Returns
     ///
     /// A `Result` containing `ApiResponse` if successful, or an error otherwise.
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_stock_data(
         &self,
-        stocks_ticker: &str,
+        ticker: &Instrument,
         multiplier: &str,
         timespan: &str,
         from: &str,
@@ -51,8 +65,8 @@ impl Aggregates {
     ) -> Result<ApiResponse, reqwest::Error> {
         let url = format!(
             "{base}/v2/aggs/ticker/{ticker}/range/{multiplier}/{timespan}/{from}/{to}",
-            base = BASE_URL,
-            ticker = stocks_ticker,
+            base = self.base_url,
+            ticker = ticker,
             multiplier = multiplier,
             timespan = timespan,
             from = from,
@@ -69,6 +83,7 @@ impl Aggregates {
             ])
             .send()
             .await?
+            .error_for_status()?
             .json::<ApiResponse>()
             .await?;
 
@@ -79,24 +94,25 @@ impl Aggregates {
 /// Represents the response from the Polygon aggregates API.
 #[derive(Deserialize, Debug, Serialize)]
 pub struct ApiResponse {
-    /// The exchange symbol that this item is traded under.
-    ticker: String,
+    /// The exchange symbol that this item is traded under, a stock unless prefixed like
+    /// `X:BTCUSD`.
+    pub ticker: Instrument,
     /// Whether or not this response was adjusted for splits.
-    adjusted: bool,
+    pub adjusted: bool,
     /// The number of aggregates (minute or day) used to generate the response.
     #[serde(rename = "queryCount")]
-    query_count: i64,
+    pub query_count: i64,
     /// A request id assigned by the server.
-    request_id: String,
+    pub request_id: String,
     /// The total number of results for this request.
     #[serde(rename = "resultsCount")]
-    results_count: i64,
+    pub results_count: i64,
     /// The status of this request's response.
-    status: String,
+    pub status: String,
     /// An array of aggregate results for the given stock.
-    results: Vec<AggregateResult>,
+    pub results: Vec<AggregateResult>,
     /// If present, this value can be used to fetch the next page of data.
-    next_url: Option<String>,
+    pub next_url: Option<String>,
 }
 
 /// Represents a single aggregate data point for a stock over a specific time window.
@@ -104,37 +120,37 @@ pub struct ApiResponse {
 pub struct AggregateResult {
     /// The close price for the stock in the given time period.
     #[serde(rename = "c")]
-    close_price: f64,
+    pub close_price: f64,
 
     /// The highest price for the stock in the given time period.
     #[serde(rename = "h")]
-    highest_price: f64,
+    pub highest_price: f64,
 
     /// The lowest price for the stock in the given time period.
     #[serde(rename = "l")]
-    lowest_price: f64,
+    pub lowest_price: f64,
 
     /// The number of transactions that occurred in the aggregate window.
     #[serde(rename = "n")]
-    number_of_transactions: u64,
+    pub number_of_transactions: u64,
 
     /// The open price for the stock in the given time period.
     #[serde(rename = "o")]
-    open_price: f64,
+    pub open_price: f64,
 
     /// Whether this aggregate is for an OTC (Over The Counter) ticker.
     #[serde(rename = "otc", default = "Stocks::default_is_otc_ticker")]
-    is_otc_ticker: bool,
+    pub is_otc_ticker: bool,
 
     /// The Unix Msec timestamp marking the start of the aggregate window.
     #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
-    timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
 
     /// The trading volume of the stock in the given time period.
     #[serde(rename = "v")]
-    trading_volume: f64,
+    pub trading_volume: f64,
 
     /// The volume-weighted average price. Might be omitted in some cases.
     #[serde(rename = "vw")]
-    volume_weighted_avg_price: Option<f64>,
+    pub volume_weighted_avg_price: Option<f64>,
 }
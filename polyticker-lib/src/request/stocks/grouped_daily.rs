@@ -1,8 +1,8 @@
 use reqwest;
 use serde::Deserialize;
 
-use std::collections::HashMap;
 use chrono::{DateTime, Utc};
+use crate::common::instrument::{deserialize_stock_ticker, Instrument};
 use crate::request::BASE_URL;
 use crate::util::{TimeUtil, Stocks};
 
@@ -10,6 +10,8 @@ use crate::util::{TimeUtil, Stocks};
 pub struct GroupedDaily {
     /// The API key used for authenticating requests.
     api_key: String,
+    /// The URL of the Polygon REST API.
+    base_url: String,
 }
 
 impl GroupedDaily {
@@ -19,7 +21,18 @@ impl GroupedDaily {
     ///
     /// * `api_key` - A string representing the Polygon API key.
     pub fn new(api_key: String) -> GroupedDaily {
-        GroupedDaily { api_key }
+        GroupedDaily::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    /// Creates a new `GroupedDaily` instance sending its requests to another server than Polygon,
+    /// e.g. a proxy or a `MockRestServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `base_url` - The URL to use in place of `https://api.polygon.io`.
+    pub fn with_base_url(api_key: String, base_url: String) -> GroupedDaily {
+        GroupedDaily { api_key, base_url }
     }
 
     /// Fetches grouped daily data for the entire stocks/equities stocks for a given date.
@@ -41,7 +54,7 @@ impl GroupedDaily {
     ) -> Result<GroupedDailyApiResponse, reqwest::Error> {
         let url = format!(
             "{base}/v2/aggs/grouped/locale/us/market/stocks/{date}",
-            base = BASE_URL,
+            base = self.base_url,
             date = date
         );
 
@@ -51,6 +64,7 @@ impl GroupedDaily {
             .query(&[("adjusted", adjusted.to_string()), ("include_otc", include_otc.to_string())])
             .send()
             .await?
+            .error_for_status()?
             .json::<GroupedDailyApiResponse>()
             .await?;
 
@@ -62,52 +76,52 @@ impl GroupedDaily {
 #[derive(Deserialize, Debug)]
 pub struct GroupedDailyApiResponse {
     /// Whether or not this response was adjusted for splits.
-    adjusted: bool,
+    pub adjusted: bool,
     /// The number of aggregates (minute or day) used to generate the response.
     #[serde(rename = "queryCount")]
-    query_count: i64,
+    pub query_count: i64,
     /// A request id assigned by the server.
-    request_id: String,
+    pub request_id: String,
     /// The total number of results for this request.
     #[serde(rename = "resultsCount")]
-    results_count: i64,
+    pub results_count: i64,
     /// The status of this request's response.
-    status: String,
+    pub status: String,
     /// An array of aggregate results for the given stock.
-    results: Vec<GroupedDailyResult>,
+    pub results: Vec<GroupedDailyResult>,
 }
 
 /// Represents a single aggregate data point for the entire stocks/equities stocks over a specific time window.
 #[derive(Deserialize, Debug)]
 pub struct GroupedDailyResult {
     /// The exchange symbol that this item is traded under.
-    #[serde(rename = "T")]
-    ticker: String,
+    #[serde(rename = "T", deserialize_with = "deserialize_stock_ticker")]
+    pub ticker: Instrument,
     /// The close price for the symbol in the given time period.
     #[serde(rename = "c")]
-    close_price: f64,
+    pub close_price: f64,
     /// The highest price for the symbol in the given time period.
     #[serde(rename = "h")]
-    highest_price: f64,
+    pub highest_price: f64,
     /// The lowest price for the symbol in the given time period.
     #[serde(rename = "l")]
-    lowest_price: f64,
+    pub lowest_price: f64,
     /// The number of transactions that occurred in the aggregate window.
     #[serde(rename = "n")]
-    number_of_transactions: Option<u64>,
+    pub number_of_transactions: Option<u64>,
     /// The open price for the symbol in the given time period.
     #[serde(rename = "o")]
-    open_price: f64,
+    pub open_price: f64,
     /// Whether this aggregate is for an OTC (Over The Counter) ticker.
     #[serde(rename = "otc", default = "Stocks::default_is_otc_ticker")]
-    is_otc_ticker: bool,
+    pub is_otc_ticker: bool,
     /// The Unix Msec timestamp marking the start of the aggregate window.
     #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
-    timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
     /// The trading volume of the symbol in the given time period.
     #[serde(rename = "v")]
-    trading_volume: f64,
+    pub trading_volume: f64,
     /// The volume-weighted average price. Might be omitted in some cases.
     #[serde(rename = "vw")]
-    volume_weighted_avg_price: Option<f64>,
+    pub volume_weighted_avg_price: Option<f64>,
 }
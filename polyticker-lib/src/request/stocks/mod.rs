@@ -1,2 +1,3 @@
 pub mod aggregates;
-pub mod grouped_daily;
\ No newline at end of file
+pub mod grouped_daily;
+pub mod trades;
\ No newline at end of file
@@ -0,0 +1,272 @@
+use reqwest;
+use serde::{Deserialize, Serialize};
+
+use crate::common::conditions::SipCondition;
+use crate::common::instrument::Instrument;
+use crate::common::trade::{Tape, Trade, TradeData};
+use crate::exchange::registry::AssetClass;
+use crate::request::BASE_URL;
+use crate::util::TimeUtil;
+use chrono::{DateTime, Utc};
+
+/// Represents an interface for fetching historical tick level trades of a stock.
+pub struct Trades {
+    /// The API key used for authenticating requests.
+    api_key: String,
+    /// The URL of the Polygon REST API.
+    base_url: String,
+}
+
+impl Trades {
+    /// Creates a new `Trades` instance with the provided API key.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    pub fn new(api_key: String) -> Trades {
+        Trades::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    /// Creates a new `Trades` instance sending its requests to another server than Polygon,
+    /// e.g. a proxy or a `MockRestServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `base_url` - The URL to use in place of `https://api.polygon.io`.
+    pub fn with_base_url(api_key: String, base_url: String) -> Trades {
+        Trades { api_key, base_url }
+    }
+
+    /// Fetches the trades of a stock within a time window, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `ticker` - The instrument, e.g. `AAPL` or `O:SPY241220P00720000`.
+    /// * `from` - The start of the time window (inclusive).
+    /// * `to` - The end of the time window (inclusive).
+    /// * `limit` - Limits the number of trades per page (at most 50000).
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the first page as `TradesApiResponse` if successful, or an error
+    /// otherwise. Further pages can be fetched with `get_next_page`.
+    pub async fn get_trades(
+        &self,
+        ticker: &Instrument,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: i32,
+    ) -> Result<TradesApiResponse, reqwest::Error> {
+        self.query_trades(ticker, from, ("timestamp.lte", to), limit)
+            .await
+    }
+
+    /// Fetches the trades of a stock from `from` (inclusive) up to `before` (exclusive), oldest
+    /// first, like `get_trades`. Useful when the end of the window is a truncated timestamp, e.g.
+    /// the milliseconds of a websocket trade plus one millisecond.
+    pub async fn get_trades_before(
+        &self,
+        ticker: &Instrument,
+        from: DateTime<Utc>,
+        before: DateTime<Utc>,
+        limit: i32,
+    ) -> Result<TradesApiResponse, reqwest::Error> {
+        self.query_trades(ticker, from, ("timestamp.lt", before), limit)
+            .await
+    }
+
+    /// Fetches the first page of trades from `from` up to `end`, a bound parameter and its value.
+    async fn query_trades(
+        &self,
+        ticker: &Instrument,
+        from: DateTime<Utc>,
+        (end_param, end): (&str, DateTime<Utc>),
+        limit: i32,
+    ) -> Result<TradesApiResponse, reqwest::Error> {
+        let url = format!(
+            "{base}/v3/trades/{ticker}",
+            base = self.base_url,
+            ticker = ticker
+        );
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", &self.api_key))
+            .query(&[
+                ("timestamp.gte", timestamp_param(from)),
+                (end_param, timestamp_param(end)),
+                ("order", "asc".to_string()),
+                ("sort", "timestamp".to_string()),
+                ("limit", limit.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TradesApiResponse>()
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Fetches the page of trades behind the `next_url` of a previous response.
+    pub async fn get_next_page(&self, next_url: &str) -> Result<TradesApiResponse, reqwest::Error> {
+        let response = reqwest::Client::new()
+            .get(next_url)
+            .header("Authorization", format!("Bearer {}", &self.api_key))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TradesApiResponse>()
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Fetches all trades of a stock within a time window, following `next_url` until the last
+    /// page.
+    pub async fn get_all_trades(
+        &self,
+        ticker: &Instrument,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<HistoricalTrade>, reqwest::Error> {
+        let response = self.get_trades(ticker, from, to, 50000).await?;
+        self.follow_pages(response).await
+    }
+
+    /// Fetches all trades of a stock from `from` (inclusive) up to `before` (exclusive),
+    /// following `next_url` until the last page.
+    pub async fn get_all_trades_before(
+        &self,
+        ticker: &Instrument,
+        from: DateTime<Utc>,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<HistoricalTrade>, reqwest::Error> {
+        let response = self.get_trades_before(ticker, from, before, 50000).await?;
+        self.follow_pages(response).await
+    }
+
+    /// Collects the trades of a first page and of every page after it.
+    async fn follow_pages(
+        &self,
+        mut response: TradesApiResponse,
+    ) -> Result<Vec<HistoricalTrade>, reqwest::Error> {
+        let mut trades = std::mem::take(&mut response.results);
+        while let Some(next_url) = response.next_url.take() {
+            response = self.get_next_page(&next_url).await?;
+            trades.append(&mut response.results);
+        }
+        Ok(trades)
+    }
+}
+
+/// Formats a timestamp as the nanosecond value accepted by the v3 endpoints.
+fn timestamp_param(timestamp: DateTime<Utc>) -> String {
+    timestamp
+        .timestamp_nanos_opt()
+        .unwrap_or(timestamp.timestamp_millis().saturating_mul(1_000_000))
+        .to_string()
+}
+
+/// Represents the response from the Polygon trades API.
+#[derive(Deserialize, Debug, Serialize)]
+pub struct TradesApiResponse {
+    /// A request id assigned by the server.
+    pub request_id: String,
+    /// The status of this request's response.
+    pub status: String,
+    /// An array of trades for the given stock.
+    #[serde(default)]
+    pub results: Vec<HistoricalTrade>,
+    /// If present, this value can be used to fetch the next page of data.
+    pub next_url: Option<String>,
+}
+
+/// Represents a single historical trade of a stock.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct HistoricalTrade {
+    /// The trade conditions.
+    #[serde(default)]
+    pub conditions: Vec<i32>,
+
+    /// The trade correction indicator.
+    pub correction: Option<i32>,
+
+    /// The exchange ID where the trade took place.
+    pub exchange: i64,
+
+    /// The trade ID, unique per exchange and ticker.
+    pub id: String,
+
+    /// When the trade was generated at the exchange.
+    #[serde(default, deserialize_with = "TimeUtil::timestamp_nanoseconds_opt")]
+    pub participant_timestamp: Option<DateTime<Utc>>,
+
+    /// The price of the trade.
+    pub price: f64,
+
+    /// The sequence number of the trade, increasing and unique per ticker.
+    pub sequence_number: i64,
+
+    /// When the SIP received the trade.
+    #[serde(deserialize_with = "TimeUtil::timestamp_nanoseconds")]
+    pub sip_timestamp: DateTime<Utc>,
+
+    /// The size of the trade.
+    pub size: f64,
+
+    /// The tape the trade was reported to (1 = NYSE, 2 = AMEX, 3 = Nasdaq).
+    pub tape: Option<i32>,
+
+    /// The ID of the trade reporting facility, for off-exchange trades.
+    pub trf_id: Option<i64>,
+
+    /// When the trade reporting facility received the trade.
+    #[serde(default, deserialize_with = "TimeUtil::timestamp_nanoseconds_opt")]
+    pub trf_timestamp: Option<DateTime<Utc>>,
+}
+
+impl HistoricalTrade {
+    /// The conditions of the trade, decoded.
+    pub fn sip_conditions(&self) -> impl Iterator<Item = SipCondition> + '_ {
+        self.conditions.iter().map(|id| SipCondition::from_id(*id))
+    }
+
+    /// Pairs the trade with the instrument it was fetched for, which the v3 endpoint does not
+    /// repeat per trade, so it can be consumed as a `Trade`.
+    pub fn with_ticker(self, ticker: Instrument) -> TickerTrade {
+        TickerTrade {
+            ticker,
+            trade: self,
+        }
+    }
+}
+
+/// A historical trade with the instrument it was fetched for.
+#[derive(Debug, Clone)]
+pub struct TickerTrade {
+    pub ticker: Instrument,
+    pub trade: HistoricalTrade,
+}
+
+/// The timestamp is the SIP's, like that of the stocks feed.
+impl Trade for TickerTrade {
+    fn get_trade(&self) -> anyhow::Result<TradeData<'_>> {
+        Ok(TradeData {
+            instrument: self.ticker.clone(),
+            price: self.trade.price,
+            size: self.trade.size,
+            timestamp: self.trade.sip_timestamp,
+            exchange_id: self.trade.exchange,
+            trade_id: Some(&self.trade.id),
+            conditions: &self.trade.conditions,
+            tape: self.trade.tape.and_then(|id| Tape::from_id(id as i64)),
+            received_at: None,
+        })
+    }
+
+    fn asset_class(&self) -> AssetClass {
+        self.ticker.asset_class()
+    }
+}
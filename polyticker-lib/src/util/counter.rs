@@ -65,6 +65,16 @@ impl<T> CountedChannel<T> {
     pub fn len(&self) -> usize {
         *self.size.lock().unwrap()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for CountedChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> Drop for CountedChannel<T> {
@@ -11,13 +11,37 @@ impl TimeUtil {
         D: serde::Deserializer<'de>,
     {
         let ts_milliseconds: i64 = Deserialize::deserialize(deserializer)?;
-        let ts_seconds = ts_milliseconds / 1000;
-        let naive_datetime = chrono::NaiveDateTime::from_timestamp_opt(ts_seconds, 0)
-            .ok_or(serde::de::Error::custom("invalid timestamp"))?;
-        Ok(DateTime::<Utc>::from_naive_utc_and_offset(
-            naive_datetime,
-            Utc,
-        ))
+        DateTime::<Utc>::from_timestamp_millis(ts_milliseconds)
+            .ok_or(serde::de::Error::custom("invalid timestamp"))
+    }
+
+    /// Like `timestamp_milliseconds`, for optional fields (use together with `#[serde(default)]`).
+    pub fn timestamp_milliseconds_opt<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Self::timestamp_milliseconds(deserializer).map(Some)
+    }
+
+    /// Deserializes a Unix timestamp in nanoseconds, as used by the v3 REST endpoints.
+    pub fn timestamp_nanoseconds<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let ts_nanoseconds: i64 = Deserialize::deserialize(deserializer)?;
+        Ok(DateTime::<Utc>::from_timestamp_nanos(ts_nanoseconds))
+    }
+
+    /// Like `timestamp_nanoseconds`, for optional fields (use together with `#[serde(default)]`).
+    pub fn timestamp_nanoseconds_opt<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Self::timestamp_nanoseconds(deserializer).map(Some)
     }
 }
 
@@ -27,3 +51,51 @@ impl Stocks {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[derive(Deserialize)]
+    struct Millis {
+        #[serde(deserialize_with = "TimeUtil::timestamp_milliseconds")]
+        t: DateTime<Utc>,
+        #[serde(default, deserialize_with = "TimeUtil::timestamp_milliseconds_opt")]
+        r: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Deserialize)]
+    struct Nanos {
+        #[serde(deserialize_with = "TimeUtil::timestamp_nanoseconds")]
+        t: DateTime<Utc>,
+    }
+
+    #[test]
+    fn milliseconds_keep_their_fraction() {
+        let decoded: Millis = serde_json::from_str(r#"{"t":1610462007425}"#).unwrap();
+        assert_eq!(decoded.t.timestamp(), 1610462007);
+        assert_eq!(decoded.t.timestamp_subsec_millis(), 425);
+        assert_eq!(decoded.t.timestamp_millis(), 1610462007425);
+        assert_eq!(decoded.r, None);
+    }
+
+    #[test]
+    fn milliseconds_before_the_epoch() {
+        let decoded: Millis = serde_json::from_str(r#"{"t":-1500,"r":0}"#).unwrap();
+        assert_eq!(decoded.t.timestamp_millis(), -1500);
+        assert_eq!(decoded.r.map(|r| r.timestamp()), Some(0));
+    }
+
+    #[test]
+    fn out_of_range_milliseconds_are_rejected() {
+        assert!(serde_json::from_str::<Millis>(&format!(r#"{{"t":{}}}"#, i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn nanoseconds_keep_their_fraction() {
+        let decoded: Nanos = serde_json::from_str(r#"{"t":1517562000016036581}"#).unwrap();
+        assert_eq!(decoded.t.timestamp(), 1517562000);
+        assert_eq!(decoded.t.nanosecond(), 16036581);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::websocket::connection::StreamError;
+use crate::websocket::symbol::Symbol;
+use crate::websocket::FeedEvent;
+use futures_util::task::AtomicWaker;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::Notify;
+
+/// What a data channel does with new events when its consumer falls behind and the event queue
+/// is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Wait for the consumer to make room. Stalls the websocket read loop, which may get the
+    /// connection closed by the server as a slow consumer.
+    #[default]
+    Block,
+    /// Discard the new event.
+    DropNewest,
+    /// Discard the oldest queued event to make room, keeping the queue as a ring buffer of the
+    /// most recent events.
+    DropOldest,
+    /// Replace a queued event of the same type and symbol with the new one, so only the latest
+    /// event per key is delivered. Falls back to dropping the oldest event when the queue is full
+    /// of distinct keys.
+    Conflate,
+}
+
+/// What happened to an event handed to the queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SendOutcome {
+    Queued,
+    /// The event or an older one was discarded.
+    Dropped,
+    /// The event replaced a queued event with the same key.
+    Conflated,
+}
+
+/// The receiver is gone, nothing will be delivered anymore.
+#[derive(Debug)]
+pub(crate) struct Disconnected;
+
+/// The event type and symbol an event is conflated by. Both are interned, so computing the key
+/// of an event does not allocate.
+type ConflationKey = (Symbol, Symbol);
+
+struct Entry<E> {
+    item: Result<E, StreamError>,
+    key: Option<ConflationKey>,
+}
+
+struct State<E> {
+    queue: VecDeque<Entry<E>>,
+    /// The position of the front of the queue in the sequence of all queued entries.
+    front_sequence: u64,
+    /// The sequence number of the queued entry for every conflation key.
+    keys: HashMap<ConflationKey, u64>,
+    /// The number of live senders, the queue is closed for the receiver once it drops to zero.
+    senders: usize,
+    receiver_closed: bool,
+}
+
+impl<E> State<E> {
+    fn pop_front(&mut self) -> Option<Result<E, StreamError>> {
+        let entry = self.queue.pop_front()?;
+        if let Some(key) = entry.key {
+            if self.keys.get(&key) == Some(&self.front_sequence) {
+                self.keys.remove(&key);
+            }
+        }
+        self.front_sequence += 1;
+        Some(entry.item)
+    }
+}
+
+struct Shared<E> {
+    state: Mutex<State<E>>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    receiver_waker: AtomicWaker,
+    space_available: Notify,
+}
+
+/// Creates a bounded event queue applying `policy` when it is full.
+pub(crate) fn channel<E: FeedEvent>(
+    capacity: usize,
+    policy: BackpressurePolicy,
+) -> (Sender<E>, Receiver<E>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity.min(4096)),
+            front_sequence: 0,
+            keys: HashMap::new(),
+            senders: 1,
+            receiver_closed: false,
+        }),
+        capacity: capacity.max(1),
+        policy,
+        receiver_waker: AtomicWaker::new(),
+        space_available: Notify::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub(crate) struct Sender<E> {
+    shared: Arc<Shared<E>>,
+}
+
+impl<E: FeedEvent> Sender<E> {
+    /// Queues an item according to the policy. Only waits for room under `Block`.
+    ///
+    /// Errors are never dropped or conflated, they are queued even if the queue is full.
+    pub(crate) async fn send(
+        &self,
+        item: Result<E, StreamError>,
+    ) -> Result<SendOutcome, Disconnected> {
+        let mut item = Some(item);
+        loop {
+            let space_available = self.shared.space_available.notified();
+            if let Some(outcome) = self.try_queue(&mut item)? {
+                return Ok(outcome);
+            }
+            space_available.await;
+        }
+    }
+
+    /// Queues an item according to the policy without ever waiting. Under `Block` a full queue
+    /// drops the new event instead.
+    pub(crate) fn try_send(
+        &self,
+        item: Result<E, StreamError>,
+    ) -> Result<SendOutcome, Disconnected> {
+        let mut item = Some(item);
+        Ok(self.try_queue(&mut item)?.unwrap_or(SendOutcome::Dropped))
+    }
+
+    /// Returns true if the queue has room for another event.
+    pub(crate) fn has_capacity(&self) -> bool {
+        self.shared.state.lock().unwrap().queue.len() < self.shared.capacity
+    }
+
+    /// Returns true once the receiver has been dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().receiver_closed
+    }
+
+    /// Queues the item unless the policy is `Block` and the queue is full, in which case the item
+    /// is left in place.
+    fn try_queue(
+        &self,
+        item: &mut Option<Result<E, StreamError>>,
+    ) -> Result<Option<SendOutcome>, Disconnected> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.receiver_closed {
+            return Err(Disconnected);
+        }
+        let full = state.queue.len() >= self.shared.capacity;
+        let is_error = matches!(item, Some(Err(_)));
+        let key = match (&item, self.shared.policy) {
+            (Some(Ok(event)), BackpressurePolicy::Conflate) => event
+                .symbol()
+                .map(|symbol| (Symbol::new(event.event_type()), Symbol::new(&symbol))),
+            _ => None,
+        };
+
+        if let Some(sequence) = key.as_ref().and_then(|key| state.keys.get(key)).copied() {
+            let index = (sequence - state.front_sequence) as usize;
+            state.queue[index].item = item.take().expect("item is only taken once");
+            return Ok(Some(SendOutcome::Conflated));
+        }
+
+        let mut outcome = SendOutcome::Queued;
+        if full && !is_error {
+            match self.shared.policy {
+                BackpressurePolicy::Block => return Ok(None),
+                BackpressurePolicy::DropNewest => {
+                    item.take();
+                    return Ok(Some(SendOutcome::Dropped));
+                }
+                BackpressurePolicy::DropOldest | BackpressurePolicy::Conflate => {
+                    state.pop_front();
+                    outcome = SendOutcome::Dropped;
+                }
+            }
+        }
+
+        let sequence = state.front_sequence + state.queue.len() as u64;
+        if let Some(key) = &key {
+            state.keys.insert(key.clone(), sequence);
+        }
+        state.queue.push_back(Entry {
+            item: item.take().expect("item is only taken once"),
+            key,
+        });
+        drop(state);
+        self.shared.receiver_waker.wake();
+        Ok(Some(outcome))
+    }
+}
+
+impl<E> Clone for Sender<E> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<E> Drop for Sender<E> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().senders -= 1;
+        self.shared.receiver_waker.wake();
+    }
+}
+
+pub(crate) struct Receiver<E> {
+    shared: Arc<Shared<E>>,
+}
+
+impl<E> Receiver<E> {
+    /// Receives the next item, or `None` once the sender is gone and the queue is drained.
+    pub(crate) fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<E, StreamError>>> {
+        // register first so a send racing with this poll is not missed
+        self.shared.receiver_waker.register(cx.waker());
+        let mut state = self.shared.state.lock().unwrap();
+        match state.pop_front() {
+            Some(item) => {
+                drop(state);
+                self.shared.space_available.notify_one();
+                Poll::Ready(Some(item))
+            }
+            None if state.senders == 0 || state.receiver_closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Stops accepting new items while still allowing queued ones to be received.
+    pub(crate) fn close(&mut self) {
+        self.shared.state.lock().unwrap().receiver_closed = true;
+        self.shared.space_available.notify_waiters();
+    }
+}
+
+impl<E> Drop for Receiver<E> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::websocket::backpressure::BackpressurePolicy;
+use crate::websocket::recorder::FrameRecorder;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use std::time::Duration;
+
+/// Settings for a data channel: buffering, heartbeat, idle detection and reconnection.
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    /// The capacity of the event channel handed to the consumer.
+    pub output_channel_size: usize,
+    /// What to do with new events when the consumer falls behind and the channel is full.
+    pub backpressure: BackpressurePolicy,
+    /// How often to send a websocket ping to keep the connection alive. `None` disables pings.
+    pub ping_interval: Option<Duration>,
+    /// How long the connection may go without receiving any frame before it is considered stale
+    /// and reconnected. `None` disables the idle watchdog.
+    pub idle_timeout: Option<Duration>,
+    /// If set, the idle watchdog only fires while the market is open, so a quiet market outside
+    /// trading hours is not mistaken for a dead connection.
+    pub market_hours: Option<MarketHours>,
+    /// How many consecutive reconnection attempts to make after the connection is lost before
+    /// giving up. `0` disables reconnection.
+    pub reconnect_attempts: u32,
+    /// The delay before the first reconnection attempt, doubled after every failed attempt.
+    pub reconnect_delay: Duration,
+    /// The upper bound for the delay between reconnection attempts.
+    pub max_reconnect_delay: Duration,
+    /// If set, every raw text frame is written to this recording before it is decoded.
+    pub recorder: Option<FrameRecorder>,
+    /// Connects to this URL instead of the cluster's Polygon endpoint, e.g. a proxy or a
+    /// `MockServer`.
+    pub url: Option<String>,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            output_channel_size: 1000,
+            backpressure: BackpressurePolicy::Block,
+            ping_interval: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(90)),
+            market_hours: None,
+            reconnect_attempts: 5,
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(30),
+            recorder: None,
+            url: None,
+        }
+    }
+}
+
+impl StreamConfig {
+    /// The default settings with the given channel capacity.
+    pub fn with_channel_size(output_channel_size: usize) -> Self {
+        StreamConfig {
+            output_channel_size,
+            ..Default::default()
+        }
+    }
+}
+
+/// A daily trading session in UTC, used to make the idle watchdog market-hours aware.
+#[derive(Clone, Debug)]
+pub struct MarketHours {
+    /// The session open, in UTC.
+    pub open: NaiveTime,
+    /// The session close, in UTC. May be earlier than `open` for sessions spanning midnight UTC.
+    pub close: NaiveTime,
+    /// Whether the market is closed on Saturdays and Sundays.
+    pub weekdays_only: bool,
+}
+
+impl MarketHours {
+    /// The US equities regular session (9:30 to 16:00 New York time) on weekdays, widened to
+    /// 13:30 to 21:00 UTC so that it covers both daylight saving and standard time.
+    pub fn us_equities() -> Self {
+        MarketHours {
+            open: NaiveTime::from_hms_opt(13, 30, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            weekdays_only: true,
+        }
+    }
+
+    /// Returns true if the market is open at the given time.
+    pub fn is_open(&self, at: DateTime<Utc>) -> bool {
+        if self.weekdays_only && matches!(at.weekday(), Weekday::Sat | Weekday::Sun) {
+            return false;
+        }
+        let time = at.time();
+        if self.open <= self.close {
+            time >= self.open && time < self.close
+        } else {
+            time >= self.open || time < self.close
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A time in the week of Monday, 2024-01-15.
+    fn at(day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn us_equities_are_open_during_the_regular_session() {
+        let hours = MarketHours::us_equities();
+        assert!(hours.is_open(at(15, 13, 30, 0)));
+        assert!(hours.is_open(at(17, 18, 0, 0)));
+        assert!(hours.is_open(at(19, 20, 59, 59)));
+    }
+
+    #[test]
+    fn us_equities_are_closed_before_and_after_the_session() {
+        let hours = MarketHours::us_equities();
+        // pre-market
+        assert!(!hours.is_open(at(15, 9, 0, 0)));
+        assert!(!hours.is_open(at(15, 13, 29, 59)));
+        // post-market, the close is exclusive
+        assert!(!hours.is_open(at(15, 21, 0, 0)));
+        assert!(!hours.is_open(at(15, 23, 59, 59)));
+        assert!(!hours.is_open(at(16, 0, 0, 0)));
+    }
+
+    #[test]
+    fn us_equities_are_closed_on_weekends() {
+        let hours = MarketHours::us_equities();
+        assert!(!hours.is_open(at(20, 15, 0, 0)));
+        assert!(!hours.is_open(at(21, 15, 0, 0)));
+        // open again on Monday
+        assert!(hours.is_open(at(22, 15, 0, 0)));
+    }
+
+    #[test]
+    fn sessions_may_span_midnight() {
+        let hours = MarketHours {
+            open: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            weekdays_only: false,
+        };
+        assert!(hours.is_open(at(15, 22, 0, 0)));
+        assert!(hours.is_open(at(16, 3, 0, 0)));
+        assert!(!hours.is_open(at(16, 6, 0, 0)));
+        assert!(!hours.is_open(at(16, 12, 0, 0)));
+        // every day of the week
+        assert!(hours.is_open(at(20, 23, 0, 0)));
+    }
+}
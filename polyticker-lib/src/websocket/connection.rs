@@ -0,0 +1,783 @@
+use crate::websocket::backpressure::{self, Disconnected, Receiver, SendOutcome, Sender};
+use crate::websocket::config::StreamConfig;
+use crate::websocket::health::{HealthMonitor, HealthSnapshot};
+use crate::websocket::{FeedEvent, Status, StatusEvent};
+use chrono::Utc;
+use futures_util::{SinkExt, Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, timeout};
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+
+pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How long to wait for the server to acknowledge a close frame when shutting down.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Errors raised while connecting to or reading from a Polygon cluster.
+#[derive(Clone, Debug)]
+pub enum StreamError {
+    /// The websocket connection could not be established or failed.
+    Connection(Arc<tungstenite::Error>),
+    /// The server rejected the API key.
+    AuthFailed(String),
+    /// The server did not answer the connect/auth handshake as expected.
+    Handshake(String),
+    /// A frame or one of its events could not be decoded.
+    Decode(String),
+    /// No frame was received within the configured idle timeout.
+    Idle(Duration),
+    /// The server closed the connection.
+    Closed,
+    /// The background task panicked or was cancelled.
+    Task(String),
+    /// A hub subscriber fell behind and this many events were dropped from its queue.
+    Lagged(u64),
+    /// A recording being replayed could not be read.
+    Recording(Arc<std::io::Error>),
+    /// The requested configuration cannot be satisfied, e.g. a pool needing more connections
+    /// than allowed.
+    Config(String),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Connection(e) => write!(f, "Connection error: {}", e),
+            StreamError::AuthFailed(message) => write!(f, "Authentication failed: {}", message),
+            StreamError::Handshake(message) => write!(f, "Handshake error: {}", message),
+            StreamError::Decode(message) => write!(f, "Decode error: {}", message),
+            StreamError::Idle(idle) => write!(f, "No data received for {:?}", idle),
+            StreamError::Closed => write!(f, "Connection closed by the server"),
+            StreamError::Task(message) => write!(f, "Task error: {}", message),
+            StreamError::Lagged(dropped) => {
+                write!(f, "Subscriber lagged behind, {} events dropped", dropped)
+            }
+            StreamError::Recording(e) => write!(f, "Recording error: {}", e),
+            StreamError::Config(message) => write!(f, "Invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Connection(e) => Some(e.as_ref()),
+            StreamError::Recording(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<tungstenite::Error> for StreamError {
+    fn from(e: tungstenite::Error) -> Self {
+        StreamError::Connection(Arc::new(e))
+    }
+}
+
+/// The events produced by a data channel, usable both with `recv` loops and as a
+/// `futures::Stream`, so feeds can be filtered, chunked or merged with stream combinators.
+///
+/// Errors that do not end the stream (such as an undecodable event) are delivered in place of the
+/// event and the stream continues. The stream ends once the background task exits.
+pub struct EventStream<E> {
+    rx: Receiver<E>,
+}
+
+impl<E> EventStream<E> {
+    pub(crate) fn new(rx: Receiver<E>) -> Self {
+        EventStream { rx }
+    }
+
+    /// Receives the next event, or `None` once the stream has ended.
+    pub async fn recv(&mut self) -> Option<Result<E, StreamError>> {
+        std::future::poll_fn(|cx| self.rx.poll_recv(cx)).await
+    }
+
+    /// Stops accepting new events while still allowing buffered ones to be received.
+    pub fn close(&mut self) {
+        self.rx.close();
+    }
+}
+
+impl<E> Stream for EventStream<E> {
+    type Item = Result<E, StreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Asks the background task of a data channel to stop.
+///
+/// Cloned from a `StreamHandle`, the signal lets another task (e.g. a Ctrl-C handler) stop the
+/// stream while the handle itself is kept to await the outcome.
+#[derive(Clone, Debug)]
+pub struct ShutdownSignal(Arc<watch::Sender<bool>>);
+
+impl ShutdownSignal {
+    pub(crate) fn new() -> Self {
+        ShutdownSignal(Arc::new(watch::channel(false).0))
+    }
+
+    /// Asks the task to unsubscribe, close the connection and exit. Triggering the signal again
+    /// has no effect.
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Returns true once the signal has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the signal has been triggered.
+    pub(crate) async fn triggered(&self) {
+        // the sender lives as long as `self`, waiting cannot fail
+        let _ = self.0.subscribe().wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// The subscription params of a data channel, shared between its handle and its background task.
+///
+/// The task subscribes and unsubscribes the live connection whenever the set changes, and
+/// subscribes to the whole set again after a reconnect.
+#[derive(Clone, Debug)]
+pub(crate) struct Subscriptions(Arc<watch::Sender<Vec<String>>>);
+
+impl Subscriptions {
+    /// Creates the set from comma separated params, e.g. `"XT.BTC-USD,XT.ETH-USD"`.
+    pub(crate) fn new(params: &str) -> Self {
+        Subscriptions(Arc::new(watch::channel(split_params(params)).0))
+    }
+
+    pub(crate) fn current(&self) -> Vec<String> {
+        self.0.borrow().clone()
+    }
+
+    /// Adds the params not subscribed yet.
+    pub(crate) fn add<S: AsRef<str>>(&self, params: &[S]) {
+        self.0.send_if_modified(|current| {
+            let before = current.len();
+            for param in params {
+                if !current.iter().any(|p| p == param.as_ref()) {
+                    current.push(param.as_ref().to_string());
+                }
+            }
+            current.len() != before
+        });
+    }
+
+    /// Removes the params that are subscribed.
+    pub(crate) fn remove<S: AsRef<str>>(&self, params: &[S]) {
+        self.0.send_if_modified(|current| {
+            let before = current.len();
+            current.retain(|p| !params.iter().any(|param| param.as_ref() == p));
+            current.len() != before
+        });
+    }
+
+    fn watch(&self) -> watch::Receiver<Vec<String>> {
+        self.0.subscribe()
+    }
+}
+
+/// Splits comma separated subscription params, ignoring blanks.
+pub(crate) fn split_params(params: &str) -> Vec<String> {
+    params
+        .split(',')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// A handle to the background task reading a data channel.
+///
+/// Awaiting the handle resolves to the outcome of the task once it exits: `Ok(())` if the
+/// receiver was dropped or the stream was shut down, or the error that ended the stream once
+/// reconnection gave up.
+pub struct StreamHandle {
+    task: JoinHandle<Result<(), StreamError>>,
+    health: Arc<HealthMonitor>,
+    shutdown: ShutdownSignal,
+    subscriptions: Subscriptions,
+}
+
+impl StreamHandle {
+    pub(crate) fn new(
+        task: JoinHandle<Result<(), StreamError>>,
+        health: Arc<HealthMonitor>,
+        shutdown: ShutdownSignal,
+        subscriptions: Subscriptions,
+    ) -> Self {
+        StreamHandle {
+            task,
+            health,
+            shutdown,
+            subscriptions,
+        }
+    }
+
+    /// Subscribes the running connection to more comma separated `params`, e.g.
+    /// `"XT.BTC-USD,XT.ETH-USD"`, without reconnecting. They are kept across reconnects.
+    /// Has no effect on a replay.
+    pub fn subscribe(&self, params: &str) {
+        self.subscriptions.add(&split_params(params));
+    }
+
+    /// Unsubscribes the running connection from comma separated `params`.
+    pub fn unsubscribe(&self, params: &str) {
+        self.subscriptions.remove(&split_params(params));
+    }
+
+    /// Returns the params the connection is subscribed to.
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.subscriptions.current()
+    }
+
+    pub(crate) fn shared_subscriptions(&self) -> Subscriptions {
+        self.subscriptions.clone()
+    }
+
+    pub(crate) fn health_monitor(&self) -> Arc<HealthMonitor> {
+        self.health.clone()
+    }
+
+    /// Stops the stream gracefully: the task unsubscribes, sends a close frame, finishes the
+    /// recording if one is configured and exits. Resolves to the outcome of the task once it has
+    /// exited; events already queued can still be read from the stream.
+    pub async fn shutdown(self) -> Result<(), StreamError> {
+        self.shutdown.trigger();
+        self.await
+    }
+
+    /// Returns a signal that stops the stream like `shutdown` when triggered, without giving up
+    /// the handle.
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.shutdown.clone()
+    }
+
+    /// Returns the current health metrics of the connection.
+    pub fn health(&self) -> HealthSnapshot {
+        self.health.snapshot()
+    }
+
+    /// Returns true if the background task has exited.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Aborts the background task without closing the connection cleanly.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+impl Future for StreamHandle {
+    type Output = Result<(), StreamError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task)
+            .poll(cx)
+            .map(|result| match result {
+                Ok(outcome) => outcome,
+                Err(e) => Err(StreamError::Task(e.to_string())),
+            })
+    }
+}
+
+/// Connects to the cluster at `url`, or the URL configured to override it, authenticates and
+/// subscribes to `params`, then forwards every decoded event to the returned stream from a
+/// background task.
+///
+/// Connect and authentication failures are returned directly; anything that goes wrong later is
+/// delivered through the stream and reported by the returned handle. Lost or stale connections are
+/// re-established as configured.
+pub(crate) async fn open_data_channel<E: FeedEvent>(
+    url: &str,
+    api_key: &str,
+    params: &str,
+    config: StreamConfig,
+) -> Result<(EventStream<E>, StreamHandle), StreamError> {
+    let url = config.url.as_deref().unwrap_or(url);
+    let ws_stream = connect(url, api_key, params).await?;
+    let health = Arc::new(HealthMonitor::new());
+    health.on_connected(false);
+    let (tx, rx) = backpressure::channel(config.output_channel_size, config.backpressure);
+    let shutdown = ShutdownSignal::new();
+    let subscriptions = Subscriptions::new(params);
+    let session = Session {
+        url: url.to_string(),
+        api_key: api_key.to_string(),
+        subscriptions: subscriptions.clone(),
+        config,
+        health: health.clone(),
+        shutdown: shutdown.clone(),
+    };
+    let task = tokio::task::spawn(session.run(ws_stream, subscriptions.current(), tx));
+    Ok((
+        EventStream::new(rx),
+        StreamHandle::new(task, health, shutdown, subscriptions),
+    ))
+}
+
+/// Connects to the server and performs the connect/auth handshake, then subscribes to `params`.
+pub(crate) async fn connect(
+    url: &str,
+    api_key: &str,
+    params: &str,
+) -> Result<WsStream, StreamError> {
+    let (mut ws_stream, _) = connect_async(url).await?;
+    info!("Connected to {}", url);
+
+    let status = next_status(&mut ws_stream).await?;
+    if status.status != Status::Connected {
+        return Err(StreamError::Handshake(format!(
+            "Expected connected status, got {:?}: {}",
+            status.status, status.message
+        )));
+    }
+
+    // Authenticate
+    let key = format!(r#"{{"action":"auth","params":"{}"}}"#, api_key);
+    ws_stream.send(Message::Text(key.into())).await?;
+    info!("Sent auth message");
+
+    let status = next_status(&mut ws_stream).await?;
+    match status.status {
+        Status::AuthSuccess => info!("Authenticated"),
+        Status::AuthFailed => return Err(StreamError::AuthFailed(status.message)),
+        other => {
+            return Err(StreamError::Handshake(format!(
+                "Expected auth status, got {:?}: {}",
+                other, status.message
+            )))
+        }
+    }
+
+    // Subscribe
+    if !params.is_empty() {
+        send_action(&mut ws_stream, "subscribe", params).await?;
+    }
+
+    Ok(ws_stream)
+}
+
+/// Sends a subscribe or unsubscribe message for comma separated `params`.
+async fn send_action(
+    ws_stream: &mut WsStream,
+    action: &str,
+    params: &str,
+) -> Result<(), StreamError> {
+    let msg = format!(r#"{{"action":"{}","params":"{}"}}"#, action, params);
+    ws_stream.send(Message::Text(msg.clone().into())).await?;
+    info!("Sent {} message: {}", action, msg);
+    Ok(())
+}
+
+/// Reads frames until the next status message, skipping control frames.
+async fn next_status(ws_stream: &mut WsStream) -> Result<StatusEvent, StreamError> {
+    loop {
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(text))) => {
+                let statuses: Vec<StatusEvent> = serde_json::from_str(&text)
+                    .map_err(|e| StreamError::Handshake(format!("{}: {}", e, text)))?;
+                return statuses
+                    .into_iter()
+                    .next()
+                    .ok_or(StreamError::Handshake("Empty status message".to_string()));
+            }
+            Some(Ok(Message::Close(_))) | None => {
+                return Err(StreamError::Handshake(
+                    "Connection closed during handshake".to_string(),
+                ))
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+/// The state of the background task of a data channel.
+struct Session {
+    url: String,
+    api_key: String,
+    subscriptions: Subscriptions,
+    config: StreamConfig,
+    health: Arc<HealthMonitor>,
+    shutdown: ShutdownSignal,
+}
+
+impl Session {
+    /// Reads events until the receiver is dropped or the stream is shut down, reconnecting
+    /// whenever the connection is lost. The recording, if any, is finished on exit.
+    async fn run<E: FeedEvent>(
+        self,
+        ws_stream: WsStream,
+        subscribed: Vec<String>,
+        tx: Sender<E>,
+    ) -> Result<(), StreamError> {
+        let outcome = self.read_and_reconnect(ws_stream, subscribed, &tx).await;
+        if let Some(recorder) = self.config.recorder.clone() {
+            // finishing waits for the writer thread to complete the file
+            match tokio::task::spawn_blocking(move || recorder.finish()).await {
+                Ok(Err(e)) => warn!("Failed to finish recording: {}", e),
+                Err(e) => warn!("Failed to finish recording: {}", e),
+                Ok(Ok(())) => {}
+            }
+        }
+        outcome
+    }
+
+    async fn read_and_reconnect<E: FeedEvent>(
+        &self,
+        mut ws_stream: WsStream,
+        mut subscribed: Vec<String>,
+        tx: &Sender<E>,
+    ) -> Result<(), StreamError> {
+        loop {
+            let error = match self.read_events(&mut ws_stream, subscribed, tx).await {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            self.health.on_disconnected();
+            warn!("{}", error);
+            // the receiver may already be gone, the handle still reports the error
+            if tx.send(Err(error.clone())).await.is_err() {
+                return Ok(());
+            }
+            (ws_stream, subscribed) = tokio::select! {
+                reconnected = self.reconnect(error, tx) => reconnected?,
+                _ = self.shutdown.triggered() => {
+                    info!("Shut down while reconnecting");
+                    return Ok(());
+                }
+            };
+        }
+    }
+
+    /// Unsubscribes and closes the connection with a close frame, waiting briefly for the server
+    /// to complete the closing handshake.
+    async fn close(&self, ws_stream: &mut WsStream, subscribed: &[String]) {
+        if !subscribed.is_empty() {
+            if let Err(e) = send_action(ws_stream, "unsubscribe", &subscribed.join(",")).await {
+                warn!("Failed to unsubscribe: {}", e);
+            }
+        }
+        if let Err(e) = ws_stream.close(None).await {
+            warn!("Failed to send close frame: {}", e);
+        }
+        // the connection is done once the server echoes the close frame
+        let drained = timeout(CLOSE_TIMEOUT, async {
+            while let Some(Ok(_)) = ws_stream.next().await {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!("Server did not acknowledge the close frame");
+        }
+        self.health.on_disconnected();
+        info!("Closed connection to {}", self.url);
+    }
+
+    /// Re-establishes the connection with exponential backoff, giving up after the configured
+    /// number of attempts or on authentication failure. Returns the connection along with the
+    /// params it is subscribed to.
+    async fn reconnect<E: FeedEvent>(
+        &self,
+        mut error: StreamError,
+        tx: &Sender<E>,
+    ) -> Result<(WsStream, Vec<String>), StreamError> {
+        let mut delay = self.config.reconnect_delay;
+        for attempt in 1..=self.config.reconnect_attempts {
+            if tx.is_closed() {
+                break;
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(self.config.max_reconnect_delay);
+            info!("Reconnecting to {} (attempt {})", self.url, attempt);
+            let subscribed = self.subscriptions.current();
+            match connect(&self.url, &self.api_key, &subscribed.join(",")).await {
+                Ok(ws_stream) => {
+                    self.health.on_connected(true);
+                    return Ok((ws_stream, subscribed));
+                }
+                Err(e @ StreamError::AuthFailed(_)) => return Err(e),
+                Err(e) => {
+                    warn!("Reconnection failed: {}", e);
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Subscribes to the params in `wanted` that are not in `subscribed` and unsubscribes from
+    /// those no longer wanted.
+    async fn resubscribe(
+        &self,
+        ws_stream: &mut WsStream,
+        subscribed: &mut Vec<String>,
+        wanted: Vec<String>,
+    ) -> Result<(), StreamError> {
+        let added: Vec<&str> = wanted
+            .iter()
+            .filter(|param| !subscribed.contains(param))
+            .map(String::as_str)
+            .collect();
+        let removed: Vec<&str> = subscribed
+            .iter()
+            .filter(|param| !wanted.contains(param))
+            .map(String::as_str)
+            .collect();
+        if !added.is_empty() {
+            send_action(ws_stream, "subscribe", &added.join(",")).await?;
+        }
+        if !removed.is_empty() {
+            send_action(ws_stream, "unsubscribe", &removed.join(",")).await?;
+        }
+        *subscribed = wanted;
+        Ok(())
+    }
+
+    /// Forwards the events of every frame to `tx`, sending pings, watching for an idle
+    /// connection and keeping the subscriptions in sync. Returns `Ok` once the receiver is
+    /// dropped, or the error that ended the connection.
+    async fn read_events<E: FeedEvent>(
+        &self,
+        ws_stream: &mut WsStream,
+        mut subscribed: Vec<String>,
+        tx: &Sender<E>,
+    ) -> Result<(), StreamError> {
+        // the params may have changed while connecting, check them right away
+        let mut changes = self.subscriptions.watch();
+        changes.mark_changed();
+        // disabled timers still need a period, their branches are never polled
+        let mut ping = interval(
+            self.config
+                .ping_interval
+                .unwrap_or(Duration::from_secs(3600)),
+        );
+        let idle_timeout = self.config.idle_timeout;
+        let mut watchdog = interval(
+            idle_timeout
+                .map(|timeout| (timeout / 4).max(Duration::from_millis(100)))
+                .unwrap_or(Duration::from_secs(3600)),
+        );
+        loop {
+            tokio::select! {
+                _ = self.shutdown.triggered() => {
+                    self.close(ws_stream, &subscribed).await;
+                    return Ok(());
+                }
+                message = ws_stream.next() => {
+                    let message = match message {
+                        Some(Ok(message)) => message,
+                        Some(Err(e)) => return Err(e.into()),
+                        None => return Err(StreamError::Closed),
+                    };
+                    self.health.on_frame();
+                    match message {
+                        Message::Text(text) => {
+                            if let Some(recorder) = &self.config.recorder {
+                                if let Err(e) = recorder.record(Utc::now(), &text) {
+                                    warn!("Failed to record frame: {}", e);
+                                }
+                            }
+                            // a full queue must not hold up the shutdown under `Block`
+                            tokio::select! {
+                                forwarded = forward_frame(&text, tx, &self.health) => {
+                                    if forwarded.is_err() {
+                                        info!("Receiver dropped");
+                                        return Ok(());
+                                    }
+                                }
+                                _ = self.shutdown.triggered() => {
+                                    self.close(ws_stream, &subscribed).await;
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        Message::Close(_) => return Err(StreamError::Closed),
+                        _ => {}
+                    }
+                }
+                Ok(()) = changes.changed() => {
+                    let wanted = changes.borrow_and_update().clone();
+                    self.resubscribe(ws_stream, &mut subscribed, wanted).await?;
+                }
+                _ = ping.tick(), if self.config.ping_interval.is_some() => {
+                    ws_stream.send(Message::Ping(Default::default())).await?;
+                }
+                _ = watchdog.tick(), if idle_timeout.is_some() => {
+                    let idle = self.health.idle_for();
+                    let market_open = self
+                        .config
+                        .market_hours
+                        .as_ref()
+                        .is_none_or(|hours| hours.is_open(Utc::now()));
+                    if market_open && idle_timeout.is_some_and(|timeout| idle >= timeout) {
+                        return Err(StreamError::Idle(idle));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Decodes a text frame and queues its events, keeping the health counters up to date.
+pub(crate) async fn forward_frame<E: FeedEvent>(
+    text: &str,
+    tx: &Sender<E>,
+    health: &HealthMonitor,
+) -> Result<(), Disconnected> {
+    for event in decode_frame::<E>(text) {
+        match &event {
+            Ok(_) => health.on_event(),
+            Err(_) => health.on_parse_error(),
+        }
+        match tx.send(event).await? {
+            SendOutcome::Queued => {}
+            SendOutcome::Dropped => health.on_dropped(),
+            SendOutcome::Conflated => health.on_conflated(),
+        }
+    }
+    Ok(())
+}
+
+/// The `ev` field of a frame element, read without decoding the rest of it.
+#[derive(Deserialize)]
+struct EventTag<'a> {
+    #[serde(borrow)]
+    ev: Cow<'a, str>,
+}
+
+/// Decodes every element of a text frame, which the server sends as a JSON array.
+///
+/// The elements are deserialized directly from the frame text into their event types, without
+/// building an intermediate `serde_json::Value`.
+pub fn decode_frame<E: FeedEvent>(text: &str) -> Vec<Result<E, StreamError>> {
+    let elements: Vec<&RawValue> = match serde_json::from_str(text) {
+        Ok(elements) => elements,
+        Err(e) => return vec![Err(StreamError::Decode(format!("{}: {}", e, text)))],
+    };
+    elements
+        .into_iter()
+        .map(|element| {
+            let json = element.get();
+            event_type(json)
+                .and_then(|event_type| E::decode(&event_type, json))
+                .map_err(|e| StreamError::Decode(format!("{}: {}", e, json)))
+        })
+        .collect()
+}
+
+/// Reads the `ev` field of a frame element. Polygon sends it first, which is checked without
+/// parsing the element; anything else falls back to a full parse.
+fn event_type(json: &str) -> anyhow::Result<Cow<'_, str>> {
+    if let Some(rest) = json.strip_prefix(r#"{"ev":""#) {
+        if let Some((event_type, _)) = rest.split_once('"') {
+            if !event_type.contains('\\') {
+                return Ok(Cow::Borrowed(event_type));
+            }
+        }
+    }
+    Ok(serde_json::from_str::<EventTag>(json)?.ev)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::crypto::CryptoEvent;
+    use crate::websocket::forex::ForexEvent;
+
+    const TRADE: &str = r#"{"ev":"XT","pair":"BTC-USD","p":33021.9,"t":1610462007425,"s":0.01616617,"c":[2],"i":"14272084","x":1,"r":1610462007576}"#;
+
+    #[test]
+    fn reads_a_leading_event_type_without_parsing() {
+        assert!(matches!(event_type(TRADE).unwrap(), Cow::Borrowed("XT")));
+        assert!(matches!(
+            event_type(r#"{"ev":"status","status":"connected"}"#).unwrap(),
+            Cow::Borrowed("status")
+        ));
+    }
+
+    #[test]
+    fn parses_the_element_when_the_event_type_is_not_first() {
+        let json = r#"{"pair":"BTC-USD","ev":"XT"}"#;
+        assert_eq!(event_type(json).unwrap(), "XT");
+        let json = r#"{ "ev" : "XT" }"#;
+        assert_eq!(event_type(json).unwrap(), "XT");
+        // escapes are decoded by the parser
+        let json = r#"{"ev":"X\u0054"}"#;
+        assert!(matches!(event_type(json).unwrap(), Cow::Owned(ref ev) if ev == "XT"));
+        assert!(event_type(r#"{"pair":"BTC-USD"}"#).is_err());
+    }
+
+    #[test]
+    fn decodes_both_paths_alike() {
+        let reordered = r#"{"pair":"BTC-USD","p":33021.9,"t":1610462007425,"s":0.01616617,"c":[2],"i":"14272084","x":1,"r":1610462007576,"ev":"XT"}"#;
+        let events = decode_frame::<CryptoEvent>(&format!("[{},{}]", TRADE, reordered));
+        assert_eq!(events.len(), 2);
+        for event in events {
+            let Ok(CryptoEvent::Trade(trade)) = event else {
+                panic!("not a trade: {:?}", event);
+            };
+            assert_eq!(trade.pair, "BTC-USD");
+            assert_eq!(trade.price, 33021.9);
+            assert_eq!(trade.trade_id, "14272084");
+        }
+    }
+
+    #[test]
+    fn reports_bad_elements_without_losing_the_others() {
+        let frame = format!(
+            r#"[{},{{"ev":"XZ"}},{{"pair":"BTC-USD"}},{{"ev":"XT","p":"high"}}]"#,
+            TRADE
+        );
+        let events = decode_frame::<CryptoEvent>(&frame);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], Ok(CryptoEvent::Trade(_))));
+        for event in &events[1..] {
+            assert!(matches!(event, Err(StreamError::Decode(_))), "{:?}", event);
+        }
+    }
+
+    #[test]
+    fn reports_a_frame_that_is_not_an_array() {
+        let events = decode_frame::<CryptoEvent>(TRADE);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], Err(StreamError::Decode(_))));
+        let events = decode_frame::<CryptoEvent>(r#"[{"ev":"XT""#);
+        assert!(matches!(events[..], [Err(StreamError::Decode(_))]));
+    }
+
+    #[test]
+    fn lends_the_forex_pair_as_symbol() {
+        let events = decode_frame::<ForexEvent>(
+            r#"[{"ev":"C","p":"EUR/USD","x":48,"a":1.0825,"b":1.0824,"t":1705330800123}]"#,
+        );
+        let Ok(event) = &events[0] else {
+            panic!("not decoded: {:?}", events[0]);
+        };
+        assert!(matches!(event.symbol(), Some(Cow::Borrowed("EUR/USD"))));
+        let ForexEvent::Quote(quote) = event else {
+            panic!("not a quote: {:?}", event);
+        };
+        assert_eq!(quote.pair.base, "EUR");
+        assert_eq!(quote.pair.quote, "USD");
+        assert_eq!(quote.pair.to_string(), "EUR/USD");
+    }
+}
@@ -1,97 +1,131 @@
+use crate::exchange::registry::{exchange_name, AssetClass};
 use crate::util::TimeUtil;
+use crate::websocket::config::StreamConfig;
+use crate::websocket::connection::{self, EventStream, StreamError, StreamHandle};
+use crate::websocket::pool::{self, PoolConfig, PoolHandle};
+use crate::websocket::symbol::Symbol;
+use crate::websocket::{FeedEvent, StatusEvent};
 use chrono::{DateTime, Utc};
-use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::Value;
-use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::Message;
+use std::borrow::Cow;
+use std::fmt;
 
+use crate::common::conditions::CryptoCondition;
+use crate::common::instrument::Instrument;
 use crate::common::trade::{Trade, TradeData};
-use tracing::info;
 
-pub struct Crypto {
-    api_key: String,
-}
+pub struct Crypto {}
 
 impl Crypto {
     pub async fn open_data_channel(
         api_key: String,
         pairs: String,
         output_channel_size: usize,
-    ) -> Receiver<CryptoTradeEvent> {
-        let (mut tx, rx): (Sender<CryptoTradeEvent>, Receiver<CryptoTradeEvent>) =
-            mpsc::channel(output_channel_size);
-
-        tokio::task::spawn(async move {
-            let url = "wss://socket.polygon.io/crypto";
-
-            // Connect to the server
-            let (mut ws_stream, _) = connect_async(url)
-                .await
-                .expect("Could not connect to the server");
-
-            info!("Connected to {}", url);
-
-            // Authenticate
-            let key = format!(r#"{{"action":"auth","params":"{}"}}"#, &api_key);
-            ws_stream
-                .send(Message::Text(key.clone()))
-                .await
-                .expect("Failed to send auth message");
-            info!("Sent auth message: {}", key);
-            // Subscribe
-            let sub_msg = format!(r#"{{"action":"subscribe", "params":"{}"}}"#, pairs);
-            ws_stream
-                .send(Message::Text(sub_msg.clone()))
-                .await
-                .expect("Failed to send subscribe message");
-            println!("Sent subscribe message: {}", sub_msg);
-
-            loop {
-                let msg = ws_stream.next().await;
-                match msg {
-                    Some(Ok(message)) => {
-                        info!("Received: {:?}", message);
-                        match &message {
-                            Message::Text(text) => {
-                                let value: Value = serde_json::from_str(&message.to_string())
-                                    .expect("Failed to parse the message");
-                                // value is a JSON array, convert it to a Vec<Value>
-                                let values = match value.as_array() {
-                                    Some(values) => values,
-                                    None => {
-                                        println!("Error: value is not an array");
-                                        continue;
-                                    }
-                                };
-                                for value in values {
-                                    info!("Value: {}", value);
-                                    match CryptoTradeEvent::from_value(&value) {
-                                        Ok(crypto_trade_event) => {
-                                            if tx.send(crypto_trade_event.clone()).await.is_err() {
-                                                eprintln!("Receiver dropped!");
-                                                break;
-                                            }
-                                        }
-                                        Err(e) => {
-                                            println!("Error: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    Some(Err(e)) => {
-                        println!("Error: {}", e);
-                        break;
-                    }
-                    None => break,
-                }
+    ) -> Result<(EventStream<CryptoEvent>, StreamHandle), StreamError> {
+        Self::open_data_channel_with_config(
+            api_key,
+            pairs,
+            StreamConfig::with_channel_size(output_channel_size),
+        )
+        .await
+    }
+
+    /// Opens a channel of events like `open_data_channel`, with custom heartbeat, idle detection
+    /// and reconnection settings.
+    pub async fn open_data_channel_with_config(
+        api_key: String,
+        pairs: String,
+        config: StreamConfig,
+    ) -> Result<(EventStream<CryptoEvent>, StreamHandle), StreamError> {
+        connection::open_data_channel("wss://socket.polygon.io/crypto", &api_key, &pairs, config)
+            .await
+    }
+
+    /// Opens a pool of connections sharing the subscriptions in `pairs`, merged into one stream,
+    /// see `PoolConfig`.
+    pub async fn open_pool(
+        api_key: String,
+        pairs: String,
+        config: PoolConfig,
+    ) -> Result<(EventStream<CryptoEvent>, PoolHandle), StreamError> {
+        pool::open_pool("wss://socket.polygon.io/crypto", &api_key, &pairs, config).await
+    }
+}
+
+/// All events that can be received from the crypto cluster.
+#[derive(Clone, Debug)]
+pub enum CryptoEvent {
+    /// A trade (`XT`).
+    Trade(CryptoTradeEvent),
+    /// A top of book quote (`XQ`).
+    Quote(CryptoQuoteEvent),
+    /// A per-minute aggregate (`XA`).
+    MinuteAggregate(CryptoAggregateEvent),
+    /// A per-second aggregate (`XAS`).
+    SecondAggregate(CryptoAggregateEvent),
+    /// A level 2 book update (`XL2`).
+    Level2(CryptoLevel2Event),
+    /// A fair market value update (`FMV`).
+    FairMarketValue(FairMarketValueEvent),
+    /// A connection, authentication or subscription status message (`status`).
+    Status(StatusEvent),
+}
+
+impl FeedEvent for CryptoEvent {
+    fn decode(event_type: &str, json: &str) -> anyhow::Result<Self> {
+        let event = match event_type {
+            "XT" => CryptoEvent::Trade(serde_json::from_str(json)?),
+            "XQ" => CryptoEvent::Quote(serde_json::from_str(json)?),
+            "XA" => CryptoEvent::MinuteAggregate(serde_json::from_str(json)?),
+            "XAS" => CryptoEvent::SecondAggregate(serde_json::from_str(json)?),
+            "XL2" => CryptoEvent::Level2(serde_json::from_str(json)?),
+            "FMV" => CryptoEvent::FairMarketValue(serde_json::from_str(json)?),
+            "status" => CryptoEvent::Status(serde_json::from_str(json)?),
+            other => {
+                return Err(anyhow::Error::msg(format!(
+                    "Unknown crypto event type: {}",
+                    other
+                )))
             }
-        });
-        rx
+        };
+        Ok(event)
+    }
+
+    fn event_type(&self) -> &str {
+        match self {
+            CryptoEvent::Trade(event) => &event.event_type,
+            CryptoEvent::Quote(event) => &event.event_type,
+            CryptoEvent::MinuteAggregate(event) => &event.event_type,
+            CryptoEvent::SecondAggregate(event) => &event.event_type,
+            CryptoEvent::Level2(event) => &event.event_type,
+            CryptoEvent::FairMarketValue(event) => &event.event_type,
+            CryptoEvent::Status(event) => &event.event_type,
+        }
+    }
+
+    fn symbol(&self) -> Option<Cow<'_, str>> {
+        match self {
+            CryptoEvent::Trade(event) => Some(Cow::Borrowed(&event.pair)),
+            CryptoEvent::Quote(event) => Some(Cow::Borrowed(&event.pair)),
+            CryptoEvent::MinuteAggregate(event) => Some(Cow::Borrowed(&event.pair)),
+            CryptoEvent::SecondAggregate(event) => Some(Cow::Borrowed(&event.pair)),
+            CryptoEvent::Level2(event) => Some(Cow::Borrowed(&event.pair)),
+            CryptoEvent::FairMarketValue(event) => Some(Cow::Borrowed(&event.symbol)),
+            CryptoEvent::Status(_) => None,
+        }
+    }
+
+    fn exchange_id(&self) -> Option<i64> {
+        match self {
+            CryptoEvent::Trade(event) => Some(event.exchange_id),
+            CryptoEvent::Quote(event) => Some(event.exchange_id),
+            CryptoEvent::Level2(event) => Some(event.exchange_id),
+            CryptoEvent::MinuteAggregate(_)
+            | CryptoEvent::SecondAggregate(_)
+            | CryptoEvent::FairMarketValue(_)
+            | CryptoEvent::Status(_) => None,
+        }
     }
 }
 
@@ -99,63 +133,352 @@ impl Crypto {
 #[derive(Clone, Debug, Deserialize)]
 pub struct CryptoTradeEvent {
     #[serde(rename = "ev")]
-    event_type: String,
+    pub event_type: Symbol,
 
     #[serde(rename = "pair")]
-    pair: String,
+    pub pair: Symbol,
 
     #[serde(rename = "p")]
-    price: f64,
+    pub price: f64,
 
     #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
-    timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
 
     #[serde(rename = "s")]
-    size: f64,
+    pub size: f64,
 
-    #[serde(rename = "c")]
-    conditions: Vec<i32>,
+    #[serde(rename = "c", default)]
+    pub conditions: Vec<i32>,
 
     #[serde(rename = "i")]
-    trade_id: String,
+    pub trade_id: String,
 
     #[serde(rename = "x")]
-    exchange_id: i64,
+    pub exchange_id: i64,
 
     #[serde(rename = "r", deserialize_with = "TimeUtil::timestamp_milliseconds")]
-    received_timestamp: DateTime<Utc>,
+    pub received_timestamp: DateTime<Utc>,
+}
+
+impl fmt::Display for CryptoTradeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} x {} on {} at {}",
+            self.pair,
+            self.price,
+            self.size,
+            exchange_name(AssetClass::Crypto, self.exchange_id),
+            self.timestamp
+        )
+    }
 }
 
 impl CryptoTradeEvent {
     pub fn from_value(value: &Value) -> anyhow::Result<Self> {
         if value["ev"] == "XT" {
-            return serde_json::from_value(value.clone()).map_err(|e| anyhow::Error::msg(e));
+            return serde_json::from_value(value.clone()).map_err(anyhow::Error::msg);
         }
         Err(anyhow::Error::msg("Not a crypto trade event"))
     }
+
+    /// The conditions of the trade, decoded.
+    pub fn crypto_conditions(&self) -> impl Iterator<Item = CryptoCondition> + '_ {
+        self.conditions
+            .iter()
+            .map(|id| CryptoCondition::from_id(*id))
+    }
 }
 
 impl Trade for CryptoTradeEvent {
-    fn get_trade(&self) -> anyhow::Result<TradeData> {
-        // split self.pair by hyphen
-        let pair_split = self.pair.split('-').collect::<Vec<&str>>();
-        let symbol = pair_split
-            .get(0)
-            .ok_or(anyhow::Error::msg("No symbol"))?
-            .to_string();
-        let currency = pair_split
-            .get(1)
-            .ok_or(anyhow::Error::msg("No currency"))?
-            .to_string();
-        let price = self.price;
-        let timestamp = self.timestamp;
-        let exchange_id = self.exchange_id;
+    fn get_trade(&self) -> anyhow::Result<TradeData<'_>> {
         Ok(TradeData {
-            symbol,
-            currency,
-            price,
-            timestamp,
-            exchange_id,
+            instrument: Instrument::from_feed(AssetClass::Crypto, &self.pair)?,
+            price: self.price,
+            size: self.size,
+            timestamp: self.timestamp,
+            exchange_id: self.exchange_id,
+            trade_id: Some(&self.trade_id),
+            conditions: &self.conditions,
+            tape: None,
+            received_at: Some(self.received_timestamp),
         })
     }
+
+    fn asset_class(&self) -> AssetClass {
+        AssetClass::Crypto
+    }
+}
+
+/// Represents a crypto top of book quote event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CryptoQuoteEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "pair")]
+    pub pair: Symbol,
+
+    #[serde(rename = "bp")]
+    pub bid_price: f64,
+
+    #[serde(rename = "bs")]
+    pub bid_size: f64,
+
+    #[serde(rename = "ap")]
+    pub ask_price: f64,
+
+    #[serde(rename = "as")]
+    pub ask_size: f64,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(rename = "x")]
+    pub exchange_id: i64,
+
+    #[serde(rename = "r", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub received_timestamp: DateTime<Utc>,
+}
+
+impl fmt::Display for CryptoQuoteEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} x {} / {} x {} on {} at {}",
+            self.pair,
+            self.bid_price,
+            self.bid_size,
+            self.ask_price,
+            self.ask_size,
+            exchange_name(AssetClass::Crypto, self.exchange_id),
+            self.timestamp
+        )
+    }
+}
+
+/// Represents a crypto aggregate (per minute or per second) event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CryptoAggregateEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "pair")]
+    pub pair: Symbol,
+
+    #[serde(rename = "o")]
+    pub open_price: f64,
+
+    #[serde(rename = "c")]
+    pub close_price: f64,
+
+    #[serde(rename = "h")]
+    pub highest_price: f64,
+
+    #[serde(rename = "l")]
+    pub lowest_price: f64,
+
+    #[serde(rename = "v")]
+    pub volume: f64,
+
+    #[serde(rename = "vw")]
+    pub volume_weighted_avg_price: f64,
+
+    /// The average trade size in the aggregate window.
+    #[serde(rename = "z", default)]
+    pub average_trade_size: Option<f64>,
+
+    #[serde(rename = "s", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub start_timestamp: DateTime<Utc>,
+
+    #[serde(rename = "e", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub end_timestamp: DateTime<Utc>,
+}
+
+/// A single price level of a level 2 book update, sent by the server as `[price, size]`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(from = "(f64, f64)")]
+pub struct PriceLevel {
+    pub price: f64,
+    pub size: f64,
+}
+
+impl From<(f64, f64)> for PriceLevel {
+    fn from((price, size): (f64, f64)) -> Self {
+        PriceLevel { price, size }
+    }
+}
+
+/// Represents a crypto level 2 book update event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CryptoLevel2Event {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "pair")]
+    pub pair: Symbol,
+
+    #[serde(rename = "b", default)]
+    pub bids: Vec<PriceLevel>,
+
+    #[serde(rename = "a", default)]
+    pub asks: Vec<PriceLevel>,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(rename = "x")]
+    pub exchange_id: i64,
+
+    #[serde(rename = "r", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub received_timestamp: DateTime<Utc>,
+}
+
+/// Represents a fair market value event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FairMarketValueEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "sym")]
+    pub symbol: Symbol,
+
+    #[serde(rename = "fmv")]
+    pub fair_market_value: f64,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::connection::decode_frame;
+    use chrono::TimeZone;
+
+    /// Decodes a frame holding a single event.
+    fn decode(frame: &str) -> CryptoEvent {
+        let mut events = decode_frame::<CryptoEvent>(frame);
+        assert_eq!(events.len(), 1);
+        events.pop().unwrap().unwrap()
+    }
+
+    fn millis(ms: i64) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(ms).unwrap()
+    }
+
+    #[test]
+    fn decodes_trade() {
+        let event = decode(
+            r#"[{"ev":"XT","pair":"BTC-USD","p":33021.9,"t":1610462007425,"s":0.01616617,"c":[2],"i":"14272084","x":1,"r":1610462007576}]"#,
+        );
+        let CryptoEvent::Trade(trade) = event else {
+            panic!("not a trade: {:?}", event);
+        };
+        assert_eq!(trade.pair, "BTC-USD");
+        assert_eq!(trade.price, 33021.9);
+        assert_eq!(trade.size, 0.01616617);
+        assert_eq!(trade.conditions, vec![2]);
+        assert_eq!(trade.trade_id, "14272084");
+        assert_eq!(trade.exchange_id, 1);
+        assert_eq!(trade.timestamp, millis(1610462007425));
+        assert_eq!(trade.received_timestamp, millis(1610462007576));
+    }
+
+    #[test]
+    fn decodes_quote() {
+        let event = decode(
+            r#"[{"ev":"XQ","pair":"BTC-USD","bp":33052.79,"bs":0.48,"ap":33073.19,"as":0.601,"t":1610462411115,"x":1,"r":1610462411128}]"#,
+        );
+        let CryptoEvent::Quote(quote) = event else {
+            panic!("not a quote: {:?}", event);
+        };
+        assert_eq!(quote.pair, "BTC-USD");
+        assert_eq!(quote.bid_price, 33052.79);
+        assert_eq!(quote.bid_size, 0.48);
+        assert_eq!(quote.ask_price, 33073.19);
+        assert_eq!(quote.ask_size, 0.601);
+        assert_eq!(quote.exchange_id, 1);
+        assert_eq!(quote.timestamp, millis(1610462411115));
+        assert_eq!(quote.received_timestamp, millis(1610462411128));
+    }
+
+    #[test]
+    fn decodes_minute_aggregate() {
+        let event = decode(
+            r#"[{"ev":"XA","pair":"BCD-BTC","v":951.6112,"vw":0.0000105,"z":0,"o":0.0000105,"c":0.0000105,"h":0.0000105,"l":0.0000105,"s":1610463240000,"e":1610463300000}]"#,
+        );
+        let CryptoEvent::MinuteAggregate(aggregate) = event else {
+            panic!("not a minute aggregate: {:?}", event);
+        };
+        assert_eq!(aggregate.pair, "BCD-BTC");
+        assert_eq!(aggregate.volume, 951.6112);
+        assert_eq!(aggregate.volume_weighted_avg_price, 0.0000105);
+        assert_eq!(aggregate.average_trade_size, Some(0.0));
+        assert_eq!(aggregate.open_price, 0.0000105);
+        assert_eq!(aggregate.start_timestamp, millis(1610463240000));
+        assert_eq!(aggregate.end_timestamp, millis(1610463300000));
+    }
+
+    #[test]
+    fn decodes_second_aggregate() {
+        let event = decode(
+            r#"[{"ev":"XAS","pair":"BTC-USD","v":0.14125,"vw":38567.4216,"z":0,"o":38567.14,"c":38568.22,"h":38568.22,"l":38567.14,"s":1644435211000,"e":1644435212000}]"#,
+        );
+        let CryptoEvent::SecondAggregate(aggregate) = event else {
+            panic!("not a second aggregate: {:?}", event);
+        };
+        assert_eq!(aggregate.event_type, "XAS");
+        assert_eq!(aggregate.open_price, 38567.14);
+        assert_eq!(aggregate.close_price, 38568.22);
+        assert_eq!(aggregate.highest_price, 38568.22);
+        assert_eq!(aggregate.lowest_price, 38567.14);
+        assert_eq!(
+            aggregate.end_timestamp - aggregate.start_timestamp,
+            chrono::Duration::seconds(1)
+        );
+    }
+
+    #[test]
+    fn decodes_level2() {
+        let event = decode(
+            r#"[{"ev":"XL2","pair":"BTC-USD","t":1598045643409,"r":1598045643428,"x":12,"b":[[11778.71,0.8],[11778.7,0.0]],"a":[[11779.09,0.3],[11779.1,1.5],[11779.17,0.4]]}]"#,
+        );
+        let CryptoEvent::Level2(book) = event else {
+            panic!("not a level 2 update: {:?}", event);
+        };
+        assert_eq!(book.pair, "BTC-USD");
+        assert_eq!(book.exchange_id, 12);
+        assert_eq!(book.bids.len(), 2);
+        assert_eq!(book.asks.len(), 3);
+        assert_eq!(book.bids[0].price, 11778.71);
+        assert_eq!(book.bids[0].size, 0.8);
+        // a zero size removes the level
+        assert_eq!(book.bids[1].size, 0.0);
+        assert_eq!(book.asks[2].price, 11779.17);
+        assert_eq!(book.timestamp, millis(1598045643409));
+    }
+
+    #[test]
+    fn decodes_fair_market_value() {
+        let event = decode(r#"[{"ev":"FMV","fmv":33021.9,"sym":"X:BTCUSD","t":1610462007425}]"#);
+        let CryptoEvent::FairMarketValue(value) = &event else {
+            panic!("not a fair market value: {:?}", event);
+        };
+        assert_eq!(value.symbol, "X:BTCUSD");
+        assert_eq!(value.fair_market_value, 33021.9);
+        assert_eq!(value.timestamp, millis(1610462007425));
+        assert_eq!(event.symbol().as_deref(), Some("X:BTCUSD"));
+        assert_eq!(event.exchange_id(), None);
+    }
+
+    #[test]
+    fn decodes_mixed_frame() {
+        let events = decode_frame::<CryptoEvent>(
+            r#"[{"ev":"status","status":"connected","message":"Connected Successfully"},{"ev":"XQ","pair":"ETH-USD","bp":1.0,"bs":1.0,"ap":1.1,"as":2.0,"t":1610462411115,"x":2,"r":1610462411128},{"ev":"XZ"}]"#,
+        );
+        assert!(matches!(events[0], Ok(CryptoEvent::Status(_))));
+        assert!(matches!(events[1], Ok(CryptoEvent::Quote(_))));
+        assert!(events[2].is_err());
+    }
 }
@@ -0,0 +1,384 @@
+use crate::exchange::registry::{exchange_name, AssetClass};
+use crate::util::TimeUtil;
+use crate::websocket::config::StreamConfig;
+use crate::websocket::connection::{self, EventStream, StreamError, StreamHandle};
+use crate::websocket::pool::{self, PoolConfig, PoolHandle};
+use crate::websocket::symbol::{self, Symbol};
+use crate::websocket::{FeedEvent, StatusEvent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::common::trade::{Trade, TradeData};
+
+pub struct Forex {}
+
+impl Forex {
+    /// Opens a channel of events from the forex cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `pairs` - The subscription parameters, e.g. `C.EUR/USD,CA.*`.
+    /// * `output_channel_size` - The capacity of the returned channel.
+    pub async fn open_data_channel(
+        api_key: String,
+        pairs: String,
+        output_channel_size: usize,
+    ) -> Result<(EventStream<ForexEvent>, StreamHandle), StreamError> {
+        Self::open_data_channel_with_config(
+            api_key,
+            pairs,
+            StreamConfig::with_channel_size(output_channel_size),
+        )
+        .await
+    }
+
+    /// Opens a channel of events like `open_data_channel`, with custom heartbeat, idle detection
+    /// and reconnection settings.
+    pub async fn open_data_channel_with_config(
+        api_key: String,
+        pairs: String,
+        config: StreamConfig,
+    ) -> Result<(EventStream<ForexEvent>, StreamHandle), StreamError> {
+        connection::open_data_channel("wss://socket.polygon.io/forex", &api_key, &pairs, config)
+            .await
+    }
+
+    /// Opens a pool of connections sharing the subscriptions in `pairs`, merged into one stream,
+    /// see `PoolConfig`.
+    pub async fn open_pool(
+        api_key: String,
+        pairs: String,
+        config: PoolConfig,
+    ) -> Result<(EventStream<ForexEvent>, PoolHandle), StreamError> {
+        pool::open_pool("wss://socket.polygon.io/forex", &api_key, &pairs, config).await
+    }
+}
+
+/// A currency pair as written by the forex cluster, e.g. `EUR/USD`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CurrencyPair {
+    /// The currency being priced, e.g. `EUR`.
+    pub base: Symbol,
+    /// The currency the price is expressed in, e.g. `USD`.
+    pub quote: Symbol,
+    /// The pair as written by the cluster, so events can lend it as their symbol.
+    text: Symbol,
+}
+
+impl CurrencyPair {
+    /// The pair of `base` priced in `quote`.
+    pub fn new(base: &str, quote: &str) -> Self {
+        CurrencyPair {
+            base: Symbol::new(base),
+            quote: Symbol::new(quote),
+            text: Symbol::new(&format!("{}/{}", base, quote)),
+        }
+    }
+
+    /// The pair as written by the cluster, e.g. `EUR/USD`.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+impl FromStr for CurrencyPair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (base, quote) = s
+            .split_once('/')
+            .ok_or(anyhow::Error::msg(format!("Invalid currency pair: {}", s)))?;
+        if base.is_empty() || quote.is_empty() {
+            return Err(anyhow::Error::msg(format!("Invalid currency pair: {}", s)));
+        }
+        Ok(CurrencyPair {
+            base: Symbol::new(base),
+            quote: Symbol::new(quote),
+            text: Symbol::new(s),
+        })
+    }
+}
+
+impl TryFrom<String> for CurrencyPair {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        value.parse()
+    }
+}
+
+impl<'de> Deserialize<'de> for CurrencyPair {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        symbol::deserialize_str_with(deserializer, |text| {
+            text.parse().map_err(|e: anyhow::Error| e.to_string())
+        })
+    }
+}
+
+impl fmt::Display for CurrencyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// All events that can be received from the forex cluster.
+#[derive(Clone, Debug)]
+pub enum ForexEvent {
+    /// A quote (`C`).
+    Quote(ForexQuoteEvent),
+    /// A per-minute aggregate (`CA`).
+    MinuteAggregate(ForexAggregateEvent),
+    /// A per-second aggregate (`CAS`).
+    SecondAggregate(ForexAggregateEvent),
+    /// A connection, authentication or subscription status message (`status`).
+    Status(StatusEvent),
+}
+
+impl FeedEvent for ForexEvent {
+    fn decode(event_type: &str, json: &str) -> anyhow::Result<Self> {
+        let event = match event_type {
+            "C" => ForexEvent::Quote(serde_json::from_str(json)?),
+            "CA" => ForexEvent::MinuteAggregate(serde_json::from_str(json)?),
+            "CAS" => ForexEvent::SecondAggregate(serde_json::from_str(json)?),
+            "status" => ForexEvent::Status(serde_json::from_str(json)?),
+            other => {
+                return Err(anyhow::Error::msg(format!(
+                    "Unknown forex event type: {}",
+                    other
+                )))
+            }
+        };
+        Ok(event)
+    }
+
+    fn event_type(&self) -> &str {
+        match self {
+            ForexEvent::Quote(event) => &event.event_type,
+            ForexEvent::MinuteAggregate(event) => &event.event_type,
+            ForexEvent::SecondAggregate(event) => &event.event_type,
+            ForexEvent::Status(event) => &event.event_type,
+        }
+    }
+
+    fn symbol(&self) -> Option<Cow<'_, str>> {
+        match self {
+            ForexEvent::Quote(event) => Some(Cow::Borrowed(event.pair.as_str())),
+            ForexEvent::MinuteAggregate(event) => Some(Cow::Borrowed(event.pair.as_str())),
+            ForexEvent::SecondAggregate(event) => Some(Cow::Borrowed(event.pair.as_str())),
+            ForexEvent::Status(_) => None,
+        }
+    }
+
+    fn exchange_id(&self) -> Option<i64> {
+        match self {
+            ForexEvent::Quote(event) => Some(event.exchange_id),
+            ForexEvent::MinuteAggregate(_)
+            | ForexEvent::SecondAggregate(_)
+            | ForexEvent::Status(_) => None,
+        }
+    }
+}
+
+/// Represents a forex quote event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ForexQuoteEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "p")]
+    pub pair: CurrencyPair,
+
+    #[serde(rename = "x")]
+    pub exchange_id: i64,
+
+    #[serde(rename = "a")]
+    pub ask_price: f64,
+
+    #[serde(rename = "b")]
+    pub bid_price: f64,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+impl fmt::Display for ForexQuoteEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} / {} on {} at {}",
+            self.pair,
+            self.bid_price,
+            self.ask_price,
+            exchange_name(AssetClass::Fx, self.exchange_id),
+            self.timestamp
+        )
+    }
+}
+
+impl ForexQuoteEvent {
+    /// The midpoint between the bid and ask price.
+    pub fn mid_price(&self) -> f64 {
+        (self.bid_price + self.ask_price) / 2.0
+    }
+}
+
+/// Exposes a forex quote as a trade at its mid price, so it can be used wherever crypto trades
+/// are consumed (e.g. `exchange::Bucket`).
+impl Trade for ForexQuoteEvent {
+    fn get_trade(&self) -> anyhow::Result<TradeData<'_>> {
+        Ok(TradeData {
+            instrument: self.pair.clone().into(),
+            price: self.mid_price(),
+            size: 0.0,
+            timestamp: self.timestamp,
+            exchange_id: self.exchange_id,
+            trade_id: None,
+            conditions: &[],
+            tape: None,
+            received_at: None,
+        })
+    }
+    fn asset_class(&self) -> AssetClass {
+        AssetClass::Fx
+    }
+}
+
+/// Represents a forex aggregate (per minute or per second) event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ForexAggregateEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "pair")]
+    pub pair: CurrencyPair,
+
+    #[serde(rename = "o")]
+    pub open_price: f64,
+
+    #[serde(rename = "c")]
+    pub close_price: f64,
+
+    #[serde(rename = "h")]
+    pub highest_price: f64,
+
+    #[serde(rename = "l")]
+    pub lowest_price: f64,
+
+    /// The number of ticks in the aggregate window.
+    #[serde(rename = "v")]
+    pub volume: f64,
+
+    #[serde(rename = "s", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub start_timestamp: DateTime<Utc>,
+
+    #[serde(
+        rename = "e",
+        default,
+        deserialize_with = "TimeUtil::timestamp_milliseconds_opt"
+    )]
+    pub end_timestamp: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::instrument::Instrument;
+    use crate::websocket::connection::decode_frame;
+    use chrono::TimeZone;
+
+    /// Decodes a frame holding a single event.
+    fn decode(frame: &str) -> ForexEvent {
+        let mut events = decode_frame::<ForexEvent>(frame);
+        assert_eq!(events.len(), 1);
+        events.pop().unwrap().unwrap()
+    }
+
+    fn millis(ms: i64) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(ms).unwrap()
+    }
+
+    #[test]
+    fn decodes_quote() {
+        let event = decode(
+            r#"[{"ev":"C","p":"USD/CNH","x":48,"a":6.83366,"b":6.83363,"t":1536036818784}]"#,
+        );
+        let ForexEvent::Quote(quote) = event else {
+            panic!("not a quote: {:?}", event);
+        };
+        assert_eq!(quote.pair, CurrencyPair::new("USD", "CNH"));
+        assert_eq!(quote.exchange_id, 48);
+        assert_eq!(quote.ask_price, 6.83366);
+        assert_eq!(quote.bid_price, 6.83363);
+        assert_eq!(quote.timestamp, millis(1536036818784));
+    }
+
+    #[test]
+    fn decodes_aggregates() {
+        let minute = decode(
+            r#"[{"ev":"CA","pair":"USD/EUR","o":0.8687,"c":0.86889,"h":0.86889,"l":0.8686,"v":20,"s":1539145740000,"e":1539145800000}]"#,
+        );
+        let ForexEvent::MinuteAggregate(aggregate) = minute else {
+            panic!("not a minute aggregate: {:?}", minute);
+        };
+        assert_eq!(aggregate.pair.to_string(), "USD/EUR");
+        assert_eq!(aggregate.open_price, 0.8687);
+        assert_eq!(aggregate.close_price, 0.86889);
+        assert_eq!(aggregate.lowest_price, 0.8686);
+        assert_eq!(aggregate.volume, 20.0);
+        assert_eq!(aggregate.start_timestamp, millis(1539145740000));
+        assert_eq!(aggregate.end_timestamp, Some(millis(1539145800000)));
+
+        let second = decode(
+            r#"[{"ev":"CAS","pair":"EUR/USD","o":1.0824,"c":1.0825,"h":1.0826,"l":1.0823,"v":4,"s":1705330800000}]"#,
+        );
+        assert_eq!(second.symbol().as_deref(), Some("EUR/USD"));
+        let ForexEvent::SecondAggregate(aggregate) = second else {
+            panic!("not a second aggregate: {:?}", second);
+        };
+        assert_eq!(aggregate.event_type, "CAS");
+        assert_eq!(aggregate.end_timestamp, None);
+    }
+
+    #[test]
+    fn rejects_unknown_events() {
+        let events = decode_frame::<ForexEvent>(r#"[{"ev":"XT","pair":"BTC-USD"}]"#);
+        assert!(matches!(events[..], [Err(StreamError::Decode(_))]));
+    }
+
+    #[test]
+    fn parses_currency_pairs() {
+        let pair: CurrencyPair = "EUR/USD".parse().unwrap();
+        assert_eq!(pair.base, "EUR");
+        assert_eq!(pair.quote, "USD");
+        assert_eq!(pair.as_str(), "EUR/USD");
+        assert_eq!(pair, CurrencyPair::new("EUR", "USD"));
+        for invalid in ["EUR/", "/USD", "EURUSD", ""] {
+            assert!(invalid.parse::<CurrencyPair>().is_err(), "{}", invalid);
+        }
+        assert!(CurrencyPair::try_from("GBP/JPY".to_string()).is_ok());
+    }
+
+    #[test]
+    fn trades_a_quote_at_its_mid_price() {
+        let event =
+            decode(r#"[{"ev":"C","p":"EUR/USD","x":48,"a":1.0826,"b":1.0824,"t":1705330800123}]"#);
+        let ForexEvent::Quote(quote) = event else {
+            panic!("not a quote: {:?}", event);
+        };
+        assert!((quote.mid_price() - 1.0825).abs() < 1e-12);
+
+        let trade = quote.get_trade().unwrap();
+        assert_eq!(trade.instrument, Instrument::forex("EUR", "USD"));
+        assert!((trade.price - 1.0825).abs() < 1e-12);
+        assert_eq!(trade.size, 0.0);
+        assert_eq!(trade.exchange_id, 48);
+        assert_eq!(trade.timestamp, millis(1705330800123));
+        assert_eq!(trade.trade_id, None);
+        assert!(trade.conditions.is_empty());
+        assert_eq!(quote.asset_class(), AssetClass::Fx);
+    }
+}
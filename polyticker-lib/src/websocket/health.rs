@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The window over which `HealthSnapshot::frames_per_second` is measured.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// A point in time view of the health of a data channel connection.
+#[derive(Clone, Debug)]
+pub struct HealthSnapshot {
+    /// Whether the connection is currently established.
+    pub connected: bool,
+    /// When the current connection was established.
+    pub connected_since: Option<DateTime<Utc>>,
+    /// When the last frame (data or control) was received.
+    pub last_message_time: Option<DateTime<Utc>>,
+    /// The number of frames received over the lifetime of the channel.
+    pub frames_received: u64,
+    /// The number of events decoded over the lifetime of the channel.
+    pub events_received: u64,
+    /// The rate of frames received over the last few seconds.
+    pub frames_per_second: f64,
+    /// The number of times the connection was re-established.
+    pub reconnects: u64,
+    /// The number of frames or events that could not be decoded.
+    pub parse_errors: u64,
+    /// The number of events discarded by the backpressure policy.
+    pub dropped_events: u64,
+    /// The number of events replaced by a newer event with the same key.
+    pub conflated_events: u64,
+}
+
+struct HealthState {
+    connected: bool,
+    connected_since: Option<DateTime<Utc>>,
+    last_message_time: Option<DateTime<Utc>>,
+    last_message_instant: Option<Instant>,
+    frames_received: u64,
+    events_received: u64,
+    reconnects: u64,
+    parse_errors: u64,
+    dropped_events: u64,
+    conflated_events: u64,
+    rate_window_start: Instant,
+    rate_window_frames: u64,
+    /// The rate measured over the last complete window.
+    frames_per_second: Option<f64>,
+}
+
+/// Collects the health metrics of a data channel, shared between its task and its handle.
+pub(crate) struct HealthMonitor {
+    state: Mutex<HealthState>,
+}
+
+impl HealthMonitor {
+    pub(crate) fn new() -> Self {
+        HealthMonitor {
+            state: Mutex::new(HealthState {
+                connected: false,
+                connected_since: None,
+                last_message_time: None,
+                last_message_instant: None,
+                frames_received: 0,
+                events_received: 0,
+                reconnects: 0,
+                parse_errors: 0,
+                dropped_events: 0,
+                conflated_events: 0,
+                rate_window_start: Instant::now(),
+                rate_window_frames: 0,
+                frames_per_second: None,
+            }),
+        }
+    }
+
+    pub(crate) fn on_connected(&self, reconnect: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.connected = true;
+        state.connected_since = Some(Utc::now());
+        // the handshake is the first sign of life of the new connection
+        state.last_message_instant = Some(Instant::now());
+        if reconnect {
+            state.reconnects += 1;
+        }
+    }
+
+    pub(crate) fn on_disconnected(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.connected = false;
+        state.connected_since = None;
+    }
+
+    pub(crate) fn on_frame(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.frames_received += 1;
+        state.last_message_time = Some(Utc::now());
+        state.last_message_instant = Some(now);
+        state.rate_window_frames += 1;
+        let elapsed = now.duration_since(state.rate_window_start);
+        if elapsed >= RATE_WINDOW {
+            state.frames_per_second = Some(state.rate_window_frames as f64 / elapsed.as_secs_f64());
+            state.rate_window_start = now;
+            state.rate_window_frames = 0;
+        }
+    }
+
+    pub(crate) fn on_event(&self) {
+        self.state.lock().unwrap().events_received += 1;
+    }
+
+    pub(crate) fn on_parse_error(&self) {
+        self.state.lock().unwrap().parse_errors += 1;
+    }
+
+    pub(crate) fn on_dropped(&self) {
+        self.state.lock().unwrap().dropped_events += 1;
+    }
+
+    pub(crate) fn on_conflated(&self) {
+        self.state.lock().unwrap().conflated_events += 1;
+    }
+
+    /// How long it has been since the last frame was received on the current connection.
+    pub(crate) fn idle_for(&self) -> Duration {
+        self.state
+            .lock()
+            .unwrap()
+            .last_message_instant
+            .map(|instant| instant.elapsed())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn snapshot(&self) -> HealthSnapshot {
+        let state = self.state.lock().unwrap();
+        let elapsed = state.rate_window_start.elapsed();
+        // windows are only closed when a frame arrives, so once the current one has run past its
+        // length (or before the first one is complete) report the rate of the current one instead
+        let frames_per_second = match state.frames_per_second {
+            Some(rate) if elapsed < RATE_WINDOW => rate,
+            _ if elapsed.is_zero() => 0.0,
+            _ => state.rate_window_frames as f64 / elapsed.as_secs_f64(),
+        };
+        HealthSnapshot {
+            connected: state.connected,
+            connected_since: state.connected_since,
+            last_message_time: state.last_message_time,
+            frames_received: state.frames_received,
+            events_received: state.events_received,
+            frames_per_second,
+            reconnects: state.reconnects,
+            parse_errors: state.parse_errors,
+            dropped_events: state.dropped_events,
+            conflated_events: state.conflated_events,
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::websocket::backpressure::{self, BackpressurePolicy, SendOutcome, Sender};
+use crate::websocket::connection::{EventStream, StreamError};
+use crate::websocket::FeedEvent;
+use futures_util::{Stream, StreamExt};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+
+/// Selects the events a hub subscriber receives. Every criterion that is set must match; an
+/// empty filter matches every event.
+///
+/// Events without a symbol or exchange (status messages, aggregates) only match filters that do
+/// not restrict on symbols or exchanges respectively.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    symbols: Option<HashSet<String>>,
+    event_types: Option<HashSet<String>>,
+    exchanges: Option<HashSet<i64>>,
+}
+
+impl EventFilter {
+    /// A filter matching every event.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only matches events about one of the given symbols, pairs or contracts, e.g. `BTC-USD`.
+    pub fn symbols<I, S>(mut self, symbols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.symbols = Some(symbols.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Only matches events of one of the given types, e.g. `XT`.
+    pub fn event_types<I, S>(mut self, event_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.event_types = Some(event_types.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Only matches events from one of the given exchanges.
+    pub fn exchanges<I>(mut self, exchanges: I) -> Self
+    where
+        I: IntoIterator<Item = i64>,
+    {
+        self.exchanges = Some(exchanges.into_iter().collect());
+        self
+    }
+
+    /// Returns true if the event passes the filter.
+    pub fn matches<E: FeedEvent>(&self, event: &E) -> bool {
+        if let Some(event_types) = &self.event_types {
+            if !event_types.contains(event.event_type()) {
+                return false;
+            }
+        }
+        if let Some(symbols) = &self.symbols {
+            match event.symbol() {
+                Some(symbol) if symbols.contains(symbol.as_ref()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(exchanges) = &self.exchanges {
+            match event.exchange_id() {
+                Some(exchange_id) if exchanges.contains(&exchange_id) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// The dispatcher's side of a subscriber.
+struct Slot<E> {
+    filter: EventFilter,
+    tx: Sender<E>,
+    /// Events dropped since the subscriber was last told it lagged.
+    missed: u64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<E: FeedEvent + Clone> Slot<E> {
+    /// Hands an item to the subscriber without waiting. Returns false once the subscriber is gone.
+    fn deliver(&mut self, item: &Result<E, StreamError>) -> bool {
+        if let Ok(event) = item {
+            if !self.filter.matches(event) {
+                return true;
+            }
+        }
+        // report the lag in order, as soon as there is room again
+        if self.missed > 0 && self.tx.has_capacity() {
+            if self
+                .tx
+                .try_send(Err(StreamError::Lagged(self.missed)))
+                .is_err()
+            {
+                return false;
+            }
+            self.missed = 0;
+        }
+        match self.tx.try_send(item.clone()) {
+            Ok(SendOutcome::Dropped) => {
+                self.missed += 1;
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+struct Subscribers<E> {
+    slots: Vec<Slot<E>>,
+    /// Set once the source stream has ended, new subscribers then get an ended stream.
+    closed: bool,
+}
+
+/// Fans the events of one data channel out to any number of subscribers, so several consumers
+/// can share a single Polygon connection.
+///
+/// Every subscriber has its own bounded queue and filter. The hub never waits for a subscriber:
+/// when a queue is full the subscriber's backpressure policy applies and, if events were
+/// dropped, the subscriber receives a `StreamError::Lagged` once it catches up. A slow
+/// subscriber therefore never delays the others or the connection.
+///
+/// Errors of the source stream are delivered to every subscriber. Dropping the hub stops the
+/// fan-out and ends all subscriber streams.
+pub struct Hub<E> {
+    subscribers: Arc<Mutex<Subscribers<E>>>,
+    task: JoinHandle<()>,
+}
+
+impl<E: FeedEvent + Clone> Hub<E> {
+    /// Starts distributing the events of `source`, typically the `EventStream` of a data
+    /// channel. Requires a tokio runtime.
+    pub fn new<S>(mut source: S) -> Self
+    where
+        S: Stream<Item = Result<E, StreamError>> + Unpin + Send + 'static,
+    {
+        let subscribers = Arc::new(Mutex::new(Subscribers {
+            slots: Vec::new(),
+            closed: false,
+        }));
+        let dispatch = subscribers.clone();
+        let task = tokio::task::spawn(async move {
+            while let Some(item) = source.next().await {
+                dispatch
+                    .lock()
+                    .unwrap()
+                    .slots
+                    .retain_mut(|slot| slot.deliver(&item));
+            }
+            let mut subscribers = dispatch.lock().unwrap();
+            subscribers.closed = true;
+            subscribers.slots.clear();
+        });
+        Hub { subscribers, task }
+    }
+
+    /// Registers a subscriber receiving the events matching `filter` through a queue of
+    /// `capacity` events, dropping new events while the queue is full.
+    pub fn subscribe(&self, filter: EventFilter, capacity: usize) -> Subscriber<E> {
+        self.subscribe_with_policy(filter, capacity, BackpressurePolicy::DropNewest)
+    }
+
+    /// Registers a subscriber with the given policy for its full queue. As the hub never waits
+    /// for a subscriber, `BackpressurePolicy::Block` behaves like `DropNewest`.
+    pub fn subscribe_with_policy(
+        &self,
+        filter: EventFilter,
+        capacity: usize,
+        policy: BackpressurePolicy,
+    ) -> Subscriber<E> {
+        let (tx, rx) = backpressure::channel(capacity, policy);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut subscribers = self.subscribers.lock().unwrap();
+        // once the source has ended the sender is dropped right away, ending the stream
+        if !subscribers.closed {
+            subscribers.slots.push(Slot {
+                filter,
+                tx,
+                missed: 0,
+                dropped: dropped.clone(),
+            });
+        }
+        Subscriber {
+            stream: EventStream::new(rx),
+            dropped,
+        }
+    }
+
+    /// The number of subscribers still receiving events.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().slots.len()
+    }
+
+    /// Returns true once the source stream has ended.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl<E> Drop for Hub<E> {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.subscribers.lock().unwrap().slots.clear();
+    }
+}
+
+/// The events a hub delivers to one subscriber, usable both with `recv` loops and as a
+/// `futures::Stream`.
+pub struct Subscriber<E> {
+    stream: EventStream<E>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<E> Subscriber<E> {
+    /// Receives the next event, or `None` once the hub or its source has ended.
+    pub async fn recv(&mut self) -> Option<Result<E, StreamError>> {
+        self.stream.recv().await
+    }
+
+    /// The total number of events dropped because this subscriber fell behind.
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<E> Stream for Subscriber<E> {
+    type Item = Result<E, StreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::connection::decode_frame;
+    use crate::websocket::crypto::CryptoEvent;
+
+    fn decode(frame: &str) -> CryptoEvent {
+        decode_frame::<CryptoEvent>(frame).pop().unwrap().unwrap()
+    }
+
+    fn trade(pair: &str, exchange_id: i64, trade_id: usize) -> CryptoEvent {
+        decode(&format!(
+            r#"[{{"ev":"XT","pair":"{}","p":100.0,"t":1610462007425,"s":1.0,"c":[],"i":"{}","x":{},"r":1610462007576}}]"#,
+            pair, trade_id, exchange_id
+        ))
+    }
+
+    fn quote(pair: &str, exchange_id: i64) -> CryptoEvent {
+        decode(&format!(
+            r#"[{{"ev":"XQ","pair":"{}","bp":99.0,"bs":1.0,"ap":101.0,"as":1.0,"t":1610462411115,"x":{},"r":1610462411128}}]"#,
+            pair, exchange_id
+        ))
+    }
+
+    fn status() -> CryptoEvent {
+        decode(r#"[{"ev":"status","status":"success","message":"subscribed to: XT.*"}]"#)
+    }
+
+    /// A hub over a source fed through the returned sender.
+    fn hub() -> (Sender<CryptoEvent>, Hub<CryptoEvent>) {
+        let (tx, rx) = backpressure::channel(64, BackpressurePolicy::Block);
+        (tx, Hub::new(EventStream::new(rx)))
+    }
+
+    /// Describes every item a subscriber receives until its stream ends.
+    async fn drain(mut subscriber: Subscriber<CryptoEvent>) -> Vec<String> {
+        let mut received = Vec::new();
+        while let Some(item) = subscriber.recv().await {
+            received.push(match item {
+                Ok(CryptoEvent::Trade(trade)) => format!("XT {} {}", trade.pair, trade.trade_id),
+                Ok(event) => format!("{} {:?}", event.event_type(), event.symbol()),
+                Err(e) => format!("error {}", e),
+            });
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn filters_events_per_subscriber() {
+        let (tx, hub) = hub();
+        let all = hub.subscribe(EventFilter::all(), 16);
+        let bitcoin = hub.subscribe(EventFilter::all().symbols(["BTC-USD"]), 16);
+        let trades = hub.subscribe(EventFilter::all().event_types(["XT"]), 16);
+        let kraken = hub.subscribe(EventFilter::all().exchanges([23]), 16);
+        let kraken_bitcoin_trades = hub.subscribe(
+            EventFilter::all()
+                .symbols(["BTC-USD"])
+                .event_types(["XT"])
+                .exchanges([23]),
+            16,
+        );
+        assert_eq!(hub.subscriber_count(), 5);
+
+        for event in [
+            status(),
+            trade("BTC-USD", 1, 1),
+            quote("BTC-USD", 23),
+            trade("ETH-USD", 23, 2),
+            trade("BTC-USD", 23, 3),
+        ] {
+            tx.try_send(Ok(event)).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(drain(all).await.len(), 5);
+        assert_eq!(
+            drain(bitcoin).await,
+            vec!["XT BTC-USD 1", r#"XQ Some("BTC-USD")"#, "XT BTC-USD 3"]
+        );
+        assert_eq!(
+            drain(trades).await,
+            vec!["XT BTC-USD 1", "XT ETH-USD 2", "XT BTC-USD 3"]
+        );
+        // the status message has no exchange
+        assert_eq!(
+            drain(kraken).await,
+            vec![r#"XQ Some("BTC-USD")"#, "XT ETH-USD 2", "XT BTC-USD 3"]
+        );
+        assert_eq!(drain(kraken_bitcoin_trades).await, vec!["XT BTC-USD 3"]);
+        assert!(hub.is_finished());
+        assert_eq!(hub.subscriber_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn delivers_source_errors_to_every_subscriber() {
+        let (tx, hub) = hub();
+        let bitcoin = hub.subscribe(EventFilter::all().symbols(["BTC-USD"]), 16);
+        let kraken = hub.subscribe(EventFilter::all().exchanges([23]), 16);
+        tx.try_send(Err(StreamError::Closed)).unwrap();
+        drop(tx);
+
+        assert_eq!(drain(bitcoin).await.len(), 1);
+        assert_eq!(drain(kraken).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_the_events_a_slow_subscriber_missed() {
+        let (tx, hub) = hub();
+        let mut fast = hub.subscribe(EventFilter::all(), 16);
+        let mut slow = hub.subscribe(EventFilter::all(), 2);
+        for trade_id in 1..=5 {
+            tx.try_send(Ok(trade("BTC-USD", 1, trade_id))).unwrap();
+        }
+        // once the fast subscriber has every event, the hub has offered them to the slow one
+        for _ in 1..=5 {
+            fast.recv().await.unwrap().unwrap();
+        }
+        assert_eq!(slow.dropped_events(), 3);
+        assert_eq!(fast.dropped_events(), 0);
+
+        // the slow subscriber catches up, then learns how many events it missed
+        for expected in ["1", "2"] {
+            let Some(Ok(CryptoEvent::Trade(trade))) = slow.recv().await else {
+                panic!("expected trade {}", expected);
+            };
+            assert_eq!(trade.trade_id, expected);
+        }
+        tx.try_send(Ok(trade("BTC-USD", 1, 6))).unwrap();
+        fast.recv().await.unwrap().unwrap();
+        assert!(matches!(
+            slow.recv().await,
+            Some(Err(StreamError::Lagged(3)))
+        ));
+        let Some(Ok(CryptoEvent::Trade(trade))) = slow.recv().await else {
+            panic!("expected trade 6");
+        };
+        assert_eq!(trade.trade_id, "6");
+
+        // the lag is reported once, the total stays available
+        assert_eq!(slow.dropped_events(), 3);
+        drop(tx);
+        assert!(drain(slow).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ends_the_streams_of_late_subscribers() {
+        let (tx, hub) = hub();
+        drop(tx);
+        while !hub.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        let subscriber = hub.subscribe(EventFilter::all(), 16);
+        assert_eq!(hub.subscriber_count(), 0);
+        assert!(drain(subscriber).await.is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::util::TimeUtil;
+use crate::websocket::config::StreamConfig;
+use crate::websocket::connection::{self, EventStream, StreamError, StreamHandle};
+use crate::websocket::pool::{self, PoolConfig, PoolHandle};
+use crate::websocket::symbol::Symbol;
+use crate::websocket::{FeedEvent, StatusEvent};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::borrow::Cow;
+
+pub struct Indices {}
+
+impl Indices {
+    /// Opens a channel of events from the indices cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `tickers` - The subscription parameters, e.g. `V.I:SPX,AM.I:NDX`.
+    /// * `output_channel_size` - The capacity of the returned channel.
+    pub async fn open_data_channel(
+        api_key: String,
+        tickers: String,
+        output_channel_size: usize,
+    ) -> Result<(EventStream<IndicesEvent>, StreamHandle), StreamError> {
+        Self::open_data_channel_with_config(
+            api_key,
+            tickers,
+            StreamConfig::with_channel_size(output_channel_size),
+        )
+        .await
+    }
+
+    /// Opens a channel of events like `open_data_channel`, with custom heartbeat, idle detection
+    /// and reconnection settings.
+    pub async fn open_data_channel_with_config(
+        api_key: String,
+        tickers: String,
+        config: StreamConfig,
+    ) -> Result<(EventStream<IndicesEvent>, StreamHandle), StreamError> {
+        connection::open_data_channel(
+            "wss://socket.polygon.io/indices",
+            &api_key,
+            &tickers,
+            config,
+        )
+        .await
+    }
+
+    /// Opens a pool of connections sharing the subscriptions in `tickers`, merged into one stream,
+    /// see `PoolConfig`.
+    pub async fn open_pool(
+        api_key: String,
+        tickers: String,
+        config: PoolConfig,
+    ) -> Result<(EventStream<IndicesEvent>, PoolHandle), StreamError> {
+        pool::open_pool(
+            "wss://socket.polygon.io/indices",
+            &api_key,
+            &tickers,
+            config,
+        )
+        .await
+    }
+}
+
+/// All events that can be received from the indices cluster.
+#[derive(Clone, Debug)]
+pub enum IndicesEvent {
+    /// An index value (`V`).
+    Value(IndexValueEvent),
+    /// A per-minute aggregate (`AM`).
+    MinuteAggregate(IndexAggregateEvent),
+    /// A per-second aggregate (`A`).
+    SecondAggregate(IndexAggregateEvent),
+    /// A connection, authentication or subscription status message (`status`).
+    Status(StatusEvent),
+}
+
+impl FeedEvent for IndicesEvent {
+    fn decode(event_type: &str, json: &str) -> anyhow::Result<Self> {
+        let event = match event_type {
+            "V" => IndicesEvent::Value(serde_json::from_str(json)?),
+            "AM" => IndicesEvent::MinuteAggregate(serde_json::from_str(json)?),
+            "A" => IndicesEvent::SecondAggregate(serde_json::from_str(json)?),
+            "status" => IndicesEvent::Status(serde_json::from_str(json)?),
+            other => {
+                return Err(anyhow::Error::msg(format!(
+                    "Unknown indices event type: {}",
+                    other
+                )))
+            }
+        };
+        Ok(event)
+    }
+
+    fn event_type(&self) -> &str {
+        match self {
+            IndicesEvent::Value(event) => &event.event_type,
+            IndicesEvent::MinuteAggregate(event) => &event.event_type,
+            IndicesEvent::SecondAggregate(event) => &event.event_type,
+            IndicesEvent::Status(event) => &event.event_type,
+        }
+    }
+
+    fn symbol(&self) -> Option<Cow<'_, str>> {
+        match self {
+            IndicesEvent::Value(event) => Some(Cow::Borrowed(&event.ticker)),
+            IndicesEvent::MinuteAggregate(event) => Some(Cow::Borrowed(&event.symbol)),
+            IndicesEvent::SecondAggregate(event) => Some(Cow::Borrowed(&event.symbol)),
+            IndicesEvent::Status(_) => None,
+        }
+    }
+
+    fn exchange_id(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// Represents an index value event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IndexValueEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    /// The index ticker, e.g. `I:SPX`.
+    #[serde(rename = "T")]
+    pub ticker: Symbol,
+
+    #[serde(rename = "val")]
+    pub value: f64,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Represents an index aggregate (per minute or per second) event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IndexAggregateEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "sym")]
+    pub symbol: Symbol,
+
+    /// The official opening value of the index for the day.
+    #[serde(rename = "op", default)]
+    pub official_open_value: Option<f64>,
+
+    #[serde(rename = "o")]
+    pub open_value: f64,
+
+    #[serde(rename = "c")]
+    pub close_value: f64,
+
+    #[serde(rename = "h")]
+    pub highest_value: f64,
+
+    #[serde(rename = "l")]
+    pub lowest_value: f64,
+
+    #[serde(rename = "s", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub start_timestamp: DateTime<Utc>,
+
+    #[serde(rename = "e", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub end_timestamp: DateTime<Utc>,
+}
+
+impl IndexAggregateEvent {
+    /// The change of the index since the official open, as a fraction of the opening value.
+    /// Returns `None` if the official open is not known yet.
+    pub fn change_since_open(&self) -> Option<f64> {
+        self.official_open_value
+            .filter(|open| *open != 0.0)
+            .map(|open| (self.close_value - open) / open)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::connection::decode_frame;
+    use chrono::TimeZone;
+
+    /// Decodes a frame holding a single event.
+    fn decode(frame: &str) -> IndicesEvent {
+        let mut events = decode_frame::<IndicesEvent>(frame);
+        assert_eq!(events.len(), 1);
+        events.pop().unwrap().unwrap()
+    }
+
+    fn millis(ms: i64) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(ms).unwrap()
+    }
+
+    #[test]
+    fn decodes_value() {
+        let event = decode(r#"[{"ev":"V","val":3988.5,"T":"I:SPX","t":1678220098130}]"#);
+        assert_eq!(event.event_type(), "V");
+        assert_eq!(event.symbol().as_deref(), Some("I:SPX"));
+        assert_eq!(event.exchange_id(), None);
+        let IndicesEvent::Value(value) = event else {
+            panic!("not a value: {:?}", event);
+        };
+        assert_eq!(value.value, 3988.5);
+        assert_eq!(value.timestamp, millis(1678220098130));
+    }
+
+    #[test]
+    fn decodes_aggregates() {
+        let minute = decode(
+            r#"[{"ev":"AM","sym":"I:SPX","op":3985.67,"o":3985.67,"c":3985.67,"h":3985.67,"l":3985.67,"s":1678220675805,"e":1678220675805}]"#,
+        );
+        assert_eq!(minute.symbol().as_deref(), Some("I:SPX"));
+        let IndicesEvent::MinuteAggregate(aggregate) = minute else {
+            panic!("not a minute aggregate: {:?}", minute);
+        };
+        assert_eq!(aggregate.official_open_value, Some(3985.67));
+        assert_eq!(aggregate.close_value, 3985.67);
+        assert_eq!(aggregate.start_timestamp, millis(1678220675805));
+        assert_eq!(aggregate.change_since_open(), Some(0.0));
+
+        let second = decode(
+            r#"[{"ev":"A","sym":"I:NDX","o":12000.0,"c":12120.0,"h":12130.0,"l":11990.0,"s":1678220675000,"e":1678220676000}]"#,
+        );
+        let IndicesEvent::SecondAggregate(aggregate) = second else {
+            panic!("not a second aggregate: {:?}", second);
+        };
+        assert_eq!(aggregate.official_open_value, None);
+        assert_eq!(aggregate.change_since_open(), None);
+        assert_eq!(aggregate.end_timestamp, millis(1678220676000));
+    }
+
+    #[test]
+    fn measures_change_since_the_official_open() {
+        let event = decode(
+            r#"[{"ev":"A","sym":"I:NDX","op":12000.0,"o":12000.0,"c":12120.0,"h":12130.0,"l":11990.0,"s":1678220675000,"e":1678220676000}]"#,
+        );
+        let IndicesEvent::SecondAggregate(aggregate) = event else {
+            panic!("not a second aggregate: {:?}", event);
+        };
+        assert!((aggregate.change_since_open().unwrap() - 0.01).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_unknown_events() {
+        let events = decode_frame::<IndicesEvent>(r#"[{"ev":"T","sym":"I:SPX"}]"#);
+        assert!(matches!(events[..], [Err(StreamError::Decode(_))]));
+    }
+}
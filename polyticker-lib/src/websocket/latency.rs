@@ -0,0 +1,499 @@
+use crate::websocket::crypto::{CryptoLevel2Event, CryptoQuoteEvent, CryptoTradeEvent};
+use crate::websocket::stocks::TradeEvent;
+use chrono::{DateTime, Utc};
+use hdrhistogram::Histogram;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The largest latency tracked precisely, larger samples are recorded as this value.
+const MAX_TRACKABLE_MICROS: u64 = 3_600_000_000;
+
+/// The timestamps an event carries along its way from the exchange to this process.
+pub trait LatencySource {
+    /// The exchange the event originated from.
+    fn exchange_id(&self) -> i64;
+    /// The symbol or pair of the event.
+    fn symbol(&self) -> &str;
+    /// When the event happened at the exchange.
+    fn exchange_timestamp(&self) -> DateTime<Utc>;
+    /// When Polygon received the event, if the feed reports it.
+    fn polygon_timestamp(&self) -> Option<DateTime<Utc>>;
+}
+
+impl LatencySource for CryptoTradeEvent {
+    fn exchange_id(&self) -> i64 {
+        self.exchange_id
+    }
+
+    fn symbol(&self) -> &str {
+        &self.pair
+    }
+
+    fn exchange_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn polygon_timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.received_timestamp)
+    }
+}
+
+impl LatencySource for CryptoQuoteEvent {
+    fn exchange_id(&self) -> i64 {
+        self.exchange_id
+    }
+
+    fn symbol(&self) -> &str {
+        &self.pair
+    }
+
+    fn exchange_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn polygon_timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.received_timestamp)
+    }
+}
+
+impl LatencySource for CryptoLevel2Event {
+    fn exchange_id(&self) -> i64 {
+        self.exchange_id
+    }
+
+    fn symbol(&self) -> &str {
+        &self.pair
+    }
+
+    fn exchange_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn polygon_timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.received_timestamp)
+    }
+}
+
+/// Stock trades only carry the SIP timestamp, so only the end to end latency is known.
+impl LatencySource for TradeEvent {
+    fn exchange_id(&self) -> i64 {
+        self.exchange_id as i64
+    }
+
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    fn exchange_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn polygon_timestamp(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+}
+
+/// The legs of the path of an event for which latency is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatencyLeg {
+    /// From the exchange timestamp to Polygon's receive timestamp.
+    ExchangeToPolygon,
+    /// From Polygon's receive timestamp to the local receive time.
+    PolygonToLocal,
+    /// From the exchange timestamp to the local receive time.
+    EndToEnd,
+}
+
+/// A histogram of latencies with microsecond resolution and percentile queries.
+#[derive(Clone, Debug)]
+pub struct LatencyHistogram {
+    histogram: Histogram<u64>,
+    /// Samples where the later timestamp preceded the earlier one (clock skew), recorded as zero.
+    negative_samples: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram {
+            histogram: Histogram::new_with_max(MAX_TRACKABLE_MICROS, 3)
+                .expect("valid histogram bounds"),
+            negative_samples: 0,
+        }
+    }
+
+    /// Records the latency between two timestamps.
+    pub fn record_between(&mut self, from: DateTime<Utc>, to: DateTime<Utc>) {
+        match (to - from).to_std() {
+            Ok(latency) => self.record(latency),
+            Err(_) => {
+                self.negative_samples += 1;
+                self.record(Duration::ZERO);
+            }
+        }
+    }
+
+    /// Records a latency sample.
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.histogram.saturating_record(micros);
+    }
+
+    /// The latency at the given percentile (0 to 100).
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_micros(self.histogram.value_at_percentile(percentile))
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_secs_f64(self.histogram.mean() / 1_000_000.0)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.histogram.max())
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_micros(self.histogram.min())
+    }
+
+    /// The number of samples recorded.
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    /// The number of samples with a negative latency, a sign of clock skew.
+    pub fn negative_samples(&self) -> u64 {
+        self.negative_samples
+    }
+
+    /// Adds the samples of another histogram to this one.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        self.histogram
+            .add(&other.histogram)
+            .expect("histograms share the same bounds");
+        self.negative_samples += other.negative_samples;
+    }
+
+    pub fn reset(&mut self) {
+        self.histogram.reset();
+        self.negative_samples = 0;
+    }
+}
+
+/// The latency histograms of every leg for one exchange and symbol.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyStats {
+    pub exchange_to_polygon: LatencyHistogram,
+    pub polygon_to_local: LatencyHistogram,
+    pub end_to_end: LatencyHistogram,
+}
+
+impl LatencyStats {
+    pub fn leg(&self, leg: LatencyLeg) -> &LatencyHistogram {
+        match leg {
+            LatencyLeg::ExchangeToPolygon => &self.exchange_to_polygon,
+            LatencyLeg::PolygonToLocal => &self.polygon_to_local,
+            LatencyLeg::EndToEnd => &self.end_to_end,
+        }
+    }
+
+    pub fn merge(&mut self, other: &LatencyStats) {
+        self.exchange_to_polygon.merge(&other.exchange_to_polygon);
+        self.polygon_to_local.merge(&other.polygon_to_local);
+        self.end_to_end.merge(&other.end_to_end);
+    }
+}
+
+/// Identifies the events a `LatencyStats` was recorded for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LatencyKey {
+    pub exchange_id: i64,
+    pub symbol: String,
+}
+
+/// Records feed latency per exchange and symbol.
+#[derive(Default)]
+pub struct LatencyTracker {
+    /// Keyed by exchange id, then symbol, so lookups need no allocation.
+    stats: HashMap<i64, HashMap<String, LatencyStats>>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the latencies of an event that was received locally at `received_at`.
+    pub fn record<E: LatencySource>(&mut self, event: &E, received_at: DateTime<Utc>) {
+        let symbols = self.stats.entry(event.exchange_id()).or_default();
+        let stats = match symbols.get_mut(event.symbol()) {
+            Some(stats) => stats,
+            None => symbols.entry(event.symbol().to_string()).or_default(),
+        };
+        let exchange_timestamp = event.exchange_timestamp();
+        if let Some(polygon_timestamp) = event.polygon_timestamp() {
+            stats
+                .exchange_to_polygon
+                .record_between(exchange_timestamp, polygon_timestamp);
+            stats
+                .polygon_to_local
+                .record_between(polygon_timestamp, received_at);
+        }
+        stats
+            .end_to_end
+            .record_between(exchange_timestamp, received_at);
+    }
+
+    /// Records the latencies of an event received just now.
+    pub fn record_now<E: LatencySource>(&mut self, event: &E) {
+        self.record(event, Utc::now());
+    }
+
+    /// The latencies of one exchange and symbol.
+    pub fn stats(&self, exchange_id: i64, symbol: &str) -> Option<&LatencyStats> {
+        self.stats.get(&exchange_id)?.get(symbol)
+    }
+
+    /// The latencies of all symbols of an exchange combined.
+    pub fn exchange_stats(&self, exchange_id: i64) -> Option<LatencyStats> {
+        self.combined(|key| key.exchange_id == exchange_id)
+    }
+
+    /// The latencies of a symbol across all exchanges combined.
+    pub fn symbol_stats(&self, symbol: &str) -> Option<LatencyStats> {
+        self.combined(|key| key.symbol == symbol)
+    }
+
+    /// The ids of all exchanges latencies were recorded for, in ascending order.
+    pub fn exchange_ids(&self) -> Vec<i64> {
+        let mut ids: Vec<i64> = self.stats.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Iterates over the latencies of every exchange and symbol.
+    pub fn iter(&self) -> impl Iterator<Item = (LatencyKey, &LatencyStats)> {
+        self.stats.iter().flat_map(|(exchange_id, symbols)| {
+            symbols.iter().map(move |(symbol, stats)| {
+                (
+                    LatencyKey {
+                        exchange_id: *exchange_id,
+                        symbol: symbol.clone(),
+                    },
+                    stats,
+                )
+            })
+        })
+    }
+
+    /// The exchanges and symbols whose latency at `percentile` exceeds `threshold` on `leg`,
+    /// slowest first, e.g. to detect a degraded venue or network.
+    pub fn exceeding(
+        &self,
+        leg: LatencyLeg,
+        percentile: f64,
+        threshold: Duration,
+    ) -> Vec<(LatencyKey, Duration)> {
+        let mut exceeding: Vec<(LatencyKey, Duration)> = self
+            .iter()
+            .filter(|(_, stats)| stats.leg(leg).count() > 0)
+            .map(|(key, stats)| (key, stats.leg(leg).percentile(percentile)))
+            .filter(|(_, latency)| *latency > threshold)
+            .collect();
+        exceeding.sort_by_key(|(_, latency)| std::cmp::Reverse(*latency));
+        exceeding
+    }
+
+    /// Clears all recorded latencies, e.g. to start a new measurement window.
+    pub fn reset(&mut self) {
+        self.stats.clear();
+    }
+
+    fn combined(&self, filter: impl Fn(&LatencyKey) -> bool) -> Option<LatencyStats> {
+        self.iter().filter(|(key, _)| filter(key)).fold(
+            None,
+            |combined: Option<LatencyStats>, (_, stats)| {
+                let mut combined = combined.unwrap_or_default();
+                combined.merge(stats);
+                Some(combined)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    /// An event of `exchange_id` that happened at `exchange_ms` and reached Polygon at
+    /// `polygon_ms`, in milliseconds after `start`.
+    struct Sample {
+        exchange_id: i64,
+        symbol: &'static str,
+        exchange_ms: i64,
+        polygon_ms: Option<i64>,
+    }
+
+    impl LatencySource for Sample {
+        fn exchange_id(&self) -> i64 {
+            self.exchange_id
+        }
+
+        fn symbol(&self) -> &str {
+            self.symbol
+        }
+
+        fn exchange_timestamp(&self) -> DateTime<Utc> {
+            at(self.exchange_ms)
+        }
+
+        fn polygon_timestamp(&self) -> Option<DateTime<Utc>> {
+            self.polygon_ms.map(at)
+        }
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap()
+    }
+
+    fn at(ms: i64) -> DateTime<Utc> {
+        start() + chrono::Duration::milliseconds(ms)
+    }
+
+    fn sample(exchange_id: i64, symbol: &'static str, exchange_ms: i64, polygon_ms: i64) -> Sample {
+        Sample {
+            exchange_id,
+            symbol,
+            exchange_ms,
+            polygon_ms: Some(polygon_ms),
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// The histograms keep three significant digits.
+    fn assert_near(actual: Duration, expected: Duration) {
+        let tolerance = expected.as_secs_f64() / 1000.0;
+        assert!(
+            (actual.as_secs_f64() - expected.as_secs_f64()).abs() <= tolerance,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn splits_latency_into_legs() {
+        let mut tracker = LatencyTracker::new();
+        tracker.record(&sample(1, "BTC-USD", 0, 40), at(50));
+
+        let stats = tracker.stats(1, "BTC-USD").unwrap();
+        assert_eq!(stats.exchange_to_polygon.count(), 1);
+        assert_near(stats.exchange_to_polygon.max(), ms(40));
+        assert_near(stats.polygon_to_local.max(), ms(10));
+        assert_near(stats.leg(LatencyLeg::EndToEnd).max(), ms(50));
+        assert!(tracker.stats(1, "ETH-USD").is_none());
+        assert!(tracker.stats(2, "BTC-USD").is_none());
+    }
+
+    #[test]
+    fn records_only_end_to_end_without_a_polygon_timestamp() {
+        let trade: TradeEvent = serde_json::from_value(json!({
+            "ev": "T",
+            "sym": "AAPL",
+            "x": 4,
+            "i": "1",
+            "z": 3,
+            "p": 180.0,
+            "s": 100,
+            "t": start().timestamp_millis(),
+            "q": 1,
+        }))
+        .unwrap();
+        let mut tracker = LatencyTracker::new();
+        tracker.record(&trade, at(25));
+
+        let stats = tracker.stats(4, "AAPL").unwrap();
+        assert_eq!(stats.exchange_to_polygon.count(), 0);
+        assert_eq!(stats.polygon_to_local.count(), 0);
+        assert_eq!(stats.end_to_end.count(), 1);
+        assert_near(stats.end_to_end.max(), ms(25));
+    }
+
+    #[test]
+    fn records_clock_skew_as_zero() {
+        let mut tracker = LatencyTracker::new();
+        // Polygon's clock is ahead of the local one
+        tracker.record(&sample(1, "BTC-USD", 0, 40), at(30));
+
+        let stats = tracker.stats(1, "BTC-USD").unwrap();
+        assert_eq!(stats.polygon_to_local.negative_samples(), 1);
+        assert_eq!(stats.polygon_to_local.max(), Duration::ZERO);
+        assert_eq!(stats.end_to_end.negative_samples(), 0);
+    }
+
+    #[test]
+    fn reports_percentiles() {
+        let mut tracker = LatencyTracker::new();
+        // end to end latencies of 1 to 100 ms
+        for latency in 1..=100 {
+            tracker.record(&sample(1, "BTC-USD", 0, 0), at(latency));
+        }
+        let end_to_end = &tracker.stats(1, "BTC-USD").unwrap().end_to_end;
+        assert_eq!(end_to_end.count(), 100);
+        assert_near(end_to_end.percentile(50.0), ms(50));
+        assert_near(end_to_end.percentile(99.0), ms(99));
+        assert_near(end_to_end.percentile(100.0), ms(100));
+        assert_near(end_to_end.min(), ms(1));
+        assert_near(end_to_end.mean(), Duration::from_micros(50_500));
+    }
+
+    #[test]
+    fn combines_stats_per_exchange_and_per_symbol() {
+        let mut tracker = LatencyTracker::new();
+        tracker.record(&sample(1, "BTC-USD", 0, 10), at(20));
+        tracker.record(&sample(1, "ETH-USD", 0, 30), at(40));
+        tracker.record(&sample(2, "BTC-USD", 0, 50), at(60));
+
+        assert_eq!(tracker.exchange_ids(), vec![1, 2]);
+        let coinbase = tracker.exchange_stats(1).unwrap();
+        assert_eq!(coinbase.end_to_end.count(), 2);
+        assert_near(coinbase.exchange_to_polygon.max(), ms(30));
+        let bitcoin = tracker.symbol_stats("BTC-USD").unwrap();
+        assert_eq!(bitcoin.end_to_end.count(), 2);
+        assert_near(bitcoin.exchange_to_polygon.min(), ms(10));
+        assert_near(bitcoin.exchange_to_polygon.max(), ms(50));
+        assert!(tracker.exchange_stats(3).is_none());
+
+        tracker.reset();
+        assert!(tracker.exchange_ids().is_empty());
+    }
+
+    #[test]
+    fn reports_the_latencies_exceeding_a_threshold_slowest_first() {
+        let mut tracker = LatencyTracker::new();
+        tracker.record(&sample(1, "BTC-USD", 0, 10), at(15));
+        tracker.record(&sample(2, "BTC-USD", 0, 200), at(205));
+        tracker.record(&sample(6, "BTC-USD", 0, 120), at(125));
+
+        let exceeding = tracker.exceeding(LatencyLeg::ExchangeToPolygon, 99.0, ms(100));
+        let keys: Vec<i64> = exceeding.iter().map(|(key, _)| key.exchange_id).collect();
+        assert_eq!(keys, vec![2, 6]);
+        assert_eq!(exceeding[0].0.symbol, "BTC-USD");
+        assert_near(exceeding[0].1, ms(200));
+        assert_near(exceeding[1].1, ms(120));
+        // the local leg is fast everywhere
+        assert!(tracker
+            .exceeding(LatencyLeg::PolygonToLocal, 99.0, ms(100))
+            .is_empty());
+    }
+}
@@ -0,0 +1,439 @@
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+
+/// How a `MockServer` behaves towards its clients.
+#[derive(Clone, Debug)]
+pub struct MockServerConfig {
+    /// The API key clients must authenticate with. `None` accepts any key.
+    pub api_key: Option<String>,
+    /// Frames sent to every client once it has subscribed, e.g. JSON arrays of events.
+    pub script: Vec<String>,
+    /// The pause before every scripted frame.
+    pub frame_interval: Duration,
+    /// Whether to close the connection once the script has been sent.
+    pub close_after_script: bool,
+}
+
+impl Default for MockServerConfig {
+    fn default() -> Self {
+        MockServerConfig {
+            api_key: None,
+            script: Vec::new(),
+            frame_interval: Duration::ZERO,
+            close_after_script: false,
+        }
+    }
+}
+
+/// What the test asks a connection to do.
+enum Command {
+    Frame(String),
+    Close,
+    Kill,
+}
+
+struct ServerState {
+    /// The authenticated connections.
+    clients: Vec<UnboundedSender<Command>>,
+    /// Every text message received from any client, in order.
+    received: Vec<String>,
+    connections: usize,
+}
+
+/// A local websocket server emulating a Polygon cluster, for testing applications offline.
+///
+/// It performs Polygon's handshake (`connected`, then `auth_success` or `auth_failed`),
+/// acknowledges subscriptions, plays a script of frames to every subscribed client and lets the
+/// test push frames or force disconnects at any time. Point a data channel at it with
+/// `StreamConfig::url`:
+///
+/// ```
+/// # use polyticker_lib::websocket::config::StreamConfig;
+/// # use polyticker_lib::websocket::crypto::{Crypto, CryptoEvent};
+/// # use polyticker_lib::websocket::mock::{EventGenerator, MockServer};
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let server = MockServer::start().await?;
+/// let config = StreamConfig {
+///     url: Some(server.url()),
+///     ..Default::default()
+/// };
+/// let (mut stream, _handle) =
+///     Crypto::open_data_channel_with_config("key".into(), "XT.*".into(), config).await?;
+/// // the subscription is acknowledged before the server sends events
+/// assert!(matches!(stream.recv().await, Some(Ok(CryptoEvent::Status(_)))));
+/// server.send_events(&[EventGenerator::new(1).crypto_trade("BTC-USD")]);
+/// assert!(matches!(stream.recv().await, Some(Ok(CryptoEvent::Trade(_)))));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping the server stops it and drops every connection.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts a server accepting any API key and sending nothing but status messages.
+    pub async fn start() -> io::Result<Self> {
+        Self::start_with_config(MockServerConfig::default()).await
+    }
+
+    /// Starts a server on a free local port.
+    pub async fn start_with_config(config: MockServerConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ServerState {
+            clients: Vec::new(),
+            received: Vec::new(),
+            connections: 0,
+        }));
+        let config = Arc::new(config);
+        let accept_state = state.clone();
+        let task = tokio::task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accept_state.lock().unwrap().connections += 1;
+                tokio::task::spawn(serve(stream, config.clone(), accept_state.clone()));
+            }
+        });
+        Ok(MockServer { addr, state, task })
+    }
+
+    /// The URL to connect to, e.g. `ws://127.0.0.1:49152`.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Sends a raw text frame to every authenticated client.
+    pub fn send_frame<S: Into<String>>(&self, frame: S) {
+        let frame = frame.into();
+        self.broadcast(|| Command::Frame(frame.clone()));
+    }
+
+    /// Sends the events as one frame to every authenticated client.
+    pub fn send_events(&self, events: &[Value]) {
+        self.send_frame(Value::from(events.to_vec()).to_string());
+    }
+
+    /// Closes every connection with a close frame.
+    pub fn disconnect_all(&self) {
+        self.broadcast(|| Command::Close);
+    }
+
+    /// Drops every connection without a close frame, like a network failure.
+    pub fn kill_connections(&self) {
+        self.broadcast(|| Command::Kill);
+    }
+
+    /// The number of connections accepted so far, including closed ones.
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// The number of authenticated connections still open.
+    pub fn active_clients(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.clients.retain(|client| !client.is_closed());
+        state.clients.len()
+    }
+
+    /// Every text message received from the clients so far, e.g. auth and subscribe actions.
+    pub fn received_messages(&self) -> Vec<String> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    fn broadcast(&self, command: impl Fn() -> Command) {
+        self.state
+            .lock()
+            .unwrap()
+            .clients
+            .retain(|client| client.send(command()).is_ok());
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.state.lock().unwrap().clients.clear();
+    }
+}
+
+fn status(status: &str, message: &str) -> Message {
+    Message::Text(
+        json!([{ "ev": "status", "status": status, "message": message }])
+            .to_string()
+            .into(),
+    )
+}
+
+/// Reads the next text message, recording it. Returns `None` once the connection is gone.
+async fn next_text(
+    ws: &mut WebSocketStream<TcpStream>,
+    state: &Mutex<ServerState>,
+) -> Option<String> {
+    loop {
+        match ws.next().await? {
+            Ok(Message::Text(text)) => {
+                state.lock().unwrap().received.push(text.to_string());
+                return Some(text.to_string());
+            }
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => continue,
+        }
+    }
+}
+
+/// Parses a `{"action": ..., "params": ...}` message.
+fn parse_action(text: &str) -> Option<(String, String)> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    Some((
+        value["action"].as_str()?.to_string(),
+        value["params"].as_str().unwrap_or_default().to_string(),
+    ))
+}
+
+async fn serve(stream: TcpStream, config: Arc<MockServerConfig>, state: Arc<Mutex<ServerState>>) {
+    let Ok(mut ws) = accept_async(stream).await else {
+        return;
+    };
+    if ws
+        .send(status("connected", "Connected Successfully"))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    // Authenticate
+    loop {
+        let Some(text) = next_text(&mut ws, &state).await else {
+            return;
+        };
+        match parse_action(&text) {
+            Some((action, key)) if action == "auth" => {
+                if config
+                    .api_key
+                    .as_ref()
+                    .is_some_and(|api_key| *api_key != key)
+                {
+                    let _ = ws
+                        .send(status("auth_failed", "authentication failed"))
+                        .await;
+                    let _ = ws.close(None).await;
+                    return;
+                }
+                if ws
+                    .send(status("auth_success", "authenticated"))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                break;
+            }
+            _ => {
+                if ws.send(status("error", "not authorized")).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    let (tx, mut commands) = mpsc::unbounded_channel();
+    state.lock().unwrap().clients.push(tx);
+
+    // the script starts with the first subscription
+    let mut script: Option<VecDeque<String>> = None;
+    loop {
+        let script_active = script.is_some();
+        tokio::select! {
+            text = next_text(&mut ws, &state) => {
+                let Some(text) = text else {
+                    return;
+                };
+                let acknowledged = match parse_action(&text) {
+                    Some((action, params)) if action == "subscribe" => {
+                        script.get_or_insert_with(|| config.script.iter().cloned().collect());
+                        params
+                            .split(',')
+                            .map(|param| status("success", &format!("subscribed to: {}", param)))
+                            .collect()
+                    }
+                    Some((action, params)) if action == "unsubscribe" => params
+                        .split(',')
+                        .map(|param| status("success", &format!("unsubscribed to: {}", param)))
+                        .collect(),
+                    _ => vec![status("error", "unknown action")],
+                };
+                for message in acknowledged {
+                    if ws.send(message).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = sleep(config.frame_interval), if script_active => {
+                match script.as_mut().and_then(|frames| frames.pop_front()) {
+                    Some(frame) => {
+                        if ws.send(Message::Text(frame.into())).await.is_err() {
+                            return;
+                        }
+                    }
+                    None if config.close_after_script => {
+                        let _ = ws.close(None).await;
+                        return;
+                    }
+                    None => script = None,
+                }
+            }
+            command = commands.recv() => match command {
+                Some(Command::Frame(frame)) => {
+                    if ws.send(Message::Text(frame.into())).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Command::Close) => {
+                    let _ = ws.close(None).await;
+                    return;
+                }
+                Some(Command::Kill) | None => return,
+            },
+        }
+    }
+}
+
+/// Generates plausible random events in Polygon's wire format, reproducible from a seed.
+pub struct EventGenerator {
+    state: u64,
+    prices: HashMap<String, f64>,
+    sequence_numbers: HashMap<String, i64>,
+}
+
+impl EventGenerator {
+    pub fn new(seed: u64) -> Self {
+        EventGenerator {
+            // xorshift must not start at zero
+            state: seed.max(1),
+            prices: HashMap::new(),
+            sequence_numbers: HashMap::new(),
+        }
+    }
+
+    /// A crypto trade (`XT`) of the pair, e.g. `BTC-USD`.
+    pub fn crypto_trade(&mut self, pair: &str) -> Value {
+        let price = self.walk_price(pair);
+        let now = Utc::now().timestamp_millis();
+        json!({
+            "ev": "XT",
+            "pair": pair,
+            "p": price,
+            "t": now - self.below(50) as i64,
+            "s": self.size(),
+            "c": [self.below(3)],
+            "i": self.next_u64().to_string(),
+            "x": 1 + self.below(4),
+            "r": now,
+        })
+    }
+
+    /// A crypto quote (`XQ`) of the pair.
+    pub fn crypto_quote(&mut self, pair: &str) -> Value {
+        let price = self.walk_price(pair);
+        let spread = price * 0.0001;
+        let now = Utc::now().timestamp_millis();
+        json!({
+            "ev": "XQ",
+            "pair": pair,
+            "bp": price - spread,
+            "bs": self.size(),
+            "ap": price + spread,
+            "as": self.size(),
+            "t": now - self.below(50) as i64,
+            "x": 1 + self.below(4),
+            "r": now,
+        })
+    }
+
+    /// A stock trade (`T`) of the symbol with the next sequence number of the symbol.
+    pub fn stock_trade(&mut self, symbol: &str) -> Value {
+        let price = self.walk_price(symbol);
+        let sequence_number = self
+            .sequence_numbers
+            .entry(symbol.to_string())
+            .and_modify(|sequence_number| *sequence_number += 1)
+            .or_insert(1);
+        let sequence_number = *sequence_number;
+        json!({
+            "ev": "T",
+            "sym": symbol,
+            "x": 1 + self.below(20),
+            "i": self.next_u64().to_string(),
+            "z": 1 + self.below(3),
+            "p": price,
+            "s": 1 + self.below(500),
+            "c": [],
+            "t": Utc::now().timestamp_millis(),
+            "q": sequence_number,
+        })
+    }
+
+    /// A batch of `count` crypto trades of randomly chosen pairs, to send as one frame.
+    pub fn crypto_trades(&mut self, pairs: &[&str], count: usize) -> Vec<Value> {
+        (0..count)
+            .map(|_| {
+                let pair = pairs[self.below(pairs.len() as u64) as usize];
+                self.crypto_trade(pair)
+            })
+            .collect()
+    }
+
+    /// A batch of `count` stock trades of randomly chosen symbols, to send as one frame.
+    pub fn stock_trades(&mut self, symbols: &[&str], count: usize) -> Vec<Value> {
+        (0..count)
+            .map(|_| {
+                let symbol = symbols[self.below(symbols.len() as u64) as usize];
+                self.stock_trade(symbol)
+            })
+            .collect()
+    }
+
+    /// Moves the price of the symbol by up to 0.1% and returns it.
+    fn walk_price(&mut self, symbol: &str) -> f64 {
+        let step = (self.unit() - 0.5) * 0.002;
+        let price = self.prices.entry(symbol.to_string()).or_insert(100.0);
+        *price *= 1.0 + step;
+        (*price * 100.0).round() / 100.0
+    }
+
+    fn size(&mut self) -> f64 {
+        ((self.unit() * 10.0) * 1000.0).round() / 1000.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound.max(1)
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
@@ -1,2 +1,82 @@
+pub mod backpressure;
+pub mod config;
+pub mod connection;
 pub mod crypto;
+pub mod forex;
+pub mod health;
+pub mod hub;
+pub mod indices;
+pub mod latency;
+#[cfg(feature = "test-support")]
+pub mod mock;
+pub mod options;
+pub mod pool;
+pub mod recorder;
+pub mod replay;
 pub mod stocks;
+pub mod symbol;
+
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// An event that can be decoded from a single element of a frame sent by a Polygon cluster.
+pub trait FeedEvent: Sized + Send + 'static {
+    /// Decodes a single element of a frame from its JSON text, `event_type` being its `ev`
+    /// field.
+    fn decode(event_type: &str, json: &str) -> anyhow::Result<Self>;
+
+    /// The `ev` field of the event, e.g. `XT`.
+    fn event_type(&self) -> &str;
+
+    /// The symbol, pair or contract the event is about, or `None` for status messages.
+    fn symbol(&self) -> Option<Cow<'_, str>>;
+
+    /// The exchange the event originated from, or `None` for events not tied to one exchange
+    /// such as aggregates.
+    fn exchange_id(&self) -> Option<i64>;
+}
+
+/// The status reported by the server in a status message.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum Status {
+    #[serde(rename = "connected")]
+    Connected,
+    #[serde(rename = "auth_success")]
+    AuthSuccess,
+    #[serde(rename = "auth_failed")]
+    AuthFailed,
+    #[serde(rename = "success")]
+    Success,
+    #[serde(rename = "error")]
+    Error,
+    #[serde(rename = "max_connections")]
+    MaxConnections,
+    /// Any status not known to this library.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Represents a status message sent by the server, e.g. on connect, authentication and
+/// subscription changes.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatusEvent {
+    #[serde(rename = "ev")]
+    pub event_type: String,
+
+    #[serde(rename = "status")]
+    pub status: Status,
+
+    #[serde(rename = "message", default)]
+    pub message: String,
+}
+
+impl StatusEvent {
+    /// Returns true if the status reports a failure (failed authentication, rejected
+    /// subscription or too many connections).
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.status,
+            Status::AuthFailed | Status::Error | Status::MaxConnections
+        )
+    }
+}
@@ -0,0 +1,424 @@
+use crate::exchange::registry::{exchange_name, AssetClass};
+use crate::util::TimeUtil;
+use crate::websocket::config::StreamConfig;
+use crate::websocket::connection::{self, EventStream, StreamError, StreamHandle};
+use crate::websocket::pool::{self, PoolConfig, PoolHandle};
+use crate::websocket::symbol::Symbol;
+use crate::websocket::{FeedEvent, StatusEvent};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::common::instrument::Instrument;
+use crate::common::trade::{Trade, TradeData};
+
+pub struct Options {}
+
+impl Options {
+    /// Opens a channel of events from the options cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `contracts` - The subscription parameters, e.g. `T.O:SPY241220P00720000,AM.*`.
+    /// * `output_channel_size` - The capacity of the returned channel.
+    pub async fn open_data_channel(
+        api_key: String,
+        contracts: String,
+        output_channel_size: usize,
+    ) -> Result<(EventStream<OptionsEvent>, StreamHandle), StreamError> {
+        Self::open_data_channel_with_config(
+            api_key,
+            contracts,
+            StreamConfig::with_channel_size(output_channel_size),
+        )
+        .await
+    }
+
+    /// Opens a channel of events like `open_data_channel`, with custom heartbeat, idle detection
+    /// and reconnection settings.
+    pub async fn open_data_channel_with_config(
+        api_key: String,
+        contracts: String,
+        config: StreamConfig,
+    ) -> Result<(EventStream<OptionsEvent>, StreamHandle), StreamError> {
+        connection::open_data_channel(
+            "wss://socket.polygon.io/options",
+            &api_key,
+            &contracts,
+            config,
+        )
+        .await
+    }
+
+    /// Opens a pool of connections sharing the subscriptions in `contracts`, merged into one stream,
+    /// see `PoolConfig`.
+    pub async fn open_pool(
+        api_key: String,
+        contracts: String,
+        config: PoolConfig,
+    ) -> Result<(EventStream<OptionsEvent>, PoolHandle), StreamError> {
+        pool::open_pool(
+            "wss://socket.polygon.io/options",
+            &api_key,
+            &contracts,
+            config,
+        )
+        .await
+    }
+}
+
+/// All events that can be received from the options cluster.
+#[derive(Clone, Debug)]
+pub enum OptionsEvent {
+    /// A trade (`T`).
+    Trade(OptionsTradeEvent),
+    /// A quote (`Q`).
+    Quote(OptionsQuoteEvent),
+    /// A per-minute aggregate (`AM`).
+    MinuteAggregate(OptionsAggregateEvent),
+    /// A per-second aggregate (`A`).
+    SecondAggregate(OptionsAggregateEvent),
+    /// A connection, authentication or subscription status message (`status`).
+    Status(StatusEvent),
+}
+
+impl FeedEvent for OptionsEvent {
+    fn decode(event_type: &str, json: &str) -> anyhow::Result<Self> {
+        let event = match event_type {
+            "T" => OptionsEvent::Trade(serde_json::from_str(json)?),
+            "Q" => OptionsEvent::Quote(serde_json::from_str(json)?),
+            "AM" => OptionsEvent::MinuteAggregate(serde_json::from_str(json)?),
+            "A" => OptionsEvent::SecondAggregate(serde_json::from_str(json)?),
+            "status" => OptionsEvent::Status(serde_json::from_str(json)?),
+            other => {
+                return Err(anyhow::Error::msg(format!(
+                    "Unknown options event type: {}",
+                    other
+                )))
+            }
+        };
+        Ok(event)
+    }
+
+    fn event_type(&self) -> &str {
+        match self {
+            OptionsEvent::Trade(event) => &event.event_type,
+            OptionsEvent::Quote(event) => &event.event_type,
+            OptionsEvent::MinuteAggregate(event) => &event.event_type,
+            OptionsEvent::SecondAggregate(event) => &event.event_type,
+            OptionsEvent::Status(event) => &event.event_type,
+        }
+    }
+
+    fn symbol(&self) -> Option<Cow<'_, str>> {
+        match self {
+            OptionsEvent::Trade(event) => Some(Cow::Borrowed(&event.symbol)),
+            OptionsEvent::Quote(event) => Some(Cow::Borrowed(&event.symbol)),
+            OptionsEvent::MinuteAggregate(event) => Some(Cow::Borrowed(&event.symbol)),
+            OptionsEvent::SecondAggregate(event) => Some(Cow::Borrowed(&event.symbol)),
+            OptionsEvent::Status(_) => None,
+        }
+    }
+
+    fn exchange_id(&self) -> Option<i64> {
+        match self {
+            OptionsEvent::Trade(event) => Some(event.exchange_id),
+            // quotes carry separate bid and ask exchanges
+            OptionsEvent::Quote(_)
+            | OptionsEvent::MinuteAggregate(_)
+            | OptionsEvent::SecondAggregate(_)
+            | OptionsEvent::Status(_) => None,
+        }
+    }
+}
+
+/// Represents an options trade event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OptionsTradeEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    /// The options contract symbol, e.g. `O:AMC210827C00037000`.
+    #[serde(rename = "sym")]
+    pub symbol: Symbol,
+
+    #[serde(rename = "x")]
+    pub exchange_id: i64,
+
+    #[serde(rename = "p")]
+    pub price: f64,
+
+    /// The number of contracts traded.
+    #[serde(rename = "s")]
+    pub trade_size: u64,
+
+    #[serde(rename = "c", default)]
+    pub trade_conditions: Vec<i32>,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(rename = "q")]
+    pub sequence_number: i64,
+}
+
+impl fmt::Display for OptionsTradeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} x {} on {} at {}",
+            self.symbol,
+            self.price,
+            self.trade_size,
+            exchange_name(AssetClass::Options, self.exchange_id),
+            self.timestamp
+        )
+    }
+}
+
+/// Options premiums are quoted in US dollars.
+impl Trade for OptionsTradeEvent {
+    fn get_trade(&self) -> anyhow::Result<TradeData<'_>> {
+        Ok(TradeData {
+            instrument: Instrument::from_feed(AssetClass::Options, &self.symbol)?,
+            price: self.price,
+            size: self.trade_size as f64,
+            timestamp: self.timestamp,
+            exchange_id: self.exchange_id,
+            trade_id: None,
+            conditions: &self.trade_conditions,
+            tape: None,
+            received_at: None,
+        })
+    }
+    fn asset_class(&self) -> AssetClass {
+        AssetClass::Options
+    }
+}
+
+/// Represents an options quote event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OptionsQuoteEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "sym")]
+    pub symbol: Symbol,
+
+    #[serde(rename = "bx")]
+    pub bid_exchange_id: i64,
+
+    #[serde(rename = "ax")]
+    pub ask_exchange_id: i64,
+
+    #[serde(rename = "bp")]
+    pub bid_price: f64,
+
+    #[serde(rename = "ap")]
+    pub ask_price: f64,
+
+    #[serde(rename = "bs")]
+    pub bid_size: u64,
+
+    #[serde(rename = "as")]
+    pub ask_size: u64,
+
+    #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(rename = "q")]
+    pub sequence_number: i64,
+}
+
+impl fmt::Display for OptionsQuoteEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} x {} on {} / {} x {} on {} at {}",
+            self.symbol,
+            self.bid_price,
+            self.bid_size,
+            exchange_name(AssetClass::Options, self.bid_exchange_id),
+            self.ask_price,
+            self.ask_size,
+            exchange_name(AssetClass::Options, self.ask_exchange_id),
+            self.timestamp
+        )
+    }
+}
+
+/// Represents an options aggregate (per minute or per second) event received from the server.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OptionsAggregateEvent {
+    #[serde(rename = "ev")]
+    pub event_type: Symbol,
+
+    #[serde(rename = "sym")]
+    pub symbol: Symbol,
+
+    /// The volume of contracts traded in the aggregate window.
+    #[serde(rename = "v")]
+    pub volume: u64,
+
+    /// The accumulated volume of the contract for the day.
+    #[serde(rename = "av")]
+    pub accumulated_volume: u64,
+
+    /// The official opening price of the contract for the day.
+    #[serde(rename = "op", default)]
+    pub official_open_price: Option<f64>,
+
+    #[serde(rename = "vw")]
+    pub volume_weighted_avg_price: f64,
+
+    #[serde(rename = "o")]
+    pub open_price: f64,
+
+    #[serde(rename = "c")]
+    pub close_price: f64,
+
+    #[serde(rename = "h")]
+    pub highest_price: f64,
+
+    #[serde(rename = "l")]
+    pub lowest_price: f64,
+
+    /// The volume-weighted average price for the day.
+    #[serde(rename = "a")]
+    pub day_volume_weighted_avg_price: f64,
+
+    /// The average trade size in the aggregate window.
+    #[serde(rename = "z")]
+    pub average_trade_size: u64,
+
+    #[serde(rename = "s", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub start_timestamp: DateTime<Utc>,
+
+    #[serde(rename = "e", deserialize_with = "TimeUtil::timestamp_milliseconds")]
+    pub end_timestamp: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::instrument::OptionRight;
+    use crate::websocket::connection::decode_frame;
+    use chrono::{NaiveDate, TimeZone};
+
+    /// Decodes a frame holding a single event.
+    fn decode(frame: &str) -> OptionsEvent {
+        let mut events = decode_frame::<OptionsEvent>(frame);
+        assert_eq!(events.len(), 1);
+        events.pop().unwrap().unwrap()
+    }
+
+    fn millis(ms: i64) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(ms).unwrap()
+    }
+
+    #[test]
+    fn decodes_trade() {
+        let event = decode(
+            r#"[{"ev":"T","sym":"O:AMC210827C00037000","x":65,"p":1.54,"s":2,"c":[209],"t":1401715883806,"q":9085}]"#,
+        );
+        assert_eq!(event.event_type(), "T");
+        assert_eq!(event.symbol().as_deref(), Some("O:AMC210827C00037000"));
+        assert_eq!(event.exchange_id(), Some(65));
+        let OptionsEvent::Trade(trade) = event else {
+            panic!("not a trade: {:?}", event);
+        };
+        assert_eq!(trade.price, 1.54);
+        assert_eq!(trade.trade_size, 2);
+        assert_eq!(trade.trade_conditions, vec![209]);
+        assert_eq!(trade.timestamp, millis(1401715883806));
+        assert_eq!(trade.sequence_number, 9085);
+    }
+
+    #[test]
+    fn trades_carry_the_parsed_contract() {
+        let event = decode(
+            r#"[{"ev":"T","sym":"O:SPY241220P00720000","x":302,"p":12.3,"s":10,"t":1734710400000,"q":1}]"#,
+        );
+        let OptionsEvent::Trade(trade) = event else {
+            panic!("not a trade: {:?}", event);
+        };
+        let data = trade.get_trade().unwrap();
+        let Instrument::Option(contract) = &data.instrument else {
+            panic!("not an option: {:?}", data.instrument);
+        };
+        assert_eq!(contract.underlying, "SPY");
+        assert_eq!(
+            contract.expiration,
+            NaiveDate::from_ymd_opt(2024, 12, 20).unwrap()
+        );
+        assert_eq!(contract.right, OptionRight::Put);
+        assert_eq!(contract.strike(), 720.0);
+        assert_eq!(data.size, 10.0);
+        assert!(data.conditions.is_empty());
+        assert_eq!(trade.asset_class(), AssetClass::Options);
+    }
+
+    #[test]
+    fn rejects_trades_on_malformed_contracts() {
+        let event =
+            decode(r#"[{"ev":"T","sym":"O:SPY","x":302,"p":12.3,"s":10,"t":1734710400000,"q":1}]"#);
+        let OptionsEvent::Trade(trade) = event else {
+            panic!("not a trade: {:?}", event);
+        };
+        assert!(trade.get_trade().is_err());
+    }
+
+    #[test]
+    fn decodes_quote() {
+        let event = decode(
+            r#"[{"ev":"Q","sym":"O:SPY241220P00720000","bx":302,"ax":301,"bp":9.46,"ap":9.47,"bs":17,"as":24,"t":1644506128351,"q":844090872}]"#,
+        );
+        assert_eq!(event.exchange_id(), None);
+        let OptionsEvent::Quote(quote) = event else {
+            panic!("not a quote: {:?}", event);
+        };
+        assert_eq!(quote.symbol, "O:SPY241220P00720000");
+        assert_eq!(quote.bid_exchange_id, 302);
+        assert_eq!(quote.ask_exchange_id, 301);
+        assert_eq!(quote.bid_price, 9.46);
+        assert_eq!(quote.ask_price, 9.47);
+        assert_eq!(quote.bid_size, 17);
+        assert_eq!(quote.ask_size, 24);
+        assert_eq!(quote.timestamp, millis(1644506128351));
+    }
+
+    #[test]
+    fn decodes_aggregates() {
+        let frame = |ev: &str| {
+            format!(
+                r#"[{{"ev":"{}","sym":"O:ONEM220121C00025000","v":2,"av":8,"op":2.2,"vw":2.05,"o":2.05,"c":2.05,"h":2.05,"l":2.05,"a":2.1312,"z":1,"s":1631331000000,"e":1631331060000}}]"#,
+                ev
+            )
+        };
+
+        let minute = decode(&frame("AM"));
+        let OptionsEvent::MinuteAggregate(aggregate) = minute else {
+            panic!("not a minute aggregate: {:?}", minute);
+        };
+        assert_eq!(aggregate.symbol, "O:ONEM220121C00025000");
+        assert_eq!(aggregate.volume, 2);
+        assert_eq!(aggregate.accumulated_volume, 8);
+        assert_eq!(aggregate.official_open_price, Some(2.2));
+        assert_eq!(aggregate.day_volume_weighted_avg_price, 2.1312);
+        assert_eq!(aggregate.average_trade_size, 1);
+        assert_eq!(aggregate.start_timestamp, millis(1631331000000));
+        assert_eq!(aggregate.end_timestamp, millis(1631331060000));
+
+        let second = decode(&frame("A"));
+        assert_eq!(second.event_type(), "A");
+        assert!(matches!(second, OptionsEvent::SecondAggregate(_)));
+    }
+
+    #[test]
+    fn rejects_unknown_events() {
+        let events = decode_frame::<OptionsEvent>(r#"[{"ev":"LV","sym":"O:SPY"}]"#);
+        assert!(matches!(events[..], [Err(StreamError::Decode(_))]));
+    }
+}
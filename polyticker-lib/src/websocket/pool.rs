@@ -0,0 +1,622 @@
+use crate::websocket::backpressure::{self, BackpressurePolicy, Sender};
+use crate::websocket::config::StreamConfig;
+use crate::websocket::connection::{
+    self, split_params, EventStream, ShutdownSignal, StreamError, StreamHandle, Subscriptions,
+};
+use crate::websocket::health::{HealthMonitor, HealthSnapshot};
+use crate::websocket::FeedEvent;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How the subscriptions of a pool are split across its connections.
+///
+/// Params are assigned by the symbol they name (`BTC-USD` for `XT.BTC-USD`), so all event types
+/// of a symbol share a connection and arrive in order. Wildcards (`XT.*`) name every symbol and
+/// cannot be split, so they are only accepted by a pool of a single connection.
+#[derive(Clone, Debug)]
+pub enum Sharding {
+    /// Spreads the symbols over up to `connections` connections by their hash. `connections`
+    /// must not exceed `PoolConfig::max_connections`.
+    Hash { connections: usize },
+    /// One connection per group of symbols, e.g. to give the busiest symbols a connection of
+    /// their own. Symbols not in any group are spread over the groups' connections by hash.
+    Groups(Vec<Vec<String>>),
+}
+
+/// The settings of a connection pool.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// The number of connections the API key is entitled to. The pool never opens more.
+    pub max_connections: usize,
+    /// How the subscriptions are split across connections.
+    pub sharding: Sharding,
+    /// The settings of every connection. The channel size and backpressure policy apply to
+    /// the queue of each connection.
+    pub stream: StreamConfig,
+    /// The capacity of the merged stream.
+    pub output_channel_size: usize,
+}
+
+impl PoolConfig {
+    /// A pool of up to `connections` connections sharded by symbol hash.
+    pub fn with_connections(connections: usize) -> Self {
+        PoolConfig {
+            max_connections: connections,
+            sharding: Sharding::Hash { connections },
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 1,
+            sharding: Sharding::Hash { connections: 1 },
+            stream: StreamConfig::default(),
+            output_channel_size: 1000,
+        }
+    }
+}
+
+/// A connection of the pool, as seen from the handle and the other connections.
+struct Shard {
+    subscriptions: Subscriptions,
+    health: Arc<HealthMonitor>,
+    shutdown: ShutdownSignal,
+    alive: bool,
+}
+
+type Shards = Arc<Mutex<Vec<Shard>>>;
+
+/// Opens the connections of a pool to the cluster at `url`, splitting the comma separated
+/// `params` across them as configured, and merges their events into one stream.
+///
+/// When a connection fails for good (reconnection gave up), its subscriptions are moved to the
+/// remaining connections. The pool ends with the error once no connection is left.
+pub(crate) async fn open_pool<E: FeedEvent>(
+    url: &str,
+    api_key: &str,
+    params: &str,
+    config: PoolConfig,
+) -> Result<(EventStream<E>, PoolHandle), StreamError> {
+    let groups: Vec<String> = assign(&split_params(params), &config)?
+        .iter()
+        .map(|group| group.join(","))
+        .collect();
+    let opened = futures_util::future::join_all(groups.iter().map(|group| {
+        connection::open_data_channel::<E>(url, api_key, group, config.stream.clone())
+    }))
+    .await;
+    let mut channels = Vec::with_capacity(opened.len());
+    let mut failure = None;
+    for channel in opened {
+        match channel {
+            Ok(channel) => channels.push(channel),
+            Err(e) => failure = failure.or(Some(e)),
+        }
+    }
+    if let Some(e) = failure {
+        for (_, handle) in channels {
+            handle.shutdown_signal().trigger();
+        }
+        return Err(e);
+    }
+    info!("Opened a pool of {} connections", channels.len());
+
+    let shards: Shards = Arc::new(Mutex::new(
+        channels
+            .iter()
+            .map(|(_, handle)| Shard {
+                subscriptions: handle.shared_subscriptions(),
+                health: handle.health_monitor(),
+                shutdown: handle.shutdown_signal(),
+                alive: true,
+            })
+            .collect(),
+    ));
+    // each connection applies the configured policy to its own queue, merging only waits
+    let (tx, rx) = backpressure::channel(config.output_channel_size, BackpressurePolicy::Block);
+    let tasks = channels
+        .into_iter()
+        .enumerate()
+        .map(|(index, (stream, handle))| {
+            tokio::task::spawn(run_shard(index, stream, handle, tx.clone(), shards.clone()))
+        })
+        .collect();
+    let shutdown = ShutdownSignal::new();
+    let task = tokio::task::spawn(supervise(tasks, shards.clone(), shutdown.clone()));
+    Ok((
+        EventStream::new(rx),
+        PoolHandle {
+            task,
+            shards,
+            shutdown,
+        },
+    ))
+}
+
+/// Splits `params` into the subscriptions of each connection.
+fn assign(params: &[String], config: &PoolConfig) -> Result<Vec<Vec<String>>, StreamError> {
+    if params.is_empty() {
+        return Err(StreamError::Config(
+            "No subscriptions for the pool".to_string(),
+        ));
+    }
+    let connections = match &config.sharding {
+        Sharding::Hash { connections } => *connections,
+        Sharding::Groups(symbols) => symbols.len(),
+    };
+    if connections > 1 {
+        if let Some(wildcard) = params.iter().find(|param| is_wildcard(param)) {
+            return Err(StreamError::Config(format!(
+                "The wildcard subscription {} cannot be split across {} connections",
+                wildcard, connections
+            )));
+        }
+    }
+    let mut groups = match &config.sharding {
+        Sharding::Hash { connections } => {
+            if *connections > config.max_connections {
+                return Err(StreamError::Config(format!(
+                    "{} connections are more than the {} allowed",
+                    connections, config.max_connections
+                )));
+            }
+            if *connections == 0 {
+                return Err(StreamError::Config(
+                    "No connections for the pool".to_string(),
+                ));
+            }
+            let mut groups = vec![Vec::new(); *connections];
+            for param in params {
+                groups[bucket(symbol_of(param), *connections)].push(param.clone());
+            }
+            groups
+        }
+        Sharding::Groups(symbols) => {
+            if symbols.len() > config.max_connections {
+                return Err(StreamError::Config(format!(
+                    "{} groups need more than the {} connections allowed",
+                    symbols.len(),
+                    config.max_connections
+                )));
+            }
+            if symbols.is_empty() {
+                return Err(StreamError::Config("No groups for the pool".to_string()));
+            }
+            let mut groups = vec![Vec::new(); symbols.len()];
+            for param in params {
+                let symbol = symbol_of(param);
+                let index = symbols
+                    .iter()
+                    .position(|group| group.iter().any(|s| s == symbol))
+                    .unwrap_or_else(|| bucket(symbol, symbols.len()));
+                groups[index].push(param.clone());
+            }
+            groups
+        }
+    };
+    // fewer symbols than connections leave some of them empty
+    groups.retain(|group| !group.is_empty());
+    Ok(groups)
+}
+
+/// The symbol a subscription param names, e.g. `BTC-USD` for `XT.BTC-USD`.
+fn symbol_of(param: &str) -> &str {
+    param.split_once('.').map_or(param, |(_, symbol)| symbol)
+}
+
+/// Whether a param subscribes to every symbol of its event type, e.g. `XT.*`.
+fn is_wildcard(param: &str) -> bool {
+    symbol_of(param) == "*"
+}
+
+fn bucket(symbol: &str, buckets: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    symbol.hash(&mut hasher);
+    (hasher.finish() % buckets as u64) as usize
+}
+
+/// Picks the live connection for `param`: the one already subscribed to its symbol, or one by
+/// hash. Returns `None` if no connection is left.
+fn place(shards: &[Shard], param: &str) -> Option<usize> {
+    let symbol = symbol_of(param);
+    let live: Vec<usize> = (0..shards.len()).filter(|&i| shards[i].alive).collect();
+    if live.is_empty() {
+        return None;
+    }
+    live.iter()
+        .copied()
+        .find(|&i| {
+            shards[i]
+                .subscriptions
+                .current()
+                .iter()
+                .any(|p| symbol_of(p) == symbol)
+        })
+        .or_else(|| Some(live[bucket(symbol, live.len())]))
+}
+
+/// Forwards the events of one connection to the merged stream. Once the connection has failed
+/// for good, moves its subscriptions to the connections left.
+async fn run_shard<E: FeedEvent>(
+    index: usize,
+    mut stream: EventStream<E>,
+    handle: StreamHandle,
+    tx: Sender<E>,
+    shards: Shards,
+) -> Result<(), StreamError> {
+    while let Some(event) = stream.recv().await {
+        if tx.send(event).await.is_err() {
+            info!("Pool receiver dropped");
+            return handle.shutdown().await;
+        }
+    }
+    let outcome = handle.await;
+    let mut shards = shards.lock().unwrap();
+    shards[index].alive = false;
+    let Err(error) = outcome else {
+        return Ok(());
+    };
+    let Some(moved) = rebalance(&shards, index) else {
+        return Err(error);
+    };
+    warn!(
+        "Connection {} of the pool failed ({}), moved {} subscriptions",
+        index, error, moved
+    );
+    Ok(())
+}
+
+/// Moves the subscriptions of the dead connection at `index` to the live ones. Returns the number
+/// of subscriptions moved, or `None` if no connection is left.
+fn rebalance(shards: &[Shard], index: usize) -> Option<usize> {
+    let orphaned = shards[index].subscriptions.current();
+    for param in &orphaned {
+        let target = place(shards, param)?;
+        shards[target].subscriptions.add(&[param]);
+    }
+    Some(orphaned.len())
+}
+
+/// Waits for every connection to end, shutting them all down when the pool is. Returns the
+/// error of the last connection to fail, the one that found no live shard left to take over its
+/// subscriptions.
+async fn supervise(
+    tasks: Vec<JoinHandle<Result<(), StreamError>>>,
+    shards: Shards,
+    shutdown: ShutdownSignal,
+) -> Result<(), StreamError> {
+    // in the order the connections end, not the order of the shards
+    let mut tasks: FuturesUnordered<_> = tasks.into_iter().collect();
+    let mut result = Ok(());
+    let mut shutting_down = false;
+    loop {
+        tokio::select! {
+            outcome = tasks.next() => match outcome {
+                Some(outcome) => {
+                    if let Err(e) = outcome.unwrap_or_else(|e| Err(StreamError::Task(e.to_string()))) {
+                        result = Err(e);
+                    }
+                }
+                None => return result,
+            },
+            _ = shutdown.triggered(), if !shutting_down => {
+                shutting_down = true;
+                for shard in shards.lock().unwrap().iter() {
+                    shard.shutdown.trigger();
+                }
+            }
+        }
+    }
+}
+
+/// A handle to the connections of a pool.
+///
+/// Awaiting the handle resolves once every connection has exited: `Ok(())` if the receiver was
+/// dropped or the pool was shut down, or the error of the last connection to fail.
+pub struct PoolHandle {
+    task: JoinHandle<Result<(), StreamError>>,
+    shards: Shards,
+    shutdown: ShutdownSignal,
+}
+
+impl PoolHandle {
+    /// Subscribes to more comma separated `params`, each on the connection already subscribed to
+    /// its symbol or on one picked by hash. Wildcards are skipped unless a single connection is
+    /// left.
+    pub fn subscribe(&self, params: &str) {
+        let shards = self.shards.lock().unwrap();
+        let live = shards.iter().filter(|shard| shard.alive).count();
+        for param in split_params(params) {
+            if live > 1 && is_wildcard(&param) {
+                warn!(
+                    "Cannot split the wildcard subscription {} across connections",
+                    param
+                );
+                continue;
+            }
+            match place(&shards, &param) {
+                Some(target) => shards[target].subscriptions.add(&[param]),
+                None => warn!("No connection left to subscribe to {}", param),
+            }
+        }
+    }
+
+    /// Unsubscribes from comma separated `params`, whichever connection they are on.
+    pub fn unsubscribe(&self, params: &str) {
+        let params = split_params(params);
+        for shard in self.shards.lock().unwrap().iter() {
+            shard.subscriptions.remove(&params);
+        }
+    }
+
+    /// Returns the params each live connection is subscribed to.
+    pub fn assignments(&self) -> Vec<Vec<String>> {
+        self.live(|shard| shard.subscriptions.current())
+    }
+
+    /// Returns the number of live connections.
+    pub fn connections(&self) -> usize {
+        self.live(|_| ()).len()
+    }
+
+    /// Returns the health metrics of each live connection.
+    pub fn health(&self) -> Vec<HealthSnapshot> {
+        self.live(|shard| shard.health.snapshot())
+    }
+
+    fn live<T>(&self, f: impl Fn(&Shard) -> T) -> Vec<T> {
+        self.shards
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|shard| shard.alive)
+            .map(f)
+            .collect()
+    }
+
+    /// Shuts every connection down gracefully and resolves once they have exited.
+    pub async fn shutdown(self) -> Result<(), StreamError> {
+        self.shutdown.trigger();
+        self.await
+    }
+
+    /// Returns a signal that shuts the pool down like `shutdown` when triggered.
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.shutdown.clone()
+    }
+
+    /// Returns true once every connection has exited.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Future for PoolHandle {
+    type Output = Result<(), StreamError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task)
+            .poll(cx)
+            .map(|result| match result {
+                Ok(outcome) => outcome,
+                Err(e) => Err(StreamError::Task(e.to_string())),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(text: &str) -> Vec<String> {
+        split_params(text)
+    }
+
+    fn hashed(connections: usize, max_connections: usize) -> PoolConfig {
+        PoolConfig {
+            max_connections,
+            sharding: Sharding::Hash { connections },
+            ..Default::default()
+        }
+    }
+
+    fn shard(params: &str) -> Shard {
+        Shard {
+            subscriptions: Subscriptions::new(params),
+            health: Arc::new(HealthMonitor::new()),
+            shutdown: ShutdownSignal::new(),
+            alive: true,
+        }
+    }
+
+    /// The index of the group holding `param`, checking that it is held exactly once.
+    fn group_of(groups: &[Vec<String>], param: &str) -> usize {
+        let holders: Vec<usize> = (0..groups.len())
+            .filter(|&i| groups[i].iter().any(|p| p == param))
+            .collect();
+        assert_eq!(holders.len(), 1, "{} held by {:?}", param, holders);
+        holders[0]
+    }
+
+    #[test]
+    fn hash_sharding_keeps_the_params_of_a_symbol_together() {
+        let symbols: Vec<String> = (0..40).map(|i| format!("S{}", i)).collect();
+        let all: Vec<String> = symbols
+            .iter()
+            .flat_map(|symbol| [format!("T.{}", symbol), format!("Q.{}", symbol)])
+            .collect();
+        let groups = assign(&all, &hashed(4, 4)).unwrap();
+
+        assert_eq!(groups.len(), 4);
+        assert_eq!(groups.iter().map(Vec::len).sum::<usize>(), all.len());
+        for symbol in &symbols {
+            assert_eq!(
+                group_of(&groups, &format!("T.{}", symbol)),
+                group_of(&groups, &format!("Q.{}", symbol))
+            );
+        }
+        // the same params always get the same connections
+        assert_eq!(groups, assign(&all, &hashed(4, 4)).unwrap());
+    }
+
+    #[test]
+    fn hash_sharding_leaves_out_empty_connections() {
+        let groups = assign(&params("XT.BTC-USD,XQ.BTC-USD"), &hashed(3, 3)).unwrap();
+        assert_eq!(groups, vec![params("XT.BTC-USD,XQ.BTC-USD")]);
+    }
+
+    #[test]
+    fn rejects_more_connections_than_allowed() {
+        let error = assign(&params("T.AAPL,T.MSFT"), &hashed(3, 2)).unwrap_err();
+        assert!(matches!(error, StreamError::Config(_)), "{:?}", error);
+        let error = assign(&params("T.AAPL"), &hashed(0, 2)).unwrap_err();
+        assert!(matches!(error, StreamError::Config(_)), "{:?}", error);
+
+        let config = PoolConfig {
+            max_connections: 1,
+            sharding: Sharding::Groups(vec![vec!["AAPL".to_string()], vec!["MSFT".to_string()]]),
+            ..Default::default()
+        };
+        let error = assign(&params("T.AAPL,T.MSFT"), &config).unwrap_err();
+        assert!(matches!(error, StreamError::Config(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn rejects_no_subscriptions() {
+        let error = assign(&[], &hashed(1, 1)).unwrap_err();
+        assert!(matches!(error, StreamError::Config(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn rejects_wildcards_across_connections() {
+        let error = assign(&params("XT.*,XQ.BTC-USD"), &hashed(2, 2)).unwrap_err();
+        assert!(matches!(error, StreamError::Config(ref message) if message.contains("XT.*")));
+
+        let groups = assign(&params("XT.*,XQ.BTC-USD"), &hashed(1, 2)).unwrap();
+        assert_eq!(groups, vec![params("XT.*,XQ.BTC-USD")]);
+    }
+
+    #[test]
+    fn groups_pin_their_symbols_and_share_the_rest() {
+        let config = PoolConfig {
+            max_connections: 2,
+            sharding: Sharding::Groups(vec![
+                vec!["AAPL".to_string(), "MSFT".to_string()],
+                vec!["SPY".to_string()],
+            ]),
+            ..Default::default()
+        };
+        let all = params("T.AAPL,Q.SPY,T.MSFT,T.SPY,T.IBM,Q.IBM");
+        let groups = assign(&all, &config).unwrap();
+
+        assert_eq!(group_of(&groups, "T.AAPL"), 0);
+        assert_eq!(group_of(&groups, "T.MSFT"), 0);
+        assert_eq!(group_of(&groups, "T.SPY"), 1);
+        assert_eq!(group_of(&groups, "Q.SPY"), 1);
+        assert_eq!(group_of(&groups, "T.IBM"), group_of(&groups, "Q.IBM"));
+    }
+
+    #[test]
+    fn places_params_with_their_symbol_on_live_connections() {
+        let mut shards = vec![shard("T.AAPL"), shard("T.MSFT"), shard("T.SPY")];
+        assert_eq!(place(&shards, "Q.MSFT"), Some(1));
+        assert_eq!(place(&shards, "Q.SPY"), Some(2));
+
+        shards[1].alive = false;
+        for _ in 0..10 {
+            let target = place(&shards, "Q.MSFT").unwrap();
+            assert_ne!(target, 1);
+        }
+
+        for shard in &mut shards {
+            shard.alive = false;
+        }
+        assert_eq!(place(&shards, "Q.MSFT"), None);
+    }
+
+    #[test]
+    fn rebalances_the_subscriptions_of_a_failed_connection() {
+        let mut shards = vec![
+            shard("T.AAPL"),
+            shard("T.MSFT,Q.MSFT,T.IBM,Q.IBM,T.GE"),
+            shard("T.SPY"),
+        ];
+        shards[1].alive = false;
+
+        assert_eq!(rebalance(&shards, 1), Some(5));
+        let live: Vec<Vec<String>> = [0, 2]
+            .iter()
+            .map(|&i| shards[i].subscriptions.current())
+            .collect();
+        assert_eq!(live.iter().map(Vec::len).sum::<usize>(), 7);
+        for param in [
+            "T.AAPL", "T.SPY", "T.MSFT", "Q.MSFT", "T.IBM", "Q.IBM", "T.GE",
+        ] {
+            group_of(&live, param);
+        }
+        assert_eq!(group_of(&live, "T.MSFT"), group_of(&live, "Q.MSFT"));
+        assert_eq!(group_of(&live, "T.IBM"), group_of(&live, "Q.IBM"));
+    }
+
+    #[test]
+    fn cannot_rebalance_without_live_connections() {
+        let mut shards = vec![shard("T.AAPL"), shard("T.MSFT")];
+        shards[0].alive = false;
+        shards[1].alive = false;
+        assert_eq!(rebalance(&shards, 0), None);
+    }
+
+    #[tokio::test]
+    async fn supervise_returns_the_error_of_the_last_connection_to_end() {
+        let shards: Shards = Arc::new(Mutex::new(vec![shard("T.AAPL"), shard("T.MSFT")]));
+        let tasks = vec![
+            tokio::spawn(async { Err(StreamError::Closed) }),
+            tokio::spawn(async {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                Err(StreamError::Idle(std::time::Duration::from_secs(90)))
+            }),
+        ];
+        let outcome = supervise(tasks, shards, ShutdownSignal::new()).await;
+        assert!(
+            matches!(outcome, Err(StreamError::Idle(_))),
+            "{:?}",
+            outcome
+        );
+    }
+
+    #[tokio::test]
+    async fn supervise_shuts_every_connection_down() {
+        let shards: Shards = Arc::new(Mutex::new(vec![shard("T.AAPL"), shard("T.MSFT")]));
+        let tasks = shards
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|shard| {
+                let shutdown = shard.shutdown.clone();
+                tokio::spawn(async move {
+                    shutdown.triggered().await;
+                    Ok(())
+                })
+            })
+            .collect();
+        let shutdown = ShutdownSignal::new();
+        shutdown.trigger();
+        let outcome = supervise(tasks, shards.clone(), shutdown).await;
+        assert!(outcome.is_ok());
+        assert!(shards
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|shard| shard.shutdown.is_triggered()));
+    }
+}
@@ -0,0 +1,366 @@
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How often buffered frames are flushed to disk, bounding what is lost if the process is killed.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A raw text frame as received from a Polygon cluster, one line of a recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// The local time the frame was received.
+    #[serde(rename = "t", with = "chrono::serde::ts_nanoseconds")]
+    pub received_at: DateTime<Utc>,
+
+    /// The frame exactly as sent by the server.
+    #[serde(rename = "frame")]
+    pub frame: String,
+}
+
+/// A request to the writer thread of a `FrameRecorder`.
+enum Command {
+    Record(RecordedFrame),
+    Flush(SyncSender<io::Result<()>>),
+    Finish(SyncSender<io::Result<()>>),
+}
+
+/// The writer thread of a recording, shared by the clones of a `FrameRecorder`.
+struct RecorderThread {
+    /// `None` once dropped, which tells the thread to complete the file and exit.
+    commands: Option<Sender<Command>>,
+    thread: Option<JoinHandle<()>>,
+    /// The error that stopped the thread, if any.
+    failure: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl RecorderThread {
+    /// The error to report once the thread is gone.
+    fn stopped(&self) -> io::Error {
+        match &*self.failure.lock().unwrap() {
+            Some(e) => copy_error(e),
+            None => io::Error::other("The recording was stopped"),
+        }
+    }
+}
+
+impl Drop for RecorderThread {
+    fn drop(&mut self) {
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes every raw frame of a data channel to a gzip compressed NDJSON file, so a session can be
+/// replayed later with `replay::open_replay_channel`.
+///
+/// Enable it by setting `StreamConfig::recorder`. Frames are compressed and written on a
+/// dedicated thread, so recording never blocks the websocket read loop on disk I/O. The recorder
+/// is cheap to clone; clones write to the same file. The gzip stream is completed when the last
+/// clone is dropped, which waits for the writer thread, or when `finish` is called, and flushed
+/// at least every second in between.
+#[derive(Clone)]
+pub struct FrameRecorder {
+    thread: Arc<RecorderThread>,
+}
+
+impl FrameRecorder {
+    /// Creates the recording at `path`, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let (commands, received) = mpsc::channel();
+        let failure = Arc::new(Mutex::new(None));
+        let thread_failure = failure.clone();
+        let thread = thread::Builder::new()
+            .name("frame-recorder".to_string())
+            .spawn(move || write_frames(file, received, thread_failure))?;
+        Ok(FrameRecorder {
+            thread: Arc::new(RecorderThread {
+                commands: Some(commands),
+                thread: Some(thread),
+                failure,
+            }),
+        })
+    }
+
+    /// Queues a frame received at `received_at` for writing. Fails if writing a previous frame
+    /// failed.
+    pub fn record(&self, received_at: DateTime<Utc>, frame: &str) -> io::Result<()> {
+        self.send(Command::Record(RecordedFrame {
+            received_at,
+            frame: frame.to_string(),
+        }))
+    }
+
+    /// Writes all frames recorded so far to disk, waiting for the writer thread.
+    pub fn flush(&self) -> io::Result<()> {
+        let (reply, outcome) = mpsc::sync_channel(1);
+        self.send(Command::Flush(reply))?;
+        outcome.recv().map_err(|_| self.thread.stopped())?
+    }
+
+    /// Completes the gzip stream, waiting for the writer thread. Frames recorded afterwards start
+    /// a new gzip member, which `FrameReader` reads transparently.
+    pub fn finish(&self) -> io::Result<()> {
+        let (reply, outcome) = mpsc::sync_channel(1);
+        self.send(Command::Finish(reply))?;
+        outcome.recv().map_err(|_| self.thread.stopped())?
+    }
+
+    fn send(&self, command: Command) -> io::Result<()> {
+        self.thread
+            .commands
+            .as_ref()
+            .ok_or_else(|| self.thread.stopped())?
+            .send(command)
+            .map_err(|_| self.thread.stopped())
+    }
+}
+
+impl fmt::Debug for FrameRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameRecorder").finish_non_exhaustive()
+    }
+}
+
+/// Runs the writer thread of a recording until every `FrameRecorder` is dropped or writing fails.
+fn write_frames(file: File, commands: Receiver<Command>, failure: Arc<Mutex<Option<io::Error>>>) {
+    let mut writer = Some(GzEncoder::new(BufWriter::new(file), Compression::default()));
+    let mut last_flush = Instant::now();
+    let mut unflushed = false;
+    loop {
+        let Some(encoder) = writer.as_mut() else {
+            return;
+        };
+        let timeout = FLUSH_INTERVAL.saturating_sub(last_flush.elapsed());
+        let outcome = match commands.recv_timeout(timeout) {
+            Ok(Command::Record(frame)) => {
+                unflushed = true;
+                write_frame(encoder, &frame)
+            }
+            Ok(Command::Flush(reply)) => {
+                let outcome = encoder.flush();
+                last_flush = Instant::now();
+                unflushed = false;
+                let _ = reply.send(outcome.as_ref().map_err(copy_error).copied());
+                outcome
+            }
+            Ok(Command::Finish(reply)) => {
+                let outcome = finish(&mut writer);
+                last_flush = Instant::now();
+                unflushed = false;
+                let _ = reply.send(outcome.as_ref().map_err(copy_error).copied());
+                outcome
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                last_flush = Instant::now();
+                if unflushed {
+                    unflushed = false;
+                    encoder.flush()
+                } else {
+                    Ok(())
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Err(e) = finish(&mut writer) {
+                    warn!("Failed to finish recording: {}", e);
+                }
+                return;
+            }
+        };
+        if let Err(e) = outcome {
+            *failure.lock().unwrap() = Some(e);
+            return;
+        }
+    }
+}
+
+fn write_frame<W: Write>(writer: &mut W, frame: &RecordedFrame) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, frame)?;
+    writer.write_all(b"\n")
+}
+
+/// Completes the gzip member being written and starts a new one.
+fn finish(writer: &mut Option<GzEncoder<BufWriter<File>>>) -> io::Result<()> {
+    let Some(encoder) = writer.take() else {
+        return Ok(());
+    };
+    let mut file = encoder.finish()?;
+    file.flush()?;
+    *writer = Some(GzEncoder::new(file, Compression::default()));
+    Ok(())
+}
+
+/// `io::Error` is not `Clone`; keeps the kind and message of an error reported twice.
+fn copy_error(e: &io::Error) -> io::Error {
+    io::Error::new(e.kind(), e.to_string())
+}
+
+/// Reads the frames of a recording written by `FrameRecorder`, in order.
+///
+/// A recording cut short because the process was killed ends at its last complete frame.
+pub struct FrameReader {
+    lines: io::Lines<BufReader<MultiGzDecoder<File>>>,
+    done: bool,
+}
+
+impl FrameReader {
+    /// Opens the recording at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(FrameReader {
+            lines: BufReader::new(MultiGzDecoder::new(file)).lines(),
+            done: false,
+        })
+    }
+}
+
+impl Iterator for FrameReader {
+    type Item = io::Result<RecordedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.lines.next()? {
+            Ok(line) => Some(serde_json::from_str(&line).map_err(io::Error::from)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+
+    /// A path in the temporary directory, removed when dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            TempPath(std::env::temp_dir().join(format!(
+                "polyticker-{}-{}.ndjson.gz",
+                name,
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn frame(index: usize) -> String {
+        format!(r#"[{{"ev":"T","sym":"AAPL","q":{},"p":1.5}}]"#, index)
+    }
+
+    fn received_at(index: usize) -> DateTime<Utc> {
+        Utc.timestamp_nanos(1_700_000_000_000_000_000 + index as i64 * 1_000_123)
+    }
+
+    fn read(path: &Path) -> Vec<RecordedFrame> {
+        FrameReader::open(path)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_back_what_was_recorded() {
+        let path = TempPath::new("roundtrip");
+        let recorder = FrameRecorder::create(&path.0).unwrap();
+        let clone = recorder.clone();
+        for index in 0..100 {
+            let recorder = if index % 2 == 0 { &recorder } else { &clone };
+            recorder.record(received_at(index), &frame(index)).unwrap();
+        }
+        drop(recorder);
+        drop(clone);
+
+        let frames = read(&path.0);
+        assert_eq!(frames.len(), 100);
+        for (index, recorded) in frames.iter().enumerate() {
+            assert_eq!(recorded.frame, frame(index));
+            assert_eq!(recorded.received_at, received_at(index));
+        }
+    }
+
+    #[test]
+    fn reads_across_finished_members() {
+        let path = TempPath::new("members");
+        let recorder = FrameRecorder::create(&path.0).unwrap();
+        recorder.record(received_at(0), &frame(0)).unwrap();
+        recorder.finish().unwrap();
+        // complete on disk without dropping the recorder
+        assert_eq!(read(&path.0).len(), 1);
+        recorder.record(received_at(1), &frame(1)).unwrap();
+        recorder.flush().unwrap();
+        drop(recorder);
+
+        let frames: Vec<String> = read(&path.0).into_iter().map(|f| f.frame).collect();
+        assert_eq!(frames, vec![frame(0), frame(1)]);
+    }
+
+    #[test]
+    fn truncated_recording_ends_at_its_last_complete_frame() {
+        let path = TempPath::new("complete");
+        let recorder = FrameRecorder::create(&path.0).unwrap();
+        for index in 0..50 {
+            recorder.record(received_at(index), &frame(index)).unwrap();
+        }
+        recorder.flush().unwrap();
+        let flushed = std::fs::metadata(&path.0).unwrap().len() as usize;
+        for index in 50..100 {
+            recorder.record(received_at(index), &frame(index)).unwrap();
+        }
+        drop(recorder);
+
+        // as if the process was killed while writing the second half
+        let bytes = std::fs::read(&path.0).unwrap();
+        let truncated = TempPath::new("truncated");
+        std::fs::write(
+            &truncated.0,
+            &bytes[..flushed + (bytes.len() - flushed) / 2],
+        )
+        .unwrap();
+
+        let frames = FrameReader::open(&truncated.0)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert!(frames.len() >= 50 && frames.len() < 100, "{}", frames.len());
+        for (index, recorded) in frames.iter().enumerate() {
+            assert_eq!(recorded.frame, frame(index));
+        }
+    }
+
+    #[test]
+    fn empty_recording_has_no_frames() {
+        let path = TempPath::new("empty");
+        drop(FrameRecorder::create(&path.0).unwrap());
+        assert!(read(&path.0).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::websocket::backpressure::{self, Sender};
+use crate::websocket::config::StreamConfig;
+use crate::websocket::connection::{
+    forward_frame, EventStream, ShutdownSignal, StreamError, StreamHandle, Subscriptions,
+};
+use crate::websocket::health::HealthMonitor;
+use crate::websocket::recorder::{FrameReader, RecordedFrame};
+use crate::websocket::FeedEvent;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use tracing::info;
+
+/// How many frames are read and decompressed ahead of the replay.
+const READ_AHEAD: usize = 1024;
+
+/// How fast a recording is replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// With the original gaps between frames.
+    Original,
+    /// With the original gaps between frames divided by the factor, e.g. `10.0` replays an hour in
+    /// six minutes. A factor of zero or less replays unpaced.
+    Accelerated(f64),
+    /// Without any pauses, as fast as the consumer keeps up.
+    Unpaced,
+}
+
+impl ReplaySpeed {
+    /// The wall clock time to wait for a gap of `elapsed` in the recording.
+    fn scale(&self, elapsed: Duration) -> Option<Duration> {
+        match self {
+            ReplaySpeed::Original => Some(elapsed),
+            ReplaySpeed::Accelerated(factor) if *factor > 0.0 => Some(elapsed.div_f64(*factor)),
+            ReplaySpeed::Accelerated(_) | ReplaySpeed::Unpaced => None,
+        }
+    }
+}
+
+/// Replays a recording written by `FrameRecorder`, decoding its frames exactly like a live data
+/// channel and delivering them through the same `EventStream` and `StreamHandle`.
+///
+/// The stream ends after the last frame, or once the handle shuts it down. A recording that cannot be read is reported through
+/// the stream and the handle as `StreamError::Recording`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the recording.
+/// * `speed` - How fast to replay the recording.
+/// * `output_channel_size` - The capacity of the event channel.
+pub fn open_replay_channel<E: FeedEvent, P: AsRef<Path>>(
+    path: P,
+    speed: ReplaySpeed,
+    output_channel_size: usize,
+) -> Result<(EventStream<E>, StreamHandle), StreamError> {
+    open_replay_channel_with_config(
+        path,
+        speed,
+        StreamConfig::with_channel_size(output_channel_size),
+    )
+}
+
+/// Replays a recording like `open_replay_channel`, buffering events as configured. Only the
+/// channel size and backpressure policy of `config` apply to a replay.
+pub fn open_replay_channel_with_config<E: FeedEvent, P: AsRef<Path>>(
+    path: P,
+    speed: ReplaySpeed,
+    config: StreamConfig,
+) -> Result<(EventStream<E>, StreamHandle), StreamError> {
+    let reader = FrameReader::open(path).map_err(|e| StreamError::Recording(Arc::new(e)))?;
+    let health = Arc::new(HealthMonitor::new());
+    health.on_connected(false);
+    let (tx, rx) = backpressure::channel(config.output_channel_size, config.backpressure);
+    let shutdown = ShutdownSignal::new();
+    let task = tokio::task::spawn(replay(reader, speed, tx, health.clone(), shutdown.clone()));
+    Ok((
+        EventStream::new(rx),
+        StreamHandle::new(task, health, shutdown, Subscriptions::new("")),
+    ))
+}
+
+async fn replay<E: FeedEvent>(
+    reader: FrameReader,
+    speed: ReplaySpeed,
+    tx: Sender<E>,
+    health: Arc<HealthMonitor>,
+    shutdown: ShutdownSignal,
+) -> Result<(), StreamError> {
+    tokio::select! {
+        outcome = replay_frames(reader, speed, &tx, &health) => outcome,
+        _ = shutdown.triggered() => {
+            info!("Replay shut down");
+            health.on_disconnected();
+            Ok(())
+        }
+    }
+}
+
+async fn replay_frames<E: FeedEvent>(
+    reader: FrameReader,
+    speed: ReplaySpeed,
+    tx: &Sender<E>,
+    health: &HealthMonitor,
+) -> Result<(), StreamError> {
+    let mut frames = read_frames(reader);
+    let started = Instant::now();
+    let mut first_received_at = None;
+    while let Some(frame) = frames.recv().await {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                let error = StreamError::Recording(Arc::new(e));
+                health.on_disconnected();
+                let _ = tx.send(Err(error.clone())).await;
+                return Err(error);
+            }
+        };
+        let first_received_at = *first_received_at.get_or_insert(frame.received_at);
+        let elapsed = (frame.received_at - first_received_at)
+            .to_std()
+            .unwrap_or_default();
+        if let Some(delay) = speed.scale(elapsed) {
+            sleep_until(started + delay).await;
+        }
+        health.on_frame();
+        if forward_frame(&frame.frame, tx, health).await.is_err() {
+            info!("Receiver dropped");
+            return Ok(());
+        }
+    }
+    health.on_disconnected();
+    Ok(())
+}
+
+/// Reads the frames of a recording on a blocking thread, keeping file I/O and decompression off
+/// the runtime. Reading stops when the receiver is dropped.
+fn read_frames(reader: FrameReader) -> mpsc::Receiver<io::Result<RecordedFrame>> {
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    tokio::task::spawn_blocking(move || {
+        for frame in reader {
+            if tx.blocking_send(frame).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::recorder::FrameRecorder;
+    use crate::websocket::stocks::StocksEvent;
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn replays_a_recording_in_order() {
+        let path =
+            std::env::temp_dir().join(format!("polyticker-replay-{}.gz", std::process::id()));
+        let recorder = FrameRecorder::create(&path).unwrap();
+        for sequence_number in 0..2_000 {
+            let frame = format!(
+                r#"[{{"ev":"T","sym":"AAPL","x":4,"i":"1","z":3,"p":1.5,"s":100,"c":[],"t":1700000000000,"q":{}}}]"#,
+                sequence_number
+            );
+            recorder.record(Utc::now(), &frame).unwrap();
+        }
+        drop(recorder);
+
+        let (mut stream, _handle) =
+            open_replay_channel::<StocksEvent, _>(&path, ReplaySpeed::Unpaced, 16).unwrap();
+        let mut sequence_numbers = Vec::new();
+        while let Some(event) = stream.recv().await {
+            match event.unwrap() {
+                StocksEvent::Trade(trade) => sequence_numbers.push(trade.sequence_number),
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sequence_numbers, (0..2_000).collect::<Vec<i64>>());
+    }
+}
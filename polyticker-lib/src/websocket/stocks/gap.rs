@@ -0,0 +1,596 @@
+use crate::common::instrument::Instrument;
+use crate::request::stocks::trades::{HistoricalTrade, Trades};
+use crate::websocket::connection::StreamError;
+use crate::websocket::stocks::{StocksEvent, TradeEvent};
+use crate::websocket::symbol::Symbol;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+
+/// The kind of sequence irregularity detected for a symbol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GapKind {
+    /// The sequence number jumped further than allowed, trades in between are missing.
+    Missing { expected: i64, received: i64 },
+    /// The sequence number did not increase (a late or duplicate trade).
+    OutOfOrder { last: i64, received: i64 },
+}
+
+/// A sequence irregularity in the trades of a symbol.
+#[derive(Clone, Debug)]
+pub struct GapEvent {
+    pub symbol: String,
+    pub kind: GapKind,
+    /// The timestamp of the last trade seen before the gap.
+    pub from: DateTime<Utc>,
+    /// The timestamp of the trade that revealed the gap.
+    pub to: DateTime<Utc>,
+}
+
+impl GapEvent {
+    /// Whether the trade with `sequence_number` is one of those missing from the gap: strictly
+    /// between the trades surrounding it. Always false for out-of-order gaps.
+    pub fn contains(&self, sequence_number: i64) -> bool {
+        match self.kind {
+            GapKind::Missing { expected, received } => {
+                (expected..received).contains(&sequence_number)
+            }
+            GapKind::OutOfOrder { .. } => false,
+        }
+    }
+}
+
+/// The last trade seen for a symbol.
+struct LastTrade {
+    sequence_number: i64,
+    timestamp: DateTime<Utc>,
+}
+
+/// Tracks the sequence numbers of stock trades per symbol and flags missing or out-of-order ones.
+///
+/// Polygon sequence numbers increase per symbol but are not contiguous: the feed skips numbers
+/// for events it does not send as trades. A jump alone therefore does not prove that trades are
+/// missing, and by default only late and duplicate prints are reported. `with_max_step` also
+/// reports jumps larger than a step known to be safe for the feed.
+pub struct SequenceGapDetector {
+    max_step: Option<i64>,
+    last: HashMap<Symbol, LastTrade>,
+}
+
+impl Default for SequenceGapDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SequenceGapDetector {
+    /// Creates a detector that reports out-of-order and duplicate trades only.
+    pub fn new() -> Self {
+        SequenceGapDetector {
+            max_step: None,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Creates a detector that also reports a missing gap when the sequence number of a symbol
+    /// increases by more than `max_step`.
+    pub fn with_max_step(max_step: i64) -> Self {
+        SequenceGapDetector {
+            max_step: Some(max_step.max(1)),
+            last: HashMap::new(),
+        }
+    }
+
+    /// Checks the sequence number of a trade against the last trade of its symbol.
+    pub fn check(&mut self, trade: &TradeEvent) -> Option<GapEvent> {
+        let Some(last) = self.last.get_mut(&trade.symbol) else {
+            self.last.insert(
+                trade.symbol.clone(),
+                LastTrade {
+                    sequence_number: trade.sequence_number,
+                    timestamp: trade.timestamp,
+                },
+            );
+            return None;
+        };
+        let kind = if trade.sequence_number <= last.sequence_number {
+            GapKind::OutOfOrder {
+                last: last.sequence_number,
+                received: trade.sequence_number,
+            }
+        } else if self
+            .max_step
+            .is_some_and(|max_step| trade.sequence_number - last.sequence_number > max_step)
+        {
+            GapKind::Missing {
+                expected: last.sequence_number + 1,
+                received: trade.sequence_number,
+            }
+        } else {
+            last.sequence_number = trade.sequence_number;
+            last.timestamp = trade.timestamp;
+            return None;
+        };
+        let gap = GapEvent {
+            symbol: trade.symbol.to_string(),
+            kind,
+            from: last.timestamp,
+            to: trade.timestamp,
+        };
+        // a late trade does not move the reference, the trade after a missing gap does
+        if let GapKind::Missing { .. } = gap.kind {
+            last.sequence_number = trade.sequence_number;
+            last.timestamp = trade.timestamp;
+        }
+        Some(gap)
+    }
+
+    /// Forgets the last trade of every symbol.
+    pub fn reset(&mut self) {
+        self.last.clear();
+    }
+}
+
+/// The shortest time between two backfill requests by default.
+const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fetches the trades missing from gaps through the historical trades endpoint.
+pub struct Backfill {
+    trades: Trades,
+    min_interval: Duration,
+}
+
+impl Backfill {
+    /// Creates a new `Backfill` with the provided API key.
+    pub fn new(api_key: String) -> Self {
+        Self::with_trades(Trades::new(api_key))
+    }
+
+    /// Creates a new `Backfill` fetching through the given client, e.g. one with another base URL.
+    pub fn with_trades(trades: Trades) -> Self {
+        Backfill {
+            trades,
+            min_interval: DEFAULT_MIN_INTERVAL,
+        }
+    }
+
+    /// Waits at least `min_interval` between two requests of a `GapDetectingStream`, one second
+    /// by default. The gaps of a symbol revealed meanwhile are fetched with a single request.
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Fetches the trades missing from a gap: those of its symbol with a sequence number strictly
+    /// between the trades surrounding the gap.
+    pub async fn fetch(&self, gap: &GapEvent) -> Result<Vec<HistoricalTrade>, reqwest::Error> {
+        let mut trades = self.fetch_window(&gap.symbol, gap.from, gap.to).await?;
+        trades.retain(|trade| gap.contains(trade.sequence_number));
+        Ok(trades)
+    }
+
+    /// Fetches every trade of a symbol between two timestamps of the feed. The feed truncates
+    /// timestamps to the millisecond, so the window extends to the end of the millisecond of
+    /// `to`: trades missing from a gap may be a few microseconds later than `to`.
+    async fn fetch_window(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<HistoricalTrade>, reqwest::Error> {
+        self.trades
+            .get_all_trades_before(
+                &Instrument::equity(symbol),
+                from,
+                to + chrono::Duration::milliseconds(1),
+            )
+            .await
+    }
+}
+
+/// The gaps of a symbol waiting to be backfilled, fetched with one request spanning all of them.
+struct QueuedBackfill {
+    symbol: String,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    gaps: Vec<GapEvent>,
+}
+
+/// Adds a gap to the queued backfill of its symbol, or queues a new one.
+fn queue(queued: &mut VecDeque<QueuedBackfill>, gap: GapEvent) {
+    match queued
+        .iter_mut()
+        .find(|backfill| backfill.symbol == gap.symbol)
+    {
+        Some(backfill) => {
+            backfill.from = backfill.from.min(gap.from);
+            backfill.to = backfill.to.max(gap.to);
+            backfill.gaps.push(gap);
+        }
+        None => queued.push_back(QueuedBackfill {
+            symbol: gap.symbol.clone(),
+            from: gap.from,
+            to: gap.to,
+            gaps: vec![gap],
+        }),
+    }
+}
+
+/// Backfills the gaps of a `GapDetectingStream`, one request at a time and at most one per
+/// `min_interval`, emitting a backfill event per gap.
+async fn run_backfills(
+    backfill: Backfill,
+    mut gaps: UnboundedReceiver<GapEvent>,
+    events: UnboundedSender<SequencedEvent>,
+) {
+    let mut queued = VecDeque::new();
+    let mut next_request = Instant::now();
+    loop {
+        if queued.is_empty() {
+            match gaps.recv().await {
+                Some(gap) => queue(&mut queued, gap),
+                None => return,
+            }
+        }
+        tokio::time::sleep_until(next_request).await;
+        // gaps revealed while waiting join the queued backfill of their symbol
+        while let Ok(gap) = gaps.try_recv() {
+            queue(&mut queued, gap);
+        }
+        let Some(next) = queued.pop_front() else {
+            continue;
+        };
+        next_request = Instant::now() + backfill.min_interval;
+        let result = backfill
+            .fetch_window(&next.symbol, next.from, next.to)
+            .await;
+        for gap in next.gaps {
+            let event = match &result {
+                Ok(trades) => SequencedEvent::Backfill {
+                    trades: trades
+                        .iter()
+                        .filter(|trade| gap.contains(trade.sequence_number))
+                        .cloned()
+                        .collect(),
+                    gap,
+                },
+                Err(e) => SequencedEvent::BackfillFailed {
+                    gap,
+                    error: e.to_string(),
+                },
+            };
+            // the stream may have been dropped in the meantime
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The items of a `GapDetectingStream`.
+#[derive(Debug)]
+pub enum SequencedEvent {
+    /// An event of the underlying stream.
+    Event(StocksEvent),
+    /// A gap detected before the trade that follows it.
+    Gap(GapEvent),
+    /// The trades missing from a gap, if backfilling is enabled.
+    Backfill {
+        gap: GapEvent,
+        trades: Vec<HistoricalTrade>,
+    },
+    /// Backfilling a missing gap failed.
+    BackfillFailed { gap: GapEvent, error: String },
+}
+
+/// Wraps a stream of stocks events, emitting a `SequencedEvent::Gap` ahead of every trade that
+/// reveals a sequence gap and, optionally, the backfilled trades of missing gaps once fetched.
+pub struct GapDetectingStream<S> {
+    inner: S,
+    inner_done: bool,
+    detector: SequenceGapDetector,
+    pending: VecDeque<SequencedEvent>,
+    backfill: Option<Backfill>,
+    /// Sends missing gaps to the backfill task, once started.
+    backfill_gaps: Option<UnboundedSender<GapEvent>>,
+    backfill_tx: UnboundedSender<SequencedEvent>,
+    backfill_rx: UnboundedReceiver<SequencedEvent>,
+    backfills_in_flight: usize,
+}
+
+impl<S> GapDetectingStream<S>
+where
+    S: Stream<Item = Result<StocksEvent, StreamError>> + Unpin,
+{
+    /// Wraps `inner`, checking its trades with `detector`.
+    pub fn new(inner: S, detector: SequenceGapDetector) -> Self {
+        let (backfill_tx, backfill_rx) = mpsc::unbounded_channel();
+        GapDetectingStream {
+            inner,
+            inner_done: false,
+            detector,
+            pending: VecDeque::new(),
+            backfill: None,
+            backfill_gaps: None,
+            backfill_tx,
+            backfill_rx,
+            backfills_in_flight: 0,
+        }
+    }
+
+    /// Fetches the trades of every missing gap in the background and emits them as
+    /// `SequencedEvent::Backfill`. Requests are rate limited and coalesced per symbol, see
+    /// `Backfill::with_min_interval`. Missing gaps are only reported by a detector created
+    /// `with_max_step`. Requires a tokio runtime.
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = Some(backfill);
+        self
+    }
+
+    fn start_backfill(&mut self, gap: GapEvent) {
+        if self.backfill_gaps.is_none() {
+            let Some(backfill) = self.backfill.take() else {
+                return;
+            };
+            let (gaps_tx, gaps_rx) = mpsc::unbounded_channel();
+            tokio::task::spawn(run_backfills(backfill, gaps_rx, self.backfill_tx.clone()));
+            self.backfill_gaps = Some(gaps_tx);
+        }
+        if let Some(gaps) = &self.backfill_gaps {
+            if gaps.send(gap).is_ok() {
+                self.backfills_in_flight += 1;
+            }
+        }
+    }
+}
+
+impl<S> Stream for GapDetectingStream<S>
+where
+    S: Stream<Item = Result<StocksEvent, StreamError>> + Unpin,
+{
+    type Item = Result<SequencedEvent, StreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Poll::Ready(Some(Ok(event)));
+        }
+        if let Poll::Ready(Some(event)) = self.backfill_rx.poll_recv(cx) {
+            self.backfills_in_flight -= 1;
+            return Poll::Ready(Some(Ok(event)));
+        }
+        if !self.inner_done {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(StocksEvent::Trade(trade)))) => {
+                    if let Some(gap) = self.detector.check(&trade) {
+                        if matches!(gap.kind, GapKind::Missing { .. }) {
+                            self.start_backfill(gap.clone());
+                        }
+                        self.pending
+                            .push_back(SequencedEvent::Event(StocksEvent::Trade(trade)));
+                        return Poll::Ready(Some(Ok(SequencedEvent::Gap(gap))));
+                    }
+                    return Poll::Ready(Some(Ok(SequencedEvent::Event(StocksEvent::Trade(trade)))));
+                }
+                Poll::Ready(Some(Ok(event))) => {
+                    return Poll::Ready(Some(Ok(SequencedEvent::Event(event))))
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => self.inner_done = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        if self.backfills_in_flight == 0 {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn trade(symbol: &str, sequence_number: i64, millis: i64) -> TradeEvent {
+        serde_json::from_value(json!({
+            "ev": "T",
+            "sym": symbol,
+            "x": 4,
+            "i": sequence_number.to_string(),
+            "z": 3,
+            "p": 100.0,
+            "s": 100,
+            "c": [],
+            "t": millis,
+            "q": sequence_number,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_missing_trades_beyond_the_step() {
+        let mut detector = SequenceGapDetector::with_max_step(1);
+        assert!(detector.check(&trade("AAPL", 10, 1_000)).is_none());
+        assert!(detector.check(&trade("AAPL", 11, 1_001)).is_none());
+        let gap = detector.check(&trade("AAPL", 15, 1_005)).unwrap();
+        assert_eq!(gap.symbol, "AAPL");
+        assert_eq!(
+            gap.kind,
+            GapKind::Missing {
+                expected: 12,
+                received: 15
+            }
+        );
+        assert_eq!(gap.from, Utc.timestamp_millis_opt(1_001).unwrap());
+        assert_eq!(gap.to, Utc.timestamp_millis_opt(1_005).unwrap());
+        assert!(!gap.contains(11));
+        assert!(gap.contains(12));
+        assert!(gap.contains(14));
+        assert!(!gap.contains(15));
+    }
+
+    #[test]
+    fn accepts_jumps_within_the_step() {
+        let mut detector = SequenceGapDetector::with_max_step(100);
+        assert!(detector.check(&trade("AAPL", 10, 1_000)).is_none());
+        assert!(detector.check(&trade("AAPL", 110, 1_001)).is_none());
+        assert!(detector.check(&trade("AAPL", 211, 1_002)).is_some());
+    }
+
+    #[test]
+    fn reports_only_out_of_order_trades_by_default() {
+        let mut detector = SequenceGapDetector::new();
+        assert!(detector.check(&trade("AAPL", 10, 1_000)).is_none());
+        assert!(detector.check(&trade("AAPL", 5_000, 1_001)).is_none());
+        let gap = detector.check(&trade("AAPL", 5_000, 1_002)).unwrap();
+        assert_eq!(
+            gap.kind,
+            GapKind::OutOfOrder {
+                last: 5_000,
+                received: 5_000
+            }
+        );
+        assert!(!gap.contains(5_000));
+    }
+
+    #[test]
+    fn late_trades_do_not_move_the_reference() {
+        let mut detector = SequenceGapDetector::with_max_step(1);
+        detector.check(&trade("AAPL", 10, 1_000));
+        let late = detector.check(&trade("AAPL", 8, 999)).unwrap();
+        assert_eq!(
+            late.kind,
+            GapKind::OutOfOrder {
+                last: 10,
+                received: 8
+            }
+        );
+        // still compared with 10, not 8
+        assert!(detector.check(&trade("AAPL", 11, 1_001)).is_none());
+    }
+
+    #[test]
+    fn missing_gaps_move_the_reference() {
+        let mut detector = SequenceGapDetector::with_max_step(1);
+        detector.check(&trade("AAPL", 10, 1_000));
+        assert!(detector.check(&trade("AAPL", 20, 1_010)).is_some());
+        // the gap is reported once, the next trade follows 20
+        assert!(detector.check(&trade("AAPL", 21, 1_011)).is_none());
+        let gap = detector.check(&trade("AAPL", 19, 1_012)).unwrap();
+        assert_eq!(
+            gap.kind,
+            GapKind::OutOfOrder {
+                last: 21,
+                received: 19
+            }
+        );
+    }
+
+    #[test]
+    fn tracks_symbols_separately() {
+        let mut detector = SequenceGapDetector::with_max_step(1);
+        detector.check(&trade("AAPL", 10, 1_000));
+        assert!(detector.check(&trade("MSFT", 3, 1_001)).is_none());
+        assert!(detector.check(&trade("AAPL", 11, 1_002)).is_none());
+        assert!(detector.check(&trade("MSFT", 4, 1_003)).is_none());
+        detector.reset();
+        assert!(detector.check(&trade("AAPL", 1, 1_004)).is_none());
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn coalesces_backfills_and_keeps_missing_trades_only() {
+        use crate::request::mock::{trades_pages, MockRestServer};
+        use futures_util::StreamExt;
+
+        let server = MockRestServer::start().await.unwrap();
+        let from = Utc.timestamp_millis_opt(1_000).unwrap();
+        // sequence numbers 1 to 10, one millisecond apart
+        server.mock_paginated("/v3/trades/AAPL", trades_pages(from, 10, 4));
+        let trades = [1, 5, 6, 9, 10].map(|sequence_number| {
+            Ok(StocksEvent::Trade(trade(
+                "AAPL",
+                sequence_number,
+                999 + sequence_number,
+            )))
+        });
+        let backfill = Backfill::with_trades(Trades::with_base_url("key".into(), server.url()))
+            .with_min_interval(Duration::from_secs(60));
+        let stream = GapDetectingStream::new(
+            futures_util::stream::iter(trades),
+            SequenceGapDetector::with_max_step(1),
+        )
+        .with_backfill(backfill);
+        let events: Vec<SequencedEvent> = stream.map(Result::unwrap).collect().await;
+
+        let gaps = events
+            .iter()
+            .filter(|event| matches!(event, SequencedEvent::Gap(_)))
+            .count();
+        assert_eq!(gaps, 2);
+        let backfilled: Vec<Vec<i64>> = events
+            .iter()
+            .filter_map(|event| match event {
+                SequencedEvent::Backfill { trades, .. } => {
+                    Some(trades.iter().map(|trade| trade.sequence_number).collect())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(backfilled, vec![vec![2, 3, 4], vec![7, 8]]);
+        // both gaps were fetched with one paginated request
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn backfills_trades_within_the_millisecond_of_the_gap_end() {
+        use crate::request::mock::{MockResponse, MockRestServer};
+
+        let historical = |sequence_number: i64, nanos: i64| {
+            json!({
+                "exchange": 4,
+                "id": sequence_number.to_string(),
+                "price": 100.0,
+                "sequence_number": sequence_number,
+                "sip_timestamp": nanos,
+                "size": 100,
+            })
+        };
+        let server = MockRestServer::start().await.unwrap();
+        // the trade closing the gap happened at 1_005.004 ms, the feed reports 1_005 ms
+        server.mock(
+            "/v3/trades/AAPL",
+            MockResponse::json(&json!({
+                "request_id": "1",
+                "status": "OK",
+                "results": [
+                    historical(11, 1_003_000_000),
+                    historical(12, 1_005_002_000),
+                    historical(13, 1_005_004_000),
+                ],
+            })),
+        );
+        let mut detector = SequenceGapDetector::with_max_step(1);
+        detector.check(&trade("AAPL", 10, 1_000));
+        let gap = detector.check(&trade("AAPL", 13, 1_005)).unwrap();
+
+        let backfill = Backfill::with_trades(Trades::with_base_url("key".into(), server.url()));
+        let trades = backfill.fetch(&gap).await.unwrap();
+        let sequence_numbers: Vec<i64> = trades.iter().map(|trade| trade.sequence_number).collect();
+        assert_eq!(sequence_numbers, vec![11, 12]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].query_param("timestamp.gte"), Some("1000000000"));
+        assert_eq!(requests[0].query_param("timestamp.lt"), Some("1006000000"));
+        assert_eq!(requests[0].query_param("timestamp.lte"), None);
+    }
+}
@@ -1,102 +1,119 @@
+pub mod gap;
+
+use crate::common::conditions::SipCondition;
+use crate::common::instrument::Instrument;
+use crate::common::trade::{Tape, Trade, TradeData};
+use crate::exchange::registry::{exchange_name, AssetClass};
 use crate::util::TimeUtil;
+use crate::websocket::config::{MarketHours, StreamConfig};
+use crate::websocket::connection::{self, EventStream, StreamError, StreamHandle};
+use crate::websocket::pool::{self, PoolConfig, PoolHandle};
+use crate::websocket::symbol::Symbol;
+use crate::websocket::{FeedEvent, StatusEvent};
 use chrono::{DateTime, Utc};
-use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::Message;
+use std::borrow::Cow;
+use std::fmt;
 
 pub struct Stocks {}
 
-pub struct DataChannel {
-    ws_stream: tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
-}
-
 impl Stocks {
+    /// Opens a channel of events from the (delayed) stocks cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - A string representing the Polygon API key.
+    /// * `tickers` - The subscription parameters, e.g. `T.MSFT,T.LPL`.
+    /// * `output_channel_size` - The capacity of the returned channel.
     pub async fn open_data_channel(
         api_key: String,
+        tickers: String,
         output_channel_size: usize,
-    ) -> Receiver<TradeEvent> {
-        let (mut tx, rx): (Sender<TradeEvent>, Receiver<TradeEvent>) =
-            mpsc::channel(output_channel_size);
-
-        tokio::task::spawn(async move {
-            let url = "wss://delayed.polygon.io/stocks";
-
-            // Connect to the server
-            let (mut ws_stream, _) = connect_async(url)
-                .await
-                .expect("Could not connect to the server");
-
-            println!("Connected to {}", url);
-
-            let msg = ws_stream.next().await;
-
-            if let Some(Ok(message)) = msg {
-                let response: Value = serde_json::from_str(&message.to_string())
-                    .expect("Failed to parse the message");
-                if response[0]["status"] == "connected" {
-                    println!("Connected Successfully!");
-
-                    let key = format!(r#"{{"action":"auth","params":"{}"}}"#, api_key);
-                    // Authenticate
-                    ws_stream
-                        .send(Message::Text(key))
-                        .await
-                        .expect("Failed to send auth message");
-
-                    // Read the auth response
-                    let auth_msg = ws_stream.next().await;
-
-                    if let Some(Ok(auth_response)) = auth_msg {
-                        let auth_value: Value = serde_json::from_str(&auth_response.to_string())
-                            .expect("Failed to parse the auth message");
-                        if auth_value[0]["status"] == "auth_success" {
-                            println!("Authenticated!");
-
-                            // Subscribe
-                            let sub_msg = r#"{"action":"subscribe","params":"AM.LPL,AM.MSFT"}"#;
-                            ws_stream
-                                .send(Message::Text(sub_msg.to_string()))
-                                .await
-                                .expect("Failed to send subscribe message");
-                        } else {
-                            println!("Authentication failed!");
-                            return;
-                        }
-                    }
-                }
-            }
+    ) -> Result<(EventStream<StocksEvent>, StreamHandle), StreamError> {
+        let config = StreamConfig {
+            market_hours: Some(MarketHours::us_equities()),
+            ..StreamConfig::with_channel_size(output_channel_size)
+        };
+        Self::open_data_channel_with_config(api_key, tickers, config).await
+    }
+
+    /// Opens a channel of events like `open_data_channel`, with custom heartbeat, idle detection
+    /// and reconnection settings.
+    pub async fn open_data_channel_with_config(
+        api_key: String,
+        tickers: String,
+        config: StreamConfig,
+    ) -> Result<(EventStream<StocksEvent>, StreamHandle), StreamError> {
+        connection::open_data_channel(
+            "wss://delayed.polygon.io/stocks",
+            &api_key,
+            &tickers,
+            config,
+        )
+        .await
+    }
+
+    /// Opens a pool of connections sharing the subscriptions in `tickers`, merged into one stream,
+    /// see `PoolConfig`.
+    pub async fn open_pool(
+        api_key: String,
+        tickers: String,
+        config: PoolConfig,
+    ) -> Result<(EventStream<StocksEvent>, PoolHandle), StreamError> {
+        pool::open_pool(
+            "wss://delayed.polygon.io/stocks",
+            &api_key,
+            &tickers,
+            config,
+        )
+        .await
+    }
+}
+
+/// All events that can be received from the stocks cluster.
+#[derive(Clone, Debug)]
+pub enum StocksEvent {
+    /// A trade (`T`).
+    Trade(TradeEvent),
+    /// A connection, authentication or subscription status message (`status`).
+    Status(StatusEvent),
+}
 
-            loop {
-                let msg = ws_stream.next().await;
-                match msg {
-                    Some(Ok(message)) => {
-                        println!("Received: {:?}", message);
-                        match &message {
-                            Message::Text(text) => {
-                                let value: Value = serde_json::from_str(&message.to_string())
-                                    .expect("Failed to parse the message");
-                                if let Some(trade_event) = TradeEvent::from_value(&value) {
-                                    if tx.send(trade_event).await.is_err() {
-                                        eprintln!("Receiver dropped!");
-                                        break;
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    Some(Err(e)) => {
-                        println!("Error: {}", e);
-                        break;
-                    }
-                    None => break,
-                }
+impl FeedEvent for StocksEvent {
+    fn decode(event_type: &str, json: &str) -> anyhow::Result<Self> {
+        let event = match event_type {
+            "T" => StocksEvent::Trade(serde_json::from_str(json)?),
+            "status" => StocksEvent::Status(serde_json::from_str(json)?),
+            other => {
+                return Err(anyhow::Error::msg(format!(
+                    "Unknown stocks event type: {}",
+                    other
+                )))
             }
-        });
-        rx
+        };
+        Ok(event)
+    }
+
+    fn event_type(&self) -> &str {
+        match self {
+            StocksEvent::Trade(event) => &event.event_type,
+            StocksEvent::Status(event) => &event.event_type,
+        }
+    }
+
+    fn symbol(&self) -> Option<Cow<'_, str>> {
+        match self {
+            StocksEvent::Trade(event) => Some(Cow::Borrowed(&event.symbol)),
+            StocksEvent::Status(_) => None,
+        }
+    }
+
+    fn exchange_id(&self) -> Option<i64> {
+        match self {
+            StocksEvent::Trade(event) => Some(event.exchange_id as i64),
+            StocksEvent::Status(_) => None,
+        }
     }
 }
 
@@ -107,43 +124,57 @@ pub enum TradeEventType {
 }
 
 /// Represents a trade event data structure received from the server.
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct TradeEvent {
     #[serde(rename = "ev")]
-    event_type: String,
+    pub event_type: Symbol,
 
     #[serde(rename = "sym")]
-    symbol: String,
+    pub symbol: Symbol,
 
     #[serde(rename = "x")]
-    exchange_id: u64,
+    pub exchange_id: u64,
 
     #[serde(rename = "i")]
-    trade_id: String,
+    pub trade_id: String,
 
     #[serde(rename = "z")]
-    tape: u64,
+    pub tape: u64,
 
     #[serde(rename = "p")]
-    price: f64,
+    pub price: f64,
 
     #[serde(rename = "s")]
-    trade_size: u64,
+    pub trade_size: u64,
 
-    #[serde(rename = "c")]
-    trade_conditions: Vec<i32>,
+    #[serde(rename = "c", default)]
+    pub trade_conditions: Vec<i32>,
 
     #[serde(rename = "t", deserialize_with = "TimeUtil::timestamp_milliseconds")]
-    timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
 
     #[serde(rename = "q")]
-    sequence_number: i64,
+    pub sequence_number: i64,
 
     #[serde(rename = "trfi", default)]
-    trade_reporting_facility_id: Option<i64>,
+    pub trade_reporting_facility_id: Option<i64>,
 
     #[serde(rename = "trft", default)]
-    trf_timestamp: Option<u64>,
+    pub trf_timestamp: Option<u64>,
+}
+
+impl fmt::Display for TradeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} x {} on {} at {}",
+            self.symbol,
+            self.price,
+            self.trade_size,
+            exchange_name(AssetClass::Stocks, self.exchange_id as i64),
+            self.timestamp
+        )
+    }
 }
 
 impl TradeEvent {
@@ -154,4 +185,89 @@ impl TradeEvent {
             None
         }
     }
+
+    /// The conditions of the trade, decoded.
+    pub fn sip_conditions(&self) -> impl Iterator<Item = SipCondition> + '_ {
+        self.trade_conditions
+            .iter()
+            .map(|id| SipCondition::from_id(*id))
+    }
+}
+
+impl Trade for TradeEvent {
+    fn get_trade(&self) -> anyhow::Result<TradeData<'_>> {
+        Ok(TradeData {
+            instrument: Instrument::Equity(self.symbol.clone()),
+            price: self.price,
+            size: self.trade_size as f64,
+            timestamp: self.timestamp,
+            exchange_id: self.exchange_id as i64,
+            trade_id: Some(&self.trade_id),
+            conditions: &self.trade_conditions,
+            tape: Tape::from_id(self.tape as i64),
+            received_at: None,
+        })
+    }
+
+    fn asset_class(&self) -> AssetClass {
+        AssetClass::Stocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::conditions::SipCondition;
+    use crate::websocket::connection::decode_frame;
+    use chrono::TimeZone;
+
+    /// Decodes a frame holding a single trade.
+    fn decode_trade(frame: &str) -> TradeEvent {
+        let mut events = decode_frame::<StocksEvent>(frame);
+        assert_eq!(events.len(), 1);
+        match events.pop().unwrap() {
+            Ok(StocksEvent::Trade(trade)) => trade,
+            other => panic!("not a trade: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_trade() {
+        let trade = decode_trade(
+            r#"[{"ev":"T","sym":"MSFT","x":4,"i":"12345","z":3,"p":114.125,"s":100,"c":[14,37],"t":1536036818784,"q":3681328}]"#,
+        );
+        assert_eq!(trade.symbol, "MSFT");
+        assert_eq!(trade.exchange_id, 4);
+        assert_eq!(trade.trade_id, "12345");
+        assert_eq!(trade.price, 114.125);
+        assert_eq!(trade.trade_size, 100);
+        assert_eq!(trade.trade_conditions, vec![14, 37]);
+        assert_eq!(
+            trade.sip_conditions().collect::<Vec<_>>(),
+            vec![SipCondition::IntermarketSweep, SipCondition::OddLotTrade]
+        );
+        assert_eq!(
+            trade.timestamp,
+            Utc.timestamp_millis_opt(1536036818784).unwrap()
+        );
+        assert_eq!(trade.sequence_number, 3681328);
+
+        let data = trade.get_trade().unwrap();
+        assert_eq!(data.instrument, Instrument::equity("MSFT"));
+        assert_eq!(data.tape, Some(Tape::C));
+        assert_eq!(data.conditions, [14, 37]);
+    }
+
+    #[test]
+    fn decodes_trade_without_conditions() {
+        // Polygon leaves out `c` for regular trades
+        let trade = decode_trade(
+            r#"[{"ev":"T","sym":"AAPL","x":12,"i":"52983525033527","z":3,"p":189.84,"s":200,"t":1705330800123,"q":1234567,"trfi":202,"trft":1705330800120}]"#,
+        );
+        assert_eq!(trade.symbol, "AAPL");
+        assert!(trade.trade_conditions.is_empty());
+        assert_eq!(trade.sip_conditions().count(), 0);
+        assert_eq!(trade.trade_reporting_facility_id, Some(202));
+        assert!(trade.get_trade().unwrap().conditions.is_empty());
+    }
 }
@@ -0,0 +1,259 @@
+//! Drives the REST clients against `MockRestServer`: pagination, error statuses and decoding of
+//! the canned bodies, all offline.
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use polyticker_lib::common::instrument::Instrument;
+use polyticker_lib::exchange::registry::{AssetClass, ExchangeRegistry, ExchangeType};
+use polyticker_lib::request::mock::{
+    aggregates_body, grouped_daily_body, trades_pages, MockResponse, MockRestServer,
+};
+use polyticker_lib::request::reference::exchanges::Exchanges;
+use polyticker_lib::request::stocks::aggregates::Aggregates;
+use polyticker_lib::request::stocks::grouped_daily::GroupedDaily;
+use polyticker_lib::request::stocks::trades::Trades;
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn follows_next_url_through_every_page() {
+    let server = MockRestServer::start().await.unwrap();
+    let from = Utc.with_ymd_and_hms(2024, 3, 1, 14, 30, 0).unwrap();
+    server.mock_paginated("/v3/trades/AAPL", trades_pages(from, 25, 10));
+    let trades = Trades::with_base_url("key".to_string(), server.url());
+
+    let all = trades
+        .get_all_trades(
+            &Instrument::equity("AAPL"),
+            from,
+            from + Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+
+    let sequences: Vec<i64> = all.iter().map(|trade| trade.sequence_number).collect();
+    assert_eq!(sequences, (1..=25).collect::<Vec<_>>());
+    assert_eq!(all[0].sip_timestamp, from);
+    assert_eq!(all[24].sip_timestamp, from + Duration::milliseconds(24));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].query_param("order"), Some("asc"));
+    assert_eq!(requests[0].query_param("limit"), Some("50000"));
+    assert_eq!(requests[1].query_param("cursor"), Some("1"));
+    assert_eq!(requests[2].query_param("cursor"), Some("2"));
+    for request in &requests {
+        assert_eq!(request.path, "/v3/trades/AAPL");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer key"));
+    }
+}
+
+#[tokio::test]
+async fn reports_a_rate_limit_as_a_status_error() {
+    let server = MockRestServer::start().await.unwrap();
+    server.mock("/v3/trades/AAPL", MockResponse::rate_limited());
+    let trades = Trades::with_base_url("key".to_string(), server.url());
+    let from = Utc.with_ymd_and_hms(2024, 3, 1, 14, 30, 0).unwrap();
+
+    let error = trades
+        .get_trades(&Instrument::equity("AAPL"), from, from, 100)
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+}
+
+#[tokio::test]
+async fn reports_an_unknown_api_key_as_a_status_error() {
+    let server = MockRestServer::start_with_api_key(Some("secret".to_string()))
+        .await
+        .unwrap();
+    server.mock(
+        "/v2/aggs/grouped/locale/us/market/stocks/2024-03-01",
+        MockResponse::json(&grouped_daily_body(
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            &["AAPL"],
+        )),
+    );
+
+    let error = GroupedDaily::with_base_url("wrong".to_string(), server.url())
+        .get_data("2024-03-01", true, false)
+        .await
+        .unwrap_err();
+    assert_eq!(error.status(), Some(StatusCode::UNAUTHORIZED));
+
+    let response = GroupedDaily::with_base_url("secret".to_string(), server.url())
+        .get_data("2024-03-01", true, false)
+        .await
+        .unwrap();
+    assert_eq!(response.results.len(), 1);
+}
+
+#[tokio::test]
+async fn reports_a_malformed_body_as_a_decode_error() {
+    let server = MockRestServer::start().await.unwrap();
+    server.mock(
+        "/v2/aggs/ticker/AAPL/range/1/day/2024-03-01/2024-03-05",
+        MockResponse::malformed(),
+    );
+
+    let error = Aggregates::with_base_url("key".to_string(), server.url())
+        .get_stock_data(
+            &Instrument::equity("AAPL"),
+            "1",
+            "day",
+            "2024-03-01",
+            "2024-03-05",
+            true,
+            "asc",
+            10,
+        )
+        .await
+        .unwrap_err();
+    assert!(error.is_decode(), "{:?}", error);
+    assert_eq!(error.status(), None);
+}
+
+#[tokio::test]
+async fn decodes_aggregates() {
+    let server = MockRestServer::start().await.unwrap();
+    let from = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+    server.mock(
+        "/v2/aggs/ticker/X:BTCUSD/range/1/day/2024-03-01/2024-03-05",
+        MockResponse::json(&aggregates_body("X:BTCUSD", from, Duration::days(1), 5)),
+    );
+
+    let response = Aggregates::with_base_url("key".to_string(), server.url())
+        .get_stock_data(
+            &Instrument::crypto("BTC", "USD"),
+            "1",
+            "day",
+            "2024-03-01",
+            "2024-03-05",
+            true,
+            "asc",
+            10,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.ticker, Instrument::crypto("BTC", "USD"));
+    assert_eq!(response.results_count, 5);
+    assert_eq!(response.results.len(), 5);
+    for (index, bar) in response.results.iter().enumerate() {
+        let open = 100.0 + index as f64;
+        assert_eq!(bar.open_price, open);
+        assert_eq!(bar.highest_price, open + 1.5);
+        assert_eq!(bar.lowest_price, open - 0.5);
+        assert_eq!(bar.close_price, open + 1.0);
+        assert_eq!(bar.number_of_transactions, 10 + index as u64);
+        assert_eq!(bar.timestamp, from + Duration::days(index as i64));
+    }
+    let request = &server.requests()[0];
+    assert_eq!(request.query_param("adjusted"), Some("true"));
+    assert_eq!(request.query_param("limit"), Some("10"));
+}
+
+#[tokio::test]
+async fn decodes_grouped_daily_bars() {
+    let server = MockRestServer::start().await.unwrap();
+    let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+    server.mock(
+        "/v2/aggs/grouped/locale/us/market/stocks/2024-03-01",
+        MockResponse::json(&grouped_daily_body(date, &["AAPL", "MSFT", "BRK-B"])),
+    );
+
+    let response = GroupedDaily::with_base_url("key".to_string(), server.url())
+        .get_data("2024-03-01", true, false)
+        .await
+        .unwrap();
+
+    let tickers: Vec<Instrument> = response
+        .results
+        .iter()
+        .map(|bar| bar.ticker.clone())
+        .collect();
+    assert_eq!(
+        tickers,
+        vec![
+            Instrument::equity("AAPL"),
+            Instrument::equity("MSFT"),
+            // share classes are stocks, not crypto pairs
+            Instrument::equity("BRK-B")
+        ]
+    );
+    for bar in &response.results {
+        assert_eq!(
+            bar.timestamp,
+            Utc.with_ymd_and_hms(2024, 3, 1, 20, 0, 0).unwrap()
+        );
+        assert_eq!(bar.number_of_transactions, Some(500));
+        assert!(bar.lowest_price < bar.open_price && bar.open_price < bar.highest_price);
+    }
+    assert_eq!(response.results[1].open_price, 20.0);
+}
+
+#[tokio::test]
+async fn refreshes_a_registry_from_the_exchanges_endpoint() {
+    let server = MockRestServer::start().await.unwrap();
+    server.mock(
+        "/v3/reference/exchanges",
+        MockResponse::json(&json!({
+            "request_id": "1",
+            "status": "OK",
+            "count": 3,
+            "results": [
+                {
+                    "id": 1,
+                    "name": "Coinbase Advanced",
+                    "type": "exchange",
+                    "asset_class": "crypto",
+                    "locale": "global",
+                    "url": "https://www.coinbase.com",
+                },
+                {
+                    "id": 10,
+                    "name": "Binance",
+                    "type": "exchange",
+                    "asset_class": "crypto",
+                    "locale": "global",
+                },
+                {
+                    "id": 35,
+                    "name": "Unknown Crypto",
+                    "acronym": "UC",
+                    "type": "TRF",
+                    "asset_class": "crypto",
+                    "locale": "global",
+                },
+            ],
+        })),
+    );
+    let client = Exchanges::with_base_url("key".to_string(), server.url());
+
+    let response = client.get_exchanges(Some("crypto")).await.unwrap();
+    assert_eq!(response.count, 3);
+    assert_eq!(response.results[0].name, "Coinbase Advanced");
+    assert_eq!(response.results[0].mic, None);
+    assert_eq!(response.results[2].acronym.as_deref(), Some("UC"));
+
+    let mut registry = ExchangeRegistry::builtin();
+    let loaded = registry
+        .refresh(&client, Some(AssetClass::Crypto))
+        .await
+        .unwrap();
+    assert_eq!(loaded, 3);
+    assert_eq!(registry.name(AssetClass::Crypto, 1), "Coinbase Advanced");
+    assert_eq!(registry.name(AssetClass::Crypto, 10), "Binance");
+    assert_eq!(
+        registry.get(AssetClass::Crypto, 35).unwrap().exchange_type,
+        ExchangeType::Trf
+    );
+    // venues of other asset classes are kept
+    assert_eq!(registry.name(AssetClass::Stocks, 1), "NYSE American");
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    for request in &requests {
+        assert_eq!(request.path, "/v3/reference/exchanges");
+        assert_eq!(request.query_param("asset_class"), Some("crypto"));
+    }
+}
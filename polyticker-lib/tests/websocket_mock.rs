@@ -0,0 +1,328 @@
+//! Drives the data channels against `MockServer`: handshake, reconnects, decoding and
+//! backpressure, all offline.
+
+use polyticker_lib::websocket::backpressure::BackpressurePolicy;
+use polyticker_lib::websocket::config::StreamConfig;
+use polyticker_lib::websocket::connection::{EventStream, StreamError, StreamHandle};
+use polyticker_lib::websocket::crypto::{Crypto, CryptoEvent, CryptoTradeEvent};
+use polyticker_lib::websocket::mock::{EventGenerator, MockServer, MockServerConfig};
+use polyticker_lib::websocket::Status;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+/// How long a test waits for something that should happen right away.
+const PATIENCE: Duration = Duration::from_secs(5);
+
+fn config(server: &MockServer) -> StreamConfig {
+    StreamConfig {
+        url: Some(server.url()),
+        reconnect_delay: Duration::from_millis(10),
+        ..Default::default()
+    }
+}
+
+async fn open(
+    server: &MockServer,
+    params: &str,
+    config: StreamConfig,
+) -> (EventStream<CryptoEvent>, StreamHandle) {
+    let (mut stream, handle) =
+        Crypto::open_data_channel_with_config("key".to_string(), params.to_string(), config)
+            .await
+            .unwrap();
+    // once acknowledged, the server sends frames to the connection
+    for param in params.split(',') {
+        match next_event(&mut stream).await {
+            CryptoEvent::Status(status) => {
+                assert_eq!(status.status, Status::Success);
+                assert_eq!(status.message, format!("subscribed to: {}", param));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+    assert_eq!(server.active_clients(), 1);
+    (stream, handle)
+}
+
+/// The next event of the stream, skipping the errors of lost connections.
+async fn next_event(stream: &mut EventStream<CryptoEvent>) -> CryptoEvent {
+    loop {
+        match timeout(PATIENCE, stream.recv()).await {
+            Ok(Some(Ok(event))) => return event,
+            Ok(Some(Err(_))) => continue,
+            Ok(None) => panic!("stream ended"),
+            Err(_) => panic!("no event within {:?}", PATIENCE),
+        }
+    }
+}
+
+async fn next_trade(stream: &mut EventStream<CryptoEvent>) -> CryptoTradeEvent {
+    loop {
+        if let CryptoEvent::Trade(trade) = next_event(stream).await {
+            return trade;
+        }
+    }
+}
+
+/// Waits until `condition` holds.
+async fn eventually(condition: impl Fn() -> bool) {
+    timeout(PATIENCE, async {
+        while !condition() {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("condition not met in time");
+}
+
+/// The params of every action the clients sent, e.g. `subscribe`.
+fn actions(server: &MockServer, action: &str) -> Vec<String> {
+    server
+        .received_messages()
+        .iter()
+        .filter_map(|message| {
+            let message: Value = serde_json::from_str(message).ok()?;
+            (message["action"] == action).then(|| message["params"].as_str().unwrap().to_string())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn completes_the_handshake() {
+    let server = MockServer::start_with_config(MockServerConfig {
+        api_key: Some("key".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let (_stream, handle) = open(&server, "XT.BTC-USD,XQ.BTC-USD", config(&server)).await;
+
+    assert_eq!(
+        server.received_messages(),
+        vec![
+            r#"{"action":"auth","params":"key"}"#,
+            r#"{"action":"subscribe","params":"XT.BTC-USD,XQ.BTC-USD"}"#,
+        ]
+    );
+    assert_eq!(server.connections(), 1);
+    assert!(handle.health().connected);
+    assert_eq!(handle.subscriptions(), vec!["XT.BTC-USD", "XQ.BTC-USD"]);
+}
+
+#[tokio::test]
+async fn reports_a_rejected_api_key() {
+    let server = MockServer::start_with_config(MockServerConfig {
+        api_key: Some("secret".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let opened = Crypto::open_data_channel_with_config(
+        "wrong".to_string(),
+        "XT.*".to_string(),
+        config(&server),
+    )
+    .await;
+
+    match opened {
+        Err(StreamError::AuthFailed(message)) => assert_eq!(message, "authentication failed"),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("authenticated with a wrong key"),
+    }
+    assert_eq!(server.active_clients(), 0);
+}
+
+#[tokio::test]
+async fn reconnects_and_resubscribes_after_a_dropped_connection() {
+    let server = MockServer::start().await.unwrap();
+    let (mut stream, handle) = open(&server, "XT.BTC-USD", config(&server)).await;
+    handle.subscribe("XT.ETH-USD");
+    eventually(|| actions(&server, "subscribe").len() == 2).await;
+
+    server.kill_connections();
+    eventually(|| server.connections() == 2 && server.active_clients() == 1).await;
+    eventually(|| actions(&server, "subscribe").len() == 3).await;
+
+    assert_eq!(actions(&server, "auth"), vec!["key", "key"]);
+    let resubscribed: BTreeSet<String> = actions(&server, "subscribe")[2]
+        .split(',')
+        .map(str::to_string)
+        .collect();
+    assert_eq!(
+        resubscribed,
+        BTreeSet::from(["XT.BTC-USD".to_string(), "XT.ETH-USD".to_string()])
+    );
+
+    // events flow again over the new connection
+    let mut generator = EventGenerator::new(1);
+    let sent = generator.crypto_trade("ETH-USD");
+    server.send_events(std::slice::from_ref(&sent));
+    let trade = next_trade(&mut stream).await;
+    assert_eq!(trade.trade_id, sent["i"].as_str().unwrap());
+    assert_eq!(handle.health().reconnects, 1);
+}
+
+#[tokio::test]
+async fn decodes_scripted_frames() {
+    let mut generator = EventGenerator::new(42);
+    let frames: Vec<Vec<Value>> = (0..5)
+        .map(|_| {
+            let mut events = generator.crypto_trades(&["BTC-USD", "ETH-USD"], 3);
+            events.push(generator.crypto_quote("BTC-USD"));
+            events
+        })
+        .collect();
+    let script: Vec<String> = frames
+        .iter()
+        .map(|events| Value::from(events.clone()).to_string())
+        .collect();
+    let server = MockServer::start_with_config(MockServerConfig {
+        script: script.clone(),
+        frame_interval: Duration::from_millis(1),
+        close_after_script: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let config = StreamConfig {
+        reconnect_attempts: 0,
+        ..config(&server)
+    };
+    let (mut stream, _handle) = open(&server, "XT.*,XQ.*", config).await;
+
+    let mut decoded = Vec::new();
+    while let Some(event) = timeout(PATIENCE, stream.recv()).await.unwrap() {
+        if let Ok(event) = event {
+            decoded.push(event);
+        }
+    }
+    // compare with the frames as parsed, floats included
+    let sent: Vec<Value> = script
+        .iter()
+        .flat_map(|frame| serde_json::from_str::<Vec<Value>>(frame).unwrap())
+        .collect();
+    assert_eq!(decoded.len(), sent.len());
+    for (event, sent) in decoded.iter().zip(&sent) {
+        match event {
+            CryptoEvent::Trade(trade) => {
+                assert_eq!(sent["ev"], "XT");
+                assert_eq!(trade.pair, sent["pair"].as_str().unwrap());
+                assert_eq!(trade.price, sent["p"].as_f64().unwrap());
+                assert_eq!(trade.size, sent["s"].as_f64().unwrap());
+                assert_eq!(trade.trade_id, sent["i"].as_str().unwrap());
+                assert_eq!(
+                    trade.timestamp.timestamp_millis(),
+                    sent["t"].as_i64().unwrap()
+                );
+            }
+            CryptoEvent::Quote(quote) => {
+                assert_eq!(sent["ev"], "XQ");
+                assert_eq!(quote.bid_price, sent["bp"].as_f64().unwrap());
+                assert_eq!(quote.ask_price, sent["ap"].as_f64().unwrap());
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+}
+
+/// Sends `events` to a channel with a queue of four events that is not read meanwhile, then
+/// returns the trade IDs delivered.
+async fn deliver_to_full_queue(policy: BackpressurePolicy, events: &[Value]) -> Vec<String> {
+    let server = MockServer::start().await.unwrap();
+    let config = StreamConfig {
+        output_channel_size: 4,
+        backpressure: policy,
+        ..config(&server)
+    };
+    let (mut stream, handle) = open(&server, "XT.*", config).await;
+    server.send_events(events);
+    // the acknowledgement was the first event
+    eventually(|| handle.health().events_received == 1 + events.len() as u64).await;
+
+    let mut delivered = Vec::new();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(100), stream.recv()).await {
+        if let CryptoEvent::Trade(trade) = event.unwrap() {
+            delivered.push(trade.trade_id);
+        }
+    }
+    delivered
+}
+
+fn trade_ids(events: &[Value]) -> Vec<String> {
+    events
+        .iter()
+        .map(|event| event["i"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn drop_newest_keeps_the_first_events() {
+    let events = EventGenerator::new(3).crypto_trades(&["BTC-USD"], 10);
+    let delivered = deliver_to_full_queue(BackpressurePolicy::DropNewest, &events).await;
+    assert_eq!(delivered, trade_ids(&events[..4]));
+}
+
+#[tokio::test]
+async fn drop_oldest_keeps_the_last_events() {
+    let events = EventGenerator::new(4).crypto_trades(&["BTC-USD"], 10);
+    let delivered = deliver_to_full_queue(BackpressurePolicy::DropOldest, &events).await;
+    assert_eq!(delivered, trade_ids(&events[6..]));
+}
+
+#[tokio::test]
+async fn conflate_keeps_the_last_event_per_symbol() {
+    let mut generator = EventGenerator::new(5);
+    let events: Vec<Value> = (0..5)
+        .flat_map(|_| {
+            [
+                generator.crypto_trade("BTC-USD"),
+                generator.crypto_trade("ETH-USD"),
+            ]
+        })
+        .collect();
+    let delivered = deliver_to_full_queue(BackpressurePolicy::Conflate, &events).await;
+    let expected = trade_ids(&events[8..]);
+    assert_eq!(
+        delivered.into_iter().collect::<BTreeSet<_>>(),
+        expected.into_iter().collect::<BTreeSet<_>>()
+    );
+}
+
+#[tokio::test]
+async fn reconnects_a_connection_that_went_silent() {
+    let server = MockServer::start().await.unwrap();
+    let config = StreamConfig {
+        // pongs count as frames, keep the connection truly silent
+        ping_interval: None,
+        idle_timeout: Some(Duration::from_millis(300)),
+        ..config(&server)
+    };
+    let (mut stream, handle) = open(&server, "XT.*", config).await;
+    let events = EventGenerator::new(6).crypto_trades(&["BTC-USD"], 3);
+    for event in &events {
+        server.send_events(std::slice::from_ref(event));
+    }
+    for _ in &events {
+        next_trade(&mut stream).await;
+    }
+    let busy = handle.health();
+    // the acknowledgement and one frame per trade
+    assert_eq!(busy.frames_received, 4);
+    assert!(busy.frames_per_second > 0.0);
+    assert_eq!(busy.reconnects, 0);
+
+    // the server stays silent until the watchdog gives up on the connection
+    match timeout(PATIENCE, stream.recv()).await {
+        Ok(Some(Err(StreamError::Idle(idle)))) => assert!(idle >= Duration::from_millis(300)),
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+    eventually(|| server.connections() == 2 && server.active_clients() == 1).await;
+    eventually(|| handle.health().reconnects == 1).await;
+    assert_eq!(actions(&server, "subscribe"), vec!["XT.*", "XT.*"]);
+
+    let quiet = handle.health();
+    assert!(quiet.connected);
+    assert!(quiet.frames_per_second < busy.frames_per_second);
+}
@@ -1,11 +1,22 @@
 use clap::{Parser, Subcommand};
-use polyticker_lib::common::trade::Trade;
-use polyticker_lib::exchange::Bucket;
+use polyticker_lib::common::instrument::{subscriptions, Instrument};
+use polyticker_lib::exchange::divergence::{DivergenceConfig, DivergenceMonitor};
+use polyticker_lib::exchange::registry::{self, exchange_name, AssetClass};
+use polyticker_lib::exchange::render::RenderFormat;
+use polyticker_lib::exchange::BucketSet;
+use polyticker_lib::request::reference::exchanges::Exchanges;
 use polyticker_lib::request::stocks::aggregates::Aggregates;
 use polyticker_lib::request::stocks::grouped_daily::GroupedDaily;
-use polyticker_lib::websocket::crypto::{Crypto, CryptoTradeEvent};
-use polyticker_lib::websocket::stocks::Stocks;
-use std::fs::File;
+use polyticker_lib::websocket::config::StreamConfig;
+use polyticker_lib::websocket::connection::StreamHandle;
+use polyticker_lib::websocket::crypto::{Crypto, CryptoEvent};
+use polyticker_lib::websocket::latency::LatencyTracker;
+use polyticker_lib::websocket::recorder::FrameRecorder;
+use polyticker_lib::websocket::replay::{open_replay_channel, ReplaySpeed};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 struct Cli {
@@ -21,10 +32,56 @@ enum Commands {
     /// Subcommand for handling tables
     Aggregates {},
     GroupedDaily {},
-    WebSocket {},
+    WebSocket {
+        /// Record the raw frames to this file for later replay
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Replay crypto events recorded with `web-socket --record`
+    Replay {
+        path: PathBuf,
+        /// Replay this many times faster than recorded
+        #[arg(short, long, default_value = "1")]
+        speed: f64,
+        /// Replay as fast as possible
+        #[arg(long)]
+        unpaced: bool,
+    },
     ExchangeBuckets {
         #[arg(short, long, default_value = "1")]
         refresh_rate: u64,
+        /// Comma separated crypto pairs to watch, e.g. `BTC-USD,X:ETHUSD`
+        #[arg(short, long, value_delimiter = ',', default_value = "BTC-USD")]
+        pairs: Vec<Instrument>,
+        /// Output format: table, json, ndjson, csv or ticker
+        #[arg(short, long, default_value = "table")]
+        format: RenderFormat,
+        /// Append the output to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Alerts when the price of a pair diverges between crypto exchanges
+    Divergence {
+        /// Comma separated crypto pairs to watch, e.g. `BTC-USD,X:ETHUSD`
+        #[arg(short, long, value_delimiter = ',', default_value = "BTC-USD")]
+        pairs: Vec<Instrument>,
+        /// Net spread in basis points at which a divergence opens
+        #[arg(short, long, default_value = "10")]
+        threshold_bps: f64,
+        /// Fee in basis points paid on each side of a trade
+        #[arg(short, long, default_value = "0")]
+        fee_bps: f64,
+        /// Seconds the spread must persist before a divergence opens
+        #[arg(short = 's', long, default_value = "2")]
+        min_persistence: u64,
+        /// Seconds between prints of the divergence duration statistics
+        #[arg(short, long, default_value = "60")]
+        refresh_rate: u64,
+    },
+    /// Feed latency per crypto exchange
+    Latency {
+        #[arg(short, long, default_value = "10")]
+        refresh_rate: u64,
     },
 }
 
@@ -34,7 +91,7 @@ async fn main() -> anyhow::Result<()> {
     match cli.command {
         Commands::Aggregates {} => {
             let api_key = cli.polygon_api_key;
-            let stocks_ticker = "AAPL";
+            let ticker = Instrument::equity("AAPL");
             let multiplier = "1";
             let timespan = "day";
             let from = "2023-01-09";
@@ -46,14 +103,7 @@ async fn main() -> anyhow::Result<()> {
 
             match aggregates
                 .get_stock_data(
-                    stocks_ticker,
-                    multiplier,
-                    timespan,
-                    from,
-                    to,
-                    adjusted,
-                    sort,
-                    limit,
+                    &ticker, multiplier, timespan, from, to, adjusted, sort, limit,
                 )
                 .await
             {
@@ -73,48 +123,224 @@ async fn main() -> anyhow::Result<()> {
                 Err(e) => println!("Error: {}", e),
             }
         }
-        Commands::WebSocket {} => {
+        Commands::WebSocket { record } => {
             let api_key = cli.polygon_api_key;
 
+            let config = StreamConfig {
+                recorder: record.map(FrameRecorder::create).transpose()?,
+                ..StreamConfig::with_channel_size(1000)
+            };
             //    let mut channel = Stocks::open_data_channel(api_key, 1000).await;
-            let mut channel = Crypto::open_data_channel(api_key, "XT.*".to_string(), 1000).await;
+            let (mut channel, handle) =
+                Crypto::open_data_channel_with_config(api_key, "XT.*".to_string(), config).await?;
+            shutdown_on_ctrl_c(&handle);
 
             while let Some(event) = channel.recv().await {
-                println!("{:#?}", event);
+                match event {
+                    Ok(event) => println!("{:#?}", event),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
             }
+            handle.await?;
         }
-        Commands::ExchangeBuckets { refresh_rate } => {
+        Commands::Replay {
+            path,
+            speed,
+            unpaced,
+        } => {
+            let speed = if unpaced {
+                ReplaySpeed::Unpaced
+            } else {
+                ReplaySpeed::Accelerated(speed)
+            };
+            let (mut channel, handle) = open_replay_channel::<CryptoEvent, _>(path, speed, 1000)?;
+            shutdown_on_ctrl_c(&handle);
+
+            while let Some(event) = channel.recv().await {
+                match event {
+                    Ok(event) => println!("{:#?}", event),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+            handle.await?;
+        }
+        Commands::ExchangeBuckets {
+            refresh_rate,
+            pairs,
+            format,
+            output,
+        } => {
             let api_key = cli.polygon_api_key;
+            refresh_exchanges(&api_key, AssetClass::Crypto).await;
 
             //    let mut channel = Stocks::open_data_channel(api_key, 1000).await;
-            let mut channel = Crypto::open_data_channel(api_key, "XT.*".to_string(), 1000).await;
+            let (mut channel, handle) =
+                Crypto::open_data_channel(api_key, subscriptions("XT", &pairs), 1000).await?;
+            shutdown_on_ctrl_c(&handle);
 
-            let mut bucket = Bucket::new("BTC", "USD");
+            let mut buckets = BucketSet::watching(&pairs);
+            let mut renderer = format.renderer();
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+                None => Box::new(std::io::stdout()),
+            };
             // start a time to print buckets every "refresh_rate" seconds
             let mut interval =
                 tokio::time::interval(tokio::time::Duration::from_secs(refresh_rate));
             loop {
                 tokio::select! {
                     _ = interval.tick() => {
-                        bucket.print_trades()?;
+                        buckets.render_instruments(&pairs, renderer.as_mut(), &mut out)?;
+                        let health = handle.health();
+                        // on stderr, so that the output stays machine readable
+                        eprintln!(
+                            "{:.1} frames/s, {} reconnects, {} parse errors",
+                            health.frames_per_second, health.reconnects, health.parse_errors
+                        );
                     }
                     event = channel.recv() => {
-                        match &event {
-                            Some(event) => process_trade(event.to_owned(), &mut bucket)?,
+                        match event {
+                            Some(Ok(CryptoEvent::Trade(event))) => {
+                                if let Err(e) = buckets.add_trade(Box::new(event)) {
+                                    eprintln!("Error: {}", e);
+                                }
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(e)) => eprintln!("Error: {}", e),
                             None => break,
                         }
                     }
                 }
             }
+            handle.await?;
+        }
+        Commands::Divergence {
+            pairs,
+            threshold_bps,
+            fee_bps,
+            min_persistence,
+            refresh_rate,
+        } => {
+            let api_key = cli.polygon_api_key;
+            refresh_exchanges(&api_key, AssetClass::Crypto).await;
+
+            let (mut channel, handle) =
+                Crypto::open_data_channel(api_key, subscriptions("XT", &pairs), 1000).await?;
+            shutdown_on_ctrl_c(&handle);
+
+            let mut buckets = BucketSet::watching(&pairs);
+            let mut monitor = DivergenceMonitor::new(DivergenceConfig {
+                default_fee_bps: fee_bps,
+                min_persistence: Duration::from_secs(min_persistence),
+                ..DivergenceConfig::with_threshold(threshold_bps)
+            });
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(refresh_rate));
+            // re-evaluate without trades too, so that stale exchanges close their divergences
+            let mut check = tokio::time::interval(tokio::time::Duration::from_secs(1));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let stats = monitor.duration_stats();
+                        println!(
+                            "{} open, {} closed in the last hour, duration mean {:?} median {:?} max {:?}",
+                            monitor.open_count(),
+                            stats.count,
+                            stats.mean,
+                            stats.median,
+                            stats.max
+                        );
+                    }
+                    _ = check.tick() => {
+                        for event in monitor.update_set(&buckets) {
+                            println!("{}", event);
+                        }
+                    }
+                    event = channel.recv() => {
+                        match event {
+                            Some(Ok(CryptoEvent::Trade(event))) => {
+                                if let Err(e) = buckets.add_trade(Box::new(event)) {
+                                    eprintln!("Error: {}", e);
+                                }
+                                for event in monitor.update_set(&buckets) {
+                                    println!("{}", event);
+                                }
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(e)) => eprintln!("Error: {}", e),
+                            None => break,
+                        }
+                    }
+                }
+            }
+            handle.await?;
+        }
+        Commands::Latency { refresh_rate } => {
+            let api_key = cli.polygon_api_key;
+            refresh_exchanges(&api_key, AssetClass::Crypto).await;
+
+            let (mut channel, handle) =
+                Crypto::open_data_channel(api_key, "XT.*".to_string(), 1000).await?;
+            shutdown_on_ctrl_c(&handle);
+
+            let mut tracker = LatencyTracker::new();
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(refresh_rate));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => print_latency(&tracker),
+                    event = channel.recv() => {
+                        match event {
+                            Some(Ok(CryptoEvent::Trade(event))) => tracker.record_now(&event),
+                            Some(Ok(_)) => {}
+                            Some(Err(e)) => eprintln!("Error: {}", e),
+                            None => break,
+                        }
+                    }
+                }
+            }
+            handle.await?;
         }
     }
     Ok(())
 }
 
-pub fn process_trade(event: CryptoTradeEvent, bucket: &mut Bucket) -> anyhow::Result<()> {
-    let trade = event.get_trade()?;
-    if trade.symbol == "BTC" && trade.currency == "USD" {
-        bucket.add_trade(Box::new(event));
+/// Loads the current exchange names from Polygon, keeping the built-in ones if that fails.
+async fn refresh_exchanges(api_key: &str, asset_class: AssetClass) {
+    let mut exchanges = registry::global().clone();
+    match exchanges
+        .refresh(&Exchanges::new(api_key.to_string()), Some(asset_class))
+        .await
+    {
+        Ok(_) => registry::install(exchanges),
+        Err(e) => eprintln!("Using built-in exchange names: {}", e),
+    }
+}
+
+/// Shuts the stream down gracefully on Ctrl-C. The event loop then ends with the stream once the
+/// connection is closed.
+fn shutdown_on_ctrl_c(handle: &StreamHandle) {
+    let shutdown = handle.shutdown_signal();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Shutting down");
+            shutdown.trigger();
+        }
+    });
+}
+
+fn print_latency(tracker: &LatencyTracker) {
+    for exchange_id in tracker.exchange_ids() {
+        if let Some(stats) = tracker.exchange_stats(exchange_id) {
+            println!(
+                "{}: {} trades, exchange->polygon p50 {:?} p99 {:?}, polygon->local p50 {:?} p99 {:?}",
+                exchange_name(AssetClass::Crypto, exchange_id),
+                stats.end_to_end.count(),
+                stats.exchange_to_polygon.percentile(50.0),
+                stats.exchange_to_polygon.percentile(99.0),
+                stats.polygon_to_local.percentile(50.0),
+                stats.polygon_to_local.percentile(99.0),
+            );
+        }
     }
-    Ok(())
 }